@@ -0,0 +1,99 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rspaces::{new_template, new_tuple, FieldType, LocalSpace, Space, Template, Tuple, TupleField};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+/// The storage local spaces used before they were indexed, a vector scanned from the front
+struct VecSpace {
+    v: Vec<Tuple>,
+}
+
+impl VecSpace {
+    fn getp(&mut self, template: &Template) -> Option<Tuple> {
+        let i = self.v.iter().position(|t| template.query(t))?;
+        Some(self.v.remove(i))
+    }
+
+    fn queryp(&self, template: &Template) -> Option<Tuple> {
+        self.v.iter().find(|t| template.query(t)).cloned()
+    }
+}
+
+fn tuple(i: usize) -> Tuple {
+    new_tuple!(String::from("job"), i as i32, 'a')
+}
+
+fn filled(n: usize) -> (LocalSpace, VecSpace) {
+    let space = LocalSpace::new_sequential();
+    let mut vec = VecSpace { v: Vec::new() };
+    for i in 0..n {
+        space.put(tuple(i)).unwrap();
+        vec.v.push(tuple(i));
+    }
+    (space, vec)
+}
+
+/// Looks up the newest tuple by value, the worst case for a scan
+fn queryp_by_value(c: &mut Criterion) {
+    let mut group = c.benchmark_group("queryp_by_value");
+    for n in SIZES {
+        let (space, vec) = filled(n);
+        let template = new_template!(
+            String::from("job").actual(),
+            (n as i32 - 1).actual(),
+            'a'.formal()
+        );
+        group.bench_with_input(BenchmarkId::new("indexed", n), &n, |b, _| {
+            b.iter(|| space.queryp(black_box(template.clone())).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("vec", n), &n, |b, _| {
+            b.iter(|| vec.queryp(black_box(&template)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Takes a tuple by value and puts it back, so the space keeps its size
+fn getp_put(c: &mut Criterion) {
+    let mut group = c.benchmark_group("getp_put");
+    for n in SIZES {
+        let (space, mut vec) = filled(n);
+        let template = new_template!(
+            String::from("job").actual(),
+            (n as i32 / 2).actual(),
+            'a'.formal()
+        );
+        group.bench_with_input(BenchmarkId::new("indexed", n), &n, |b, _| {
+            b.iter(|| {
+                let t = space.getp(black_box(template.clone())).unwrap();
+                space.put(t).unwrap();
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("vec", n), &n, |b, _| {
+            b.iter(|| {
+                let t = vec.getp(black_box(&template)).unwrap();
+                vec.v.push(t);
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Looks for a tuple of another arity, which the index can rule out without scanning
+fn queryp_miss(c: &mut Criterion) {
+    let mut group = c.benchmark_group("queryp_miss");
+    for n in SIZES {
+        let (space, vec) = filled(n);
+        let template = new_template!(String::from("job").actual(), 0.formal());
+        group.bench_with_input(BenchmarkId::new("indexed", n), &n, |b, _| {
+            b.iter(|| space.queryp(black_box(template.clone())).is_err())
+        });
+        group.bench_with_input(BenchmarkId::new("vec", n), &n, |b, _| {
+            b.iter(|| vec.queryp(black_box(&template)).is_none())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, queryp_by_value, getp_put, queryp_miss);
+criterion_main!(benches);
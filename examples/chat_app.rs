@@ -66,7 +66,7 @@ fn handle_coms<T: Space>(space: Arc<T>, mut starting: bool) {
             print!("Enter new message (type exit to end chat): ");
             let msg = input();
             space.put(new_tuple!(x, name.clone(), msg.clone())).unwrap();
-            if msg == String::from("exit") {
+            if msg == "exit" {
                 break;
             }
         } else {
@@ -79,7 +79,7 @@ fn handle_coms<T: Space>(space: Arc<T>, mut starting: bool) {
                 .unwrap();
             let name = t.get_field::<String>(1);
             let msg = t.get_field::<String>(2);
-            if *msg == String::from("exit") {
+            if *msg == "exit" {
                 println!("Partner quit, quitting as well");
                 break;
             } else {
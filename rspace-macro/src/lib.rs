@@ -1,6 +1,5 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn;
 
 #[proc_macro_derive(TupleField)]
 pub fn tuplefield_proc(input: TokenStream) -> TokenStream {
@@ -33,8 +32,32 @@ fn impl_tuplespace(ast: &syn::DeriveInput) -> TokenStream {
                         Some(_) => true,
                         None => false,
                     },
+                    _ => false,
                 }
             }
+            fn compare(&self, element: &dyn TupleField) -> Option<std::cmp::Ordering> {
+                // Picks the comparison of PartialOrd when the type implements it, and otherwise
+                // falls back to having no ordering through the autoref of the second trait
+                struct Compare<'a, T>(&'a T, &'a T);
+                trait Ordered {
+                    fn ordering(&self) -> Option<std::cmp::Ordering>;
+                }
+                impl<T: PartialOrd> Ordered for Compare<'_, T> {
+                    fn ordering(&self) -> Option<std::cmp::Ordering> {
+                        self.1.partial_cmp(self.0)
+                    }
+                }
+                trait Unordered {
+                    fn ordering(&self) -> Option<std::cmp::Ordering>;
+                }
+                impl<T> Unordered for &Compare<'_, T> {
+                    fn ordering(&self) -> Option<std::cmp::Ordering> {
+                        None
+                    }
+                }
+                let element = (*element).as_any().downcast_ref::<Self>()?;
+                (&Compare(self, element)).ordering()
+            }
         }
     };
     gen.into()
@@ -0,0 +1,300 @@
+use std::sync::Arc;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+    sync::{mpsc, watch, Semaphore},
+    task::JoinSet,
+};
+
+use crate::{
+    async_space::AsyncSpace,
+    frame::AsyncFramedStream,
+    gate::{Connection, Subscriptions, HANDSHAKE_TIMEOUT, MAX_WAITING, SUBSCRIPTION_BACKLOG},
+    handshake::{self, Admission},
+    Error, ErrorCode, LocalSpace, Message, MessageType, Repository, Result, Space, Tuple,
+};
+
+/// A gate running on a tokio runtime, serving the spaces of a repository to both blocking and
+/// async remote spaces.
+///
+/// Every connection is a task, and requests waiting on a local space do not hold up a thread.
+pub(crate) struct AsyncGate {
+    shutdown: watch::Sender<bool>,
+}
+
+/// A reply queued to be written. The tuple a waiting get took for it goes back in its old place
+/// if the reply is never written.
+struct Reply {
+    message: Message,
+    taken: Option<(Arc<dyn Space>, u64, Tuple)>,
+}
+
+impl From<Message> for Reply {
+    fn from(message: Message) -> Reply {
+        Reply {
+            message,
+            taken: None,
+        }
+    }
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        if let Some((space, id, tuple)) = self.taken.take() {
+            if let Some(local) = space.as_local() {
+                local.restore(id, tuple);
+            }
+        }
+    }
+}
+
+impl AsyncGate {
+    pub(crate) async fn bind(addr: String, repo: Arc<Repository>) -> Result<AsyncGate> {
+        let listener = TcpListener::bind(addr).await?;
+        let (shutdown, signal) = watch::channel(false);
+        tokio::spawn(AsyncGate::accept(listener, repo, signal));
+        Ok(AsyncGate { shutdown })
+    }
+
+    /// Stops accepting clients and closes every connection of the gate
+    pub(crate) fn close(&self) {
+        let _ = self.shutdown.send(true);
+    }
+
+    async fn accept(
+        listener: TcpListener,
+        repo: Arc<Repository>,
+        mut signal: watch::Receiver<bool>,
+    ) {
+        let mut clients = 1..;
+        loop {
+            tokio::select! {
+                _ = signal.changed() => break,
+                accepted = listener.accept() => {
+                    if let Ok((stream, _)) = accepted {
+                        let repo = Arc::clone(&repo);
+                        let client = clients.next().unwrap();
+                        tokio::spawn(AsyncGate::serve(stream, repo, client, signal.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn serve(
+        mut stream: TcpStream,
+        repo: Arc<Repository>,
+        client: u64,
+        mut signal: watch::Receiver<bool>,
+    ) {
+        let greeted = tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            AsyncGate::greet(&mut stream, &repo, client),
+        );
+        let Admission {
+            space,
+            codec,
+            permissions,
+        } = match greeted.await {
+            Ok(Some(admission)) => admission,
+            _ => return,
+        };
+        let (read, write) = stream.into_split();
+        let (replies, rx) = mpsc::unbounded_channel();
+        // Events wait for the client to read them, so a subscription the client does not keep
+        // up with is dropped
+        let (events, events_rx) = mpsc::channel(SUBSCRIPTION_BACKLOG);
+        let writer = tokio::spawn(AsyncGate::write_replies(
+            AsyncFramedStream::new(write, codec),
+            rx,
+            events_rx,
+        ));
+        let mut stream = AsyncFramedStream::new(read, codec);
+        // Every request runs as a task of its own, so a waiting get does not hold up the requests
+        // after it. The tasks are aborted when the connection ends.
+        let mut requests = JoinSet::new();
+        let waiting = Arc::new(Semaphore::new(MAX_WAITING));
+        let mut subscriptions = Subscriptions::default();
+        loop {
+            let received = tokio::select! {
+                _ = signal.changed() => break,
+                received = stream.recv() => received,
+                // Reaps finished requests
+                Some(_) = requests.join_next() => continue,
+            };
+            match received {
+                Ok(message) if !permissions.allow(&message.action) => {
+                    let _ = replies.send(Connection::forbidden(message).into());
+                }
+                Ok(message) if message.action == MessageType::Subscribe => {
+                    let events = events.clone();
+                    let reply = subscriptions
+                        .subscribe(&*space, message, move |m| events.blocking_send(m).is_ok());
+                    let _ = replies.send(reply.into());
+                }
+                Ok(message) if message.action == MessageType::Unsubscribe => {
+                    let _ = replies.send(subscriptions.unsubscribe(message).into());
+                }
+                Ok(message) if Connection::blocks(&message.action) => {
+                    match Arc::clone(&waiting).try_acquire_owned() {
+                        Ok(permit) => {
+                            let space = Arc::clone(&space);
+                            let replies = replies.clone();
+                            requests.spawn(async move {
+                                let reply = AsyncGate::wait(space, message).await;
+                                drop(permit);
+                                let _ = replies.send(reply);
+                            });
+                        }
+                        Err(_) => {
+                            let _ = replies.send(Connection::too_many_waiting(message.id).into());
+                        }
+                    }
+                }
+                Ok(message) => {
+                    let space = Arc::clone(&space);
+                    let replies = replies.clone();
+                    requests.spawn(async move {
+                        let _ = replies.send(AsyncGate::dispatch(&space, message).await.into());
+                    });
+                }
+                // The frame was read in full, so the connection can carry on after replying
+                Err(Error::Serialization(e)) => {
+                    let reply = Connection::error_reply(ErrorCode::Malformed, e.to_string());
+                    let _ = replies.send(reply.into());
+                }
+                // The frame boundaries are lost, so the connection is closed after replying
+                Err(Error::Protocol(reason)) => {
+                    let reply = Connection::error_reply(ErrorCode::Protocol, reason);
+                    let _ = replies.send(reply.into());
+                    break;
+                }
+                Err(_e) => break,
+            }
+        }
+        drop(requests);
+        // The threads forwarding events hold on to the replies until they notice
+        drop(subscriptions);
+        drop((replies, events));
+        // Flushes the replies already queued before the connection is closed
+        let _ = writer.await;
+    }
+
+    /// Reads the greeting of the client and answers it
+    async fn greet(stream: &mut TcpStream, repo: &Repository, client: u64) -> Option<Admission> {
+        let mut buffer = Vec::new();
+        let hello = loop {
+            match handshake::parse_hello(&mut buffer) {
+                Ok(Some(hello)) => break hello,
+                Ok(None) => {}
+                Err(e) => {
+                    stream.write_all(&handshake::malformed(&e)).await.ok();
+                    return None;
+                }
+            }
+            let mut chunk = [0; 1024];
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return None,
+                Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+            }
+        };
+        let (reply, admission) = handshake::admit(repo, hello, None, client);
+        stream.write_all(&reply).await.ok()?;
+        admission
+    }
+
+    async fn write_replies(
+        mut stream: AsyncFramedStream<OwnedWriteHalf>,
+        mut replies: mpsc::UnboundedReceiver<Reply>,
+        mut events: mpsc::Receiver<Message>,
+    ) {
+        loop {
+            let mut reply = tokio::select! {
+                Some(reply) = replies.recv() => reply,
+                Some(m) = events.recv() => Reply::from(m),
+                else => break,
+            };
+            if stream.send(&reply.message).await.is_err() {
+                break;
+            }
+            reply.taken = None;
+        }
+    }
+
+    /// Executes a request that waits for a tuple. A tuple taken from a local space is kept with
+    /// the reply, to be put back if the reply is not written.
+    async fn wait(space: Arc<dyn Space>, message: Message) -> Reply {
+        let (local, (destroy, deadline)) =
+            match (space.as_local(), Connection::waits(&message.action)) {
+                (Some(local), Some(waits)) => (local, waits),
+                _ => return AsyncGate::dispatch(&space, message).await.into(),
+            };
+        let result = local
+            .wait_async_entry(message.template, destroy, deadline)
+            .await;
+        let taken = match &result {
+            Ok((id, tuple)) if destroy => Some((*id, tuple.clone())),
+            _ => None,
+        };
+        let message = Connection::reply(result.map(|(_, tuple)| vec![tuple])).answering(message.id);
+        Reply {
+            message,
+            taken: taken.map(|(id, tuple)| (Arc::clone(&space), id, tuple)),
+        }
+    }
+
+    /// Executes the request, waiting on local spaces without blocking. Other spaces can only
+    /// block, so their requests run on the blocking thread pool.
+    async fn dispatch(space: &Arc<dyn Space>, message: Message) -> Message {
+        if let Some(local) = space.as_local() {
+            return AsyncGate::dispatch_local(local, message).await;
+        }
+        let space = Arc::clone(space);
+        match tokio::task::spawn_blocking(move || Connection::dispatch(&*space, message)).await {
+            Ok(response) => response,
+            Err(e) => Connection::error_reply(ErrorCode::SpaceFailure, e.to_string()),
+        }
+    }
+
+    async fn dispatch_local(space: &LocalSpace, message: Message) -> Message {
+        let id = message.id;
+        AsyncGate::execute_local(space, message).await.answering(id)
+    }
+
+    async fn execute_local(space: &LocalSpace, message: Message) -> Message {
+        let template = message.template;
+        let result = match message.action {
+            MessageType::Get => AsyncSpace::get(space, template).await.map(|t| vec![t]),
+            MessageType::Getp => AsyncSpace::getp(space, template).await.map(|t| vec![t]),
+            MessageType::Getall => AsyncSpace::getall(space, template).await,
+            MessageType::Query => AsyncSpace::query(space, template).await.map(|t| vec![t]),
+            MessageType::Queryp => AsyncSpace::queryp(space, template).await.map(|t| vec![t]),
+            MessageType::Queryall => AsyncSpace::queryall(space, template).await,
+            MessageType::Put => match Connection::put_tuple(message.tuple) {
+                Ok(tuple) => AsyncSpace::put(space, tuple).await.map(|_| Vec::new()),
+                Err(reply) => return reply,
+            },
+            MessageType::GetTimeout(timeout) => AsyncSpace::get_timeout(space, template, timeout)
+                .await
+                .map(|t| vec![t]),
+            MessageType::QueryTimeout(timeout) => {
+                AsyncSpace::query_timeout(space, template, timeout)
+                    .await
+                    .map(|t| vec![t])
+            }
+            MessageType::PutWithTtl(ttl) => match Connection::put_tuple(message.tuple) {
+                Ok(tuple) => AsyncSpace::put_with_ttl(space, tuple, ttl)
+                    .await
+                    .map(|_| Vec::new()),
+                Err(reply) => return reply,
+            },
+            MessageType::Renew(ttl) => AsyncSpace::renew(space, template, ttl).await,
+            MessageType::Transaction(steps) => {
+                return Connection::transaction(space, steps, message.tuple, template)
+            }
+            m => return Connection::not_a_request(m),
+        };
+        Connection::reply(result)
+    }
+}
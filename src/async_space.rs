@@ -0,0 +1,506 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+
+use crate::{
+    batch, frame::AsyncFramedStream, handshake, new_template, Codec, ConnectOptions, Endpoint,
+    Error, LocalSpace, Message, MessageType, Operation, RemoteSpace, Result, Space, SpaceUri,
+    Template, Tuple, UriError,
+};
+
+/// The async counterpart of `Space`, for use from tokio tasks.
+///
+/// Blocking operations wait without holding up the thread, so any number of tasks can wait on a
+/// space at once.
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// # use rspaces::asynchronous::AsyncSpace;
+/// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+/// let space = LocalSpace::new_sequential();
+/// AsyncSpace::put(&space, new_tuple!(5, 'a')).await.unwrap();
+///
+/// let tuple = AsyncSpace::get(&space, new_template!(5.actual(), 'b'.formal()))
+///     .await
+///     .unwrap();
+/// assert_eq!('a', *tuple.get_field::<char>(1));
+/// # });
+/// ```
+#[async_trait]
+pub trait AsyncSpace: Send + Sync {
+    /// Finds a tuple matching the template, removes it from the space and returns it.
+    ///
+    /// Waits until a tuple is found
+    async fn get(&self, template: Template) -> Result<Tuple>;
+
+    /// Tries to get a matching tuple from the space by removing it without waiting
+    ///
+    /// # Errors
+    /// This will return `Error::NoMatch` if no tuple is found
+    async fn getp(&self, template: Template) -> Result<Tuple>;
+
+    /// Puts the given tuple into the tuple space
+    async fn put(&self, tuple: Tuple) -> Result<()>;
+
+    /// Puts the given tuple into the tuple space until the time to live runs out, see
+    /// `Space::put_with_ttl`
+    async fn put_with_ttl(&self, tuple: Tuple, ttl: Duration) -> Result<()>;
+
+    /// Renews the leases of the matching tuples put with a time to live, see `Space::renew`
+    async fn renew(&self, template: Template, ttl: Duration) -> Result<Vec<Tuple>>;
+
+    /// Tries to find a matching tuple and returns it without removing it or waiting
+    ///
+    /// # Errors
+    /// This will return `Error::NoMatch` if no tuple is found
+    async fn queryp(&self, template: Template) -> Result<Tuple>;
+
+    /// Finds a tuple matching the template and returns it without removing it.
+    ///
+    /// Waits until a tuple is found
+    async fn query(&self, template: Template) -> Result<Tuple>;
+
+    /// Like `get`, but gives up after the timeout
+    ///
+    /// # Errors
+    /// This will return `Error::TimedOut` if no tuple is found in time
+    async fn get_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple>;
+
+    /// Like `query`, but gives up after the timeout
+    ///
+    /// # Errors
+    /// This will return `Error::TimedOut` if no tuple is found in time
+    async fn query_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple>;
+
+    /// Removes and returns all tuples matching the template
+    async fn getall(&self, template: Template) -> Result<Vec<Tuple>>;
+
+    /// Returns all tuples matching the template without removing them
+    async fn queryall(&self, template: Template) -> Result<Vec<Tuple>>;
+
+    /// Makes the operations as one transaction, see `Space::batch`
+    async fn batch(&self, operations: Vec<Operation>) -> Result<Vec<Vec<Tuple>>>;
+}
+
+/// Local spaces are shared between blocking and async code, tuples put from either side wake
+/// waiters on both
+#[async_trait]
+impl AsyncSpace for LocalSpace {
+    async fn get(&self, template: Template) -> Result<Tuple> {
+        self.wait_async(template, true, None).await
+    }
+
+    async fn getp(&self, template: Template) -> Result<Tuple> {
+        Space::getp(self, template)
+    }
+
+    async fn put(&self, tuple: Tuple) -> Result<()> {
+        Space::put_tuple(self, tuple)
+    }
+
+    async fn put_with_ttl(&self, tuple: Tuple, ttl: Duration) -> Result<()> {
+        Space::put_with_ttl(self, tuple, ttl)
+    }
+
+    async fn renew(&self, template: Template, ttl: Duration) -> Result<Vec<Tuple>> {
+        Space::renew(self, template, ttl)
+    }
+
+    async fn queryp(&self, template: Template) -> Result<Tuple> {
+        Space::queryp(self, template)
+    }
+
+    async fn query(&self, template: Template) -> Result<Tuple> {
+        self.wait_async(template, false, None).await
+    }
+
+    async fn get_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple> {
+        // A timeout too long to be represented never runs out
+        let deadline = Instant::now().checked_add(timeout);
+        self.wait_async(template, true, deadline).await
+    }
+
+    async fn query_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple> {
+        let deadline = Instant::now().checked_add(timeout);
+        self.wait_async(template, false, deadline).await
+    }
+
+    async fn getall(&self, template: Template) -> Result<Vec<Tuple>> {
+        Space::getall(self, template)
+    }
+
+    async fn queryall(&self, template: Template) -> Result<Vec<Tuple>> {
+        Space::queryall(self, template)
+    }
+
+    async fn batch(&self, operations: Vec<Operation>) -> Result<Vec<Vec<Tuple>>> {
+        Space::batch(self, operations)
+    }
+}
+
+/// A space on a gate, accessed from tokio tasks. Speaks the same protocol as `RemoteSpace`, so
+/// it can connect to both blocking and async gates.
+///
+/// Tasks sharing the remote space can have any number of requests outstanding at once. A
+/// request whose future is dropped is not called off at the gate, so tuples taken for it are
+/// put back into the space once they arrive, as if they were put anew.
+pub struct AsyncRemoteSpace {
+    client_id: String,
+    requests: mpsc::UnboundedSender<Message>,
+    pending: Arc<StdMutex<Pending>>,
+    next_id: AtomicU64,
+    reader: JoinHandle<()>,
+}
+
+/// Tasks waiting for replies, by request id
+#[derive(Default)]
+struct Pending {
+    replies: HashMap<u64, oneshot::Sender<Result<Message>>>,
+    /// Requests taking tuples out of the space whose futures were dropped before the reply
+    /// arrived
+    abandoned: HashSet<u64>,
+    closed: bool,
+}
+
+/// Forgets the request if its future is dropped before the reply arrives. Tuples taken for a
+/// request that is forgotten are put back into the space, by the reader if the reply is yet
+/// to arrive.
+struct PendingGuard<'a> {
+    pending: &'a StdMutex<Pending>,
+    requests: &'a mpsc::UnboundedSender<Message>,
+    id: u64,
+    /// Whether the request takes the tuples it replies with out of the space
+    takes: bool,
+    rx: oneshot::Receiver<Result<Message>>,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.pending.lock().unwrap();
+        let waiting = pending.replies.remove(&self.id).is_some();
+        if !self.takes {
+            return;
+        }
+        if waiting {
+            pending.abandoned.insert(self.id);
+        } else if let Ok(Ok(reply)) = self.rx.try_recv() {
+            // The reply arrived but was never read
+            AsyncRemoteSpace::put_back(self.requests, reply);
+        }
+    }
+}
+
+impl AsyncRemoteSpace {
+    /// Connects to a space on a gate, given a connection string like `127.0.0.1:3800/space`
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidAddress` if the connection string is not a valid uri and
+    /// `Error::SpaceNotFound` if the gate has no space of that name
+    pub async fn new(conn: String) -> Result<AsyncRemoteSpace> {
+        AsyncRemoteSpace::with_options(conn, ConnectOptions::default()).await
+    }
+
+    /// Connects like `new`, but encodes messages with the given codec instead of json
+    ///
+    /// # Errors
+    /// Returns `Error::Rejected` if the gate does not support the codec
+    pub async fn with_codec(conn: String, codec: Codec) -> Result<AsyncRemoteSpace> {
+        let options = ConnectOptions {
+            codec,
+            ..Default::default()
+        };
+        AsyncRemoteSpace::with_options(conn, options).await
+    }
+
+    /// Connects to a space on a gate, introducing the client with the given options
+    ///
+    /// # Errors
+    /// Returns `Error::SpaceNotFound` if the gate has no space of that name, and
+    /// `Error::Rejected` if the gate refuses the connection for any other reason
+    pub async fn with_options(conn: String, options: ConnectOptions) -> Result<AsyncRemoteSpace> {
+        AsyncRemoteSpace::connect(&conn.parse()?, options).await
+    }
+
+    /// Connects to the space at the uri, which must be a tcp uri. Options given in the uri take
+    /// precedence over the connect options.
+    pub async fn connect(uri: &SpaceUri, options: ConnectOptions) -> Result<AsyncRemoteSpace> {
+        let options = uri.apply(options);
+        #[cfg(feature = "tls")]
+        let tls = options.tls.is_some();
+        #[cfg(not(feature = "tls"))]
+        let tls = false;
+        let addr = match &uri.endpoint {
+            Endpoint::Tcp(addr) if !tls => addr,
+            _ => {
+                return Err(Error::InvalidAddress(UriError::UnsupportedScheme(
+                    String::from("async remote spaces only connect over plain tcp"),
+                )))
+            }
+        };
+        let greet = AsyncRemoteSpace::greet(addr, uri.space.clone(), &options);
+        let (stream, client_id) = match options.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, greet).await {
+                Ok(greeted) => greeted?,
+                Err(_) => return Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into()),
+            },
+            None => greet.await?,
+        };
+        let codec = options.codec;
+
+        let (read, write) = stream.into_split();
+        let pending = Arc::new(StdMutex::new(Pending::default()));
+        let (requests, rx) = mpsc::unbounded_channel();
+        // Requests are written by a task of their own, so a cancelled request is never cut off halfway
+        tokio::spawn(AsyncRemoteSpace::write_requests(
+            AsyncFramedStream::new(write, codec),
+            rx,
+        ));
+        let reader = tokio::spawn(AsyncRemoteSpace::read_replies(
+            AsyncFramedStream::new(read, codec),
+            Arc::clone(&pending),
+            requests.clone(),
+            codec,
+        ));
+        Ok(AsyncRemoteSpace {
+            client_id,
+            requests,
+            pending,
+            next_id: AtomicU64::new(1),
+            reader,
+        })
+    }
+
+    /// The id the gate knows this client by
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Connects to the gate and asks for the space, returning the id of the connection
+    async fn greet(
+        addr: &str,
+        space: String,
+        options: &ConnectOptions,
+    ) -> Result<(TcpStream, String)> {
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(&options.hello(space)?).await?;
+        let welcome = handshake::read_frame_async(&mut stream).await?;
+        Ok((stream, handshake::check_welcome(&welcome)?))
+    }
+
+    async fn write_requests(
+        mut stream: AsyncFramedStream<OwnedWriteHalf>,
+        mut requests: mpsc::UnboundedReceiver<Message>,
+    ) {
+        while let Some(m) = requests.recv().await {
+            if stream.send(&m).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn read_replies(
+        mut stream: AsyncFramedStream<OwnedReadHalf>,
+        pending: Arc<StdMutex<Pending>>,
+        requests: mpsc::UnboundedSender<Message>,
+        codec: Codec,
+    ) {
+        let error = loop {
+            let payload = match stream.read_frame().await {
+                Ok(payload) => payload,
+                Err(e) => break e,
+            };
+            match codec.decode(&payload) {
+                Ok(m) => {
+                    // Replies nobody waits for were cancelled, or are errors about frames that
+                    // could not be decoded. Tuples taken for cancelled requests go back.
+                    let mut pending = pending.lock().unwrap();
+                    if let Some(tx) = pending.replies.remove(&m.id) {
+                        let _ = tx.send(Ok(m));
+                    } else if pending.abandoned.remove(&m.id) {
+                        AsyncRemoteSpace::put_back(&requests, m);
+                    }
+                }
+                // The frame was read in full, so only the request it replies to fails
+                Err(e) => {
+                    let id = codec.decode_id(&payload);
+                    let waiting = id.and_then(|id| pending.lock().unwrap().replies.remove(&id));
+                    if let Some(tx) = waiting {
+                        let _ = tx.send(Err(e));
+                    }
+                }
+            }
+        };
+        let mut pending = pending.lock().unwrap();
+        pending.closed = true;
+        pending.abandoned.clear();
+        for (_, tx) in pending.replies.drain() {
+            let e = match &error {
+                Error::Closed | Error::Io(_) => Error::Closed,
+                e => Error::Protocol(e.to_string()),
+            };
+            let _ = tx.send(Err(e));
+        }
+    }
+
+    /// Puts the tuples of a reply to a request that took them back into the space, as the task
+    /// that made the request is gone. The gate's reply to the put is not waited for.
+    fn put_back(requests: &mpsc::UnboundedSender<Message>, reply: Message) {
+        if reply.action != MessageType::Ok {
+            return;
+        }
+        for tuple in reply.tuple {
+            let _ = requests.send(Message {
+                id: 0,
+                action: MessageType::Put,
+                tuple: vec![tuple],
+                template: new_template!(),
+            });
+        }
+    }
+
+    /// Requests that take the tuples they reply with out of the space
+    fn takes(action: &MessageType) -> bool {
+        matches!(
+            action,
+            MessageType::Get | MessageType::Getp | MessageType::GetTimeout(_) | MessageType::Getall
+        )
+    }
+
+    async fn request(
+        &self,
+        action: MessageType,
+        tuple: Vec<Tuple>,
+        template: Template,
+    ) -> Result<Message> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.closed {
+                return Err(Error::Closed);
+            }
+            pending.replies.insert(id, tx);
+        }
+        let mut guard = PendingGuard {
+            pending: &self.pending,
+            requests: &self.requests,
+            id,
+            takes: AsyncRemoteSpace::takes(&action),
+            rx,
+        };
+        let m = Message {
+            id,
+            action,
+            tuple,
+            template,
+        };
+        if self.requests.send(m).is_err() {
+            return Err(Error::Closed);
+        }
+        (&mut guard.rx).await.unwrap_or(Err(Error::Closed))
+    }
+
+    async fn send_recv(&self, action: MessageType, template: Template) -> Result<Tuple> {
+        RemoteSpace::single_tuple(self.request(action, Vec::new(), template).await?)
+    }
+
+    async fn send_recv_multiple(
+        &self,
+        action: MessageType,
+        template: Template,
+    ) -> Result<Vec<Tuple>> {
+        RemoteSpace::tuples(self.request(action, Vec::new(), template).await?)
+    }
+}
+
+impl Drop for AsyncRemoteSpace {
+    fn drop(&mut self) {
+        // The writer task ends by itself once the requests channel is dropped
+        self.reader.abort();
+    }
+}
+
+#[async_trait]
+impl AsyncSpace for AsyncRemoteSpace {
+    async fn get(&self, template: Template) -> Result<Tuple> {
+        self.send_recv(MessageType::Get, template).await
+    }
+
+    async fn getp(&self, template: Template) -> Result<Tuple> {
+        self.send_recv(MessageType::Getp, template).await
+    }
+
+    async fn put(&self, tuple: Tuple) -> Result<()> {
+        let reply = self
+            .request(MessageType::Put, vec![tuple], new_template!())
+            .await?;
+        RemoteSpace::tuples(reply).map(|_| ())
+    }
+
+    async fn put_with_ttl(&self, tuple: Tuple, ttl: Duration) -> Result<()> {
+        let reply = self
+            .request(MessageType::PutWithTtl(ttl), vec![tuple], new_template!())
+            .await?;
+        RemoteSpace::tuples(reply).map(|_| ())
+    }
+
+    async fn renew(&self, template: Template, ttl: Duration) -> Result<Vec<Tuple>> {
+        self.send_recv_multiple(MessageType::Renew(ttl), template)
+            .await
+    }
+
+    async fn queryp(&self, template: Template) -> Result<Tuple> {
+        self.send_recv(MessageType::Queryp, template).await
+    }
+
+    async fn query(&self, template: Template) -> Result<Tuple> {
+        self.send_recv(MessageType::Query, template).await
+    }
+
+    async fn get_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple> {
+        self.send_recv(MessageType::GetTimeout(timeout), template)
+            .await
+    }
+
+    async fn query_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple> {
+        self.send_recv(MessageType::QueryTimeout(timeout), template)
+            .await
+    }
+
+    async fn getall(&self, template: Template) -> Result<Vec<Tuple>> {
+        self.send_recv_multiple(MessageType::Getall, template).await
+    }
+
+    async fn queryall(&self, template: Template) -> Result<Vec<Tuple>> {
+        self.send_recv_multiple(MessageType::Queryall, template)
+            .await
+    }
+
+    async fn batch(&self, operations: Vec<Operation>) -> Result<Vec<Vec<Tuple>>> {
+        let (steps, tuples, template) = batch::encode(operations);
+        let reply = self
+            .request(MessageType::Transaction(steps), tuples, template)
+            .await?;
+        match reply.action {
+            MessageType::Committed(counts) => batch::split(counts, reply.tuple),
+            action => Err(RemoteSpace::reply_error(action)),
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    ops::BitOr,
+    path::Path,
+};
+
+use crate::{Credentials, MessageType, Result};
+
+/// Grants made to this principal apply to every authenticated client
+pub const EVERYONE: &str = "*";
+
+/// How the gates of a repository check the credentials sent by connecting clients
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// # use std::sync::Arc;
+/// let repo = Arc::new(Repository::new());
+/// repo.add_space(String::from("jobs"), Arc::new(LocalSpace::new_sequential()));
+/// repo.set_authenticator(Authenticator::token("secret", "worker"));
+/// repo.grant("worker", "jobs", Permissions::READ | Permissions::TAKE);
+/// ```
+#[derive(Clone, Debug)]
+pub enum Authenticator {
+    /// Every client presenting the token is known as the principal
+    Token { token: String, principal: String },
+    /// Clients log in with a user name, which is their principal, and a password
+    Users(HashMap<String, String>),
+    /// Clients are known by the certificate they present to a gate added with
+    /// `Repository::add_gate_tls`, see `TlsAcceptor::with_client_auth`. Certificates are kept as
+    /// DER and mapped to their principal.
+    Certificates(HashMap<Vec<u8>, String>),
+}
+
+impl Authenticator {
+    pub fn token(token: &str, principal: &str) -> Authenticator {
+        Authenticator::Token {
+            token: token.to_string(),
+            principal: principal.to_string(),
+        }
+    }
+
+    /// Reads users from a file with a `name:password` line for each user. Empty lines and lines
+    /// starting with `#` are skipped.
+    ///
+    /// # Errors
+    /// Returns `Error::Io` if the file can not be read or a line has no `:`
+    pub fn users_from_file<P: AsRef<Path>>(path: P) -> Result<Authenticator> {
+        let mut users = HashMap::new();
+        for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(':') {
+                Some((name, password)) => {
+                    users.insert(name.to_string(), password.to_string());
+                }
+                None => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("line {} of the users file has no ':'", i + 1),
+                    )
+                    .into())
+                }
+            }
+        }
+        Ok(Authenticator::Users(users))
+    }
+
+    /// Knows clients by the first certificate of each PEM bundle as the principal paired with it
+    ///
+    /// # Errors
+    /// Returns `Error::Io` if a bundle holds no certificate
+    #[cfg(feature = "tls")]
+    pub fn certificates<'a, I>(certificates: I) -> Result<Authenticator>
+    where
+        I: IntoIterator<Item = (&'a [u8], &'a str)>,
+    {
+        let mut principals = HashMap::new();
+        for (pem, principal) in certificates {
+            principals.insert(crate::tls::certificate(pem)?, principal.to_string());
+        }
+        Ok(Authenticator::Certificates(principals))
+    }
+
+    /// Returns the principal the credentials, or the certificate the client authenticated its
+    /// TLS connection with, belong to
+    pub(crate) fn authenticate(
+        &self,
+        credentials: Option<&Credentials>,
+        certificate: Option<&[u8]>,
+    ) -> Option<String> {
+        match (self, credentials) {
+            (Authenticator::Token { token, principal }, Some(Credentials::Token(given))) => {
+                same_secret(token, given).then(|| principal.clone())
+            }
+            (Authenticator::Users(users), Some(Credentials::User { name, password })) => {
+                let expected = users.get(name)?;
+                same_secret(expected, password).then(|| name.clone())
+            }
+            // Only the certificate counts, whatever credentials the hello carries
+            (Authenticator::Certificates(principals), _) => principals.get(certificate?).cloned(),
+            _ => None,
+        }
+    }
+}
+
+/// Compares secrets in time independent of where they differ
+fn same_secret(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// What a principal may do with a space
+///
+/// Taking tuples also reveals them, so `TAKE` without `READ` still lets a client see the tuples
+/// it removes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Permissions {
+    /// `query`, `queryp`, `queryall`, `query_timeout` and subscribing
+    pub read: bool,
+    /// `get`, `getp`, `getall` and `get_timeout`
+    pub take: bool,
+    pub put: bool,
+}
+
+impl Permissions {
+    pub const NONE: Permissions = Permissions {
+        read: false,
+        take: false,
+        put: false,
+    };
+    pub const READ: Permissions = Permissions {
+        read: true,
+        ..Permissions::NONE
+    };
+    pub const TAKE: Permissions = Permissions {
+        take: true,
+        ..Permissions::NONE
+    };
+    pub const PUT: Permissions = Permissions {
+        put: true,
+        ..Permissions::NONE
+    };
+    pub const ALL: Permissions = Permissions {
+        read: true,
+        take: true,
+        put: true,
+    };
+
+    /// Whether the request is allowed, replies are never checked
+    pub(crate) fn allow(&self, action: &MessageType) -> bool {
+        match action {
+            MessageType::Query
+            | MessageType::Queryp
+            | MessageType::Queryall
+            | MessageType::QueryTimeout(_)
+            | MessageType::Subscribe => self.read,
+            MessageType::Get
+            | MessageType::Getp
+            | MessageType::Getall
+            | MessageType::GetTimeout(_) => self.take,
+            MessageType::Put | MessageType::PutWithTtl(_) => self.put,
+            MessageType::Renew(_) => self.read && self.put,
+            MessageType::Transaction(steps) => steps.iter().all(|s| self.allow(&s.action())),
+            _ => true,
+        }
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Permissions;
+
+    fn bitor(self, other: Permissions) -> Permissions {
+        Permissions {
+            read: self.read || other.read,
+            take: self.take || other.take,
+            put: self.put || other.put,
+        }
+    }
+}
+
+/// The authenticator and grants of a repository
+#[derive(Default)]
+pub(crate) struct Access {
+    pub(crate) authenticator: Option<Authenticator>,
+    /// Permissions by principal and space
+    pub(crate) grants: HashMap<String, HashMap<String, Permissions>>,
+}
+
+impl Access {
+    /// Checks the credentials of a client connecting to the space and returns what it may do
+    /// there. Without an authenticator every client may do everything.
+    pub(crate) fn admit(
+        &self,
+        credentials: Option<&Credentials>,
+        certificate: Option<&[u8]>,
+        space: &str,
+    ) -> std::result::Result<Permissions, String> {
+        let authenticator = match &self.authenticator {
+            Some(authenticator) => authenticator,
+            None => return Ok(Permissions::ALL),
+        };
+        let principal = match authenticator.authenticate(credentials, certificate) {
+            Some(principal) => principal,
+            None => return Err(String::from("missing or invalid credentials")),
+        };
+        let granted = |principal: &str| {
+            self.grants
+                .get(principal)
+                .and_then(|spaces| spaces.get(space))
+                .copied()
+                .unwrap_or_default()
+        };
+        match granted(&principal) | granted(EVERYONE) {
+            Permissions::NONE => Err(format!("{} may not use space {}", principal, space)),
+            permissions => Ok(permissions),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Arity, Error, MessageType, Result, Template, Tuple};
+
+/// One operation of a transaction run by `Space::batch`
+#[derive(Clone)]
+pub enum Operation {
+    Put(Tuple),
+    Getp(Template),
+    Queryp(Template),
+    Getall(Template),
+    Queryall(Template),
+}
+
+/// One operation of a `Transaction` request to a gate.
+///
+/// The tuples of the puts are the tuples of the message in order, and the templates of the other
+/// operations follow each other in the fields of the template of the message.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Step {
+    Put,
+    Getp(Shape),
+    Queryp(Shape),
+    Getall(Shape),
+    Queryall(Shape),
+}
+
+/// How many fields of the template of a `Transaction` request belong to the template of a step,
+/// and its arity
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Shape {
+    pub fields: usize,
+    pub arity: Arity,
+}
+
+impl Step {
+    /// The request making the same operation on its own, which takes the same permission
+    pub(crate) fn action(&self) -> MessageType {
+        match self {
+            Step::Put => MessageType::Put,
+            Step::Getp(_) => MessageType::Getp,
+            Step::Queryp(_) => MessageType::Queryp,
+            Step::Getall(_) => MessageType::Getall,
+            Step::Queryall(_) => MessageType::Queryall,
+        }
+    }
+}
+
+/// Lays the operations out as the steps, tuples and template of a `Transaction` request
+pub(crate) fn encode(operations: Vec<Operation>) -> (Vec<Step>, Vec<Tuple>, Template) {
+    let mut steps = Vec::new();
+    let mut tuples = Vec::new();
+    let mut fields = Vec::new();
+    for operation in operations {
+        let (step, template): (fn(Shape) -> Step, Template) = match operation {
+            Operation::Put(tuple) => {
+                steps.push(Step::Put);
+                tuples.push(tuple);
+                continue;
+            }
+            Operation::Getp(template) => (Step::Getp, template),
+            Operation::Queryp(template) => (Step::Queryp, template),
+            Operation::Getall(template) => (Step::Getall, template),
+            Operation::Queryall(template) => (Step::Queryall, template),
+        };
+        steps.push(step(Shape {
+            fields: template.fields.len(),
+            arity: template.arity,
+        }));
+        fields.extend(template.fields);
+    }
+    (steps, tuples, Template::new_fields(fields))
+}
+
+/// The operations of a `Transaction` request, or why the request is not valid
+pub(crate) fn decode(
+    steps: Vec<Step>,
+    tuples: Vec<Tuple>,
+    template: Template,
+) -> std::result::Result<Vec<Operation>, String> {
+    let mut tuples = tuples.into_iter();
+    let mut fields = template.fields.into_iter();
+    let mut operations = Vec::new();
+    for step in steps {
+        let (operation, shape): (fn(Template) -> Operation, Shape) = match step {
+            Step::Put => match tuples.next() {
+                Some(tuple) => {
+                    operations.push(Operation::Put(tuple));
+                    continue;
+                }
+                None => return Err(String::from("the transaction puts more tuples than it has")),
+            },
+            Step::Getp(shape) => (Operation::Getp, shape),
+            Step::Queryp(shape) => (Operation::Queryp, shape),
+            Step::Getall(shape) => (Operation::Getall, shape),
+            Step::Queryall(shape) => (Operation::Queryall, shape),
+        };
+        let taken: Vec<_> = fields.by_ref().take(shape.fields).collect();
+        if taken.len() < shape.fields {
+            return Err(String::from(
+                "the templates of the transaction have more fields than it has",
+            ));
+        }
+        let mut template = Template::new_fields(taken);
+        template.arity = shape.arity;
+        operations.push(operation(template));
+    }
+    if tuples.next().is_some() || fields.next().is_some() {
+        return Err(String::from(
+            "the transaction has tuples or fields no step uses",
+        ));
+    }
+    Ok(operations)
+}
+
+/// Splits the tuples of the reply to a `Transaction` request among its operations
+pub(crate) fn split(counts: Vec<usize>, mut tuples: Vec<Tuple>) -> Result<Vec<Vec<Tuple>>> {
+    if counts.iter().sum::<usize>() != tuples.len() {
+        return Err(Error::Protocol(format!(
+            "the operations returned {} tuples but the reply has {}",
+            counts.iter().sum::<usize>(),
+            tuples.len()
+        )));
+    }
+    let mut results = Vec::new();
+    for count in counts {
+        let rest = tuples.split_off(count);
+        results.push(tuples);
+        tuples = rest;
+    }
+    Ok(results)
+}
@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    frame::{write_message, FramedStream},
+    new_template,
+    transport::Stream,
+    Codec, Error, Message, MessageType, Result, SpaceEvent, Template,
+};
+
+/// The client end of a connection to a gate, shared by every thread using a remote space.
+///
+/// Each request is sent with a new id, and a background thread reading the connection hands
+/// every reply to the caller waiting for that id. Any number of requests can be outstanding, so
+/// a blocked `get` does not hold up other threads. Events pushed to a subscription carry the id
+/// of the request that made it, and are handed to its receiver.
+pub(crate) struct Client {
+    /// Shared with the reader thread, which ends subscriptions nobody receives anymore
+    writer: Arc<Mutex<Box<dyn Stream>>>,
+    codec: Codec,
+    pending: Arc<Mutex<Pending>>,
+    next_id: AtomicU64,
+}
+
+/// Callers waiting for replies and subscriptions receiving events, by request id
+#[derive(Default)]
+struct Pending {
+    replies: HashMap<u64, Sender<Result<Message>>>,
+    subscriptions: HashMap<u64, Sender<SpaceEvent>>,
+    closed: bool,
+}
+
+impl Client {
+    pub(crate) fn new(stream: Box<dyn Stream>, codec: Codec) -> Result<Client> {
+        let reader = FramedStream::new(stream.try_clone()?, codec);
+        let writer = Arc::new(Mutex::new(stream));
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let (w, p) = (Arc::clone(&writer), Arc::clone(&pending));
+        thread::spawn(move || Client::read_replies(reader, p, w, codec));
+        Ok(Client {
+            writer,
+            codec,
+            pending,
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    /// Sends the request and waits for its reply
+    pub(crate) fn request(&self, m: Message) -> Result<Message> {
+        self.exchange(m, None)
+    }
+
+    /// Sends a subscription request and waits for its reply. If the gate accepts it, the events
+    /// that follow are handed to the sender until its receiver is dropped.
+    pub(crate) fn subscribe(&self, m: Message, events: Sender<SpaceEvent>) -> Result<Message> {
+        self.exchange(m, Some(events))
+    }
+
+    fn exchange(&self, mut m: Message, events: Option<Sender<SpaceEvent>>) -> Result<Message> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let subscribing = events.is_some();
+        let (tx, rx) = mpsc::channel();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.closed {
+                return Err(Error::Closed);
+            }
+            pending.replies.insert(id, tx);
+            // Registered before sending, as events may overtake the reply
+            if let Some(events) = events {
+                pending.subscriptions.insert(id, events);
+            }
+        }
+        m.id = id;
+        let sent = {
+            let mut writer = self.writer.lock().unwrap();
+            write_message(&mut *writer, self.codec, &m)
+        };
+        if let Err(e) = sent {
+            let mut pending = self.pending.lock().unwrap();
+            pending.replies.remove(&id);
+            pending.subscriptions.remove(&id);
+            return Err(e);
+        }
+        let reply = rx.recv().unwrap_or(Err(Error::Closed));
+        let accepted = matches!(&reply, Ok(m) if m.action == MessageType::Ok);
+        if subscribing && !accepted {
+            self.pending.lock().unwrap().subscriptions.remove(&id);
+        }
+        reply
+    }
+
+    fn read_replies(
+        mut stream: FramedStream<Box<dyn Stream>>,
+        pending: Arc<Mutex<Pending>>,
+        writer: Arc<Mutex<Box<dyn Stream>>>,
+        codec: Codec,
+    ) {
+        let error = loop {
+            let payload = match stream.read_frame() {
+                Ok(payload) => payload,
+                Err(e) => break e,
+            };
+            match codec.decode(&payload) {
+                Ok(m) if matches!(m.action, MessageType::Event(_)) => {
+                    Client::deliver(m, &pending, &writer, codec)
+                }
+                Ok(m) => {
+                    let mut pending = pending.lock().unwrap();
+                    match pending.replies.remove(&m.id) {
+                        Some(tx) => {
+                            let _ = tx.send(Ok(m));
+                        }
+                        // Other replies nobody waits for are errors about frames that could
+                        // not be decoded
+                        None => {
+                            // The gate dropped the subscription, so its receiver ends
+                            pending.subscriptions.remove(&m.id);
+                        }
+                    }
+                }
+                // The frame was read in full, so only the request it replies to fails, if its
+                // id can be made out. Events that can not be decoded are dropped.
+                Err(e) => {
+                    let id = codec.decode_id(&payload);
+                    let waiting = id.and_then(|id| pending.lock().unwrap().replies.remove(&id));
+                    if let Some(tx) = waiting {
+                        let _ = tx.send(Err(e));
+                    }
+                }
+            }
+        };
+        let mut pending = pending.lock().unwrap();
+        pending.closed = true;
+        // Ends the subscriptions, so their receivers see the connection is gone
+        pending.subscriptions.clear();
+        for (_, tx) in pending.replies.drain() {
+            let e = match &error {
+                Error::Closed | Error::Io(_) => Error::Closed,
+                e => Error::Protocol(e.to_string()),
+            };
+            let _ = tx.send(Err(e));
+        }
+    }
+}
+
+impl Client {
+    /// Hands an event to its subscription, telling the gate to stop pushing events once the
+    /// receiver is dropped
+    fn deliver(
+        m: Message,
+        pending: &Mutex<Pending>,
+        writer: &Arc<Mutex<Box<dyn Stream>>>,
+        codec: Codec,
+    ) {
+        let id = m.id;
+        let mut pending = pending.lock().unwrap();
+        let events = match pending.subscriptions.get(&id) {
+            Some(events) => events,
+            None => return,
+        };
+        // Events that are not valid are dropped, the subscription goes on
+        let event = match SpaceEvent::from_message(m) {
+            Some(event) => event,
+            None => return,
+        };
+        if events.send(event).is_ok() {
+            return;
+        }
+        pending.subscriptions.remove(&id);
+        drop(pending);
+        // The reply carries the id of the subscription, which nobody waits for anymore
+        let stop = Message {
+            id,
+            action: MessageType::Unsubscribe,
+            tuple: Vec::new(),
+            template: new_template!(),
+        };
+        // Written by a thread of its own, as the writer may be held by a request blocked until
+        // the gate reads, which it may only do once this thread reads the events it sends
+        let writer = Arc::clone(writer);
+        thread::spawn(move || {
+            let mut writer = writer.lock().unwrap();
+            let _ = write_message(&mut *writer, codec, &stop);
+        });
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // Stops the reader thread
+        let _ = self.writer.lock().unwrap().shutdown();
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{de::IgnoredAny, Deserialize};
+
+use crate::{Error, Message, Result};
+
+/// How messages are encoded on a connection between a remote space and a gate.
+///
+/// The codec is chosen by the remote space when connecting, gates support all of them.
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// # use std::sync::Arc;
+/// let repo = Arc::new(Repository::new());
+/// repo.add_space(String::from("space"), Arc::new(LocalSpace::new_sequential()));
+/// Repository::add_gate(Arc::clone(&repo), String::from("gate"), "127.0.0.1:3791".to_string())
+///     .unwrap();
+///
+/// let space =
+///     RemoteSpace::with_codec(String::from("127.0.0.1:3791/space"), Codec::MessagePack).unwrap();
+/// space.put(new_tuple!(5, 2.5)).unwrap();
+/// assert_eq!(1, space.queryall(new_template!(5.actual(), 0.0.formal())).unwrap().len());
+/// # drop(space);
+/// # repo.close_gate(String::from("gate"));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// Human readable json, useful for debugging
+    #[default]
+    Json,
+    /// Compact binary MessagePack
+    MessagePack,
+}
+
+impl Codec {
+    /// The name of the codec as sent in the hello
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            Codec::MessagePack => "msgpack",
+        }
+    }
+
+    /// Finds the codec with the given name
+    pub fn from_name(name: &str) -> Option<Codec> {
+        match name {
+            "json" => Some(Codec::Json),
+            "msgpack" => Some(Codec::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn encode(self, m: &Message) -> Result<Vec<u8>> {
+        match self {
+            Codec::Json => Ok(serde_json::to_vec(m)?),
+            Codec::MessagePack => Ok(rmp_serde::to_vec(m)?),
+        }
+    }
+
+    pub(crate) fn decode(self, payload: &[u8]) -> Result<Message> {
+        match self {
+            Codec::Json => Ok(serde_json::from_slice(payload)?),
+            Codec::MessagePack => Ok(rmp_serde::from_slice(payload)?),
+        }
+    }
+
+    /// Recovers the id of a message that could not be decoded, such as one carrying a tuple
+    /// with a field type this side does not know
+    pub(crate) fn decode_id(self, payload: &[u8]) -> Option<u64> {
+        let header = match self {
+            Codec::Json => serde_json::from_slice::<Header>(payload).ok(),
+            Codec::MessagePack => rmp_serde::from_slice::<Header>(payload).ok(),
+        };
+        header.map(|header| header.id)
+    }
+}
+
+/// A message of which only the id is decoded. The other fields are listed as MessagePack
+/// encodes messages as arrays.
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Header {
+    id: u64,
+    action: IgnoredAny,
+    tuple: IgnoredAny,
+    template: IgnoredAny,
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        Error::Serialization(Box::new(e))
+    }
+}
+
+impl From<rmp_serde::decode::Error> for Error {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        Error::Serialization(Box::new(e))
+    }
+}
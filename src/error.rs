@@ -0,0 +1,138 @@
+use std::fmt::Display;
+
+use crate::{ErrorCode, Rejection, UriError};
+
+/// Errors returned by spaces, remote spaces, gates and repositories
+#[derive(Debug)]
+pub enum Error {
+    /// No tuple in the space matched the template
+    NoMatch,
+    /// No space with the requested name exists in the repository
+    SpaceNotFound,
+    /// A blocking operation did not find a tuple before its timeout
+    TimedOut,
+    /// The connection was closed by the other end
+    Closed,
+    /// The connection string could not be understood
+    InvalidAddress(UriError),
+    /// The other end sent something that does not follow the protocol
+    Protocol(String),
+    /// A regular expression in a template could not be compiled
+    InvalidPattern(String),
+    /// The gate replied with an error instead of executing the request
+    Remote(ErrorCode, String),
+    /// The gate refused the connection
+    Rejected(Rejection, String),
+    /// A message or tuple could not be serialized or deserialized by the codec of the connection
+    Serialization(Box<dyn std::error::Error + Send + Sync>),
+    /// The TLS connection could not be set up, or a certificate or key could not be used
+    Tls(Box<dyn std::error::Error + Send + Sync>),
+    /// An io error occurred on the underlying connection
+    Io(std::io::Error),
+    /// A field of a tuple could not be extracted as the requested type
+    Field(FieldError),
+}
+
+/// Errors returned when extracting typed values from a tuple
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FieldError {
+    /// The index is past the end of the tuple
+    OutOfBounds { index: usize, len: usize },
+    /// The field holds a different type than the one requested
+    TypeMismatch {
+        index: usize,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The tuple does not have the number of fields the target type needs
+    ArityMismatch { expected: usize, found: usize },
+}
+
+/// Result type used throughout rspaces
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NoMatch => write!(f, "no tuple matched the template"),
+            Error::SpaceNotFound => write!(f, "space not found"),
+            Error::TimedOut => write!(f, "timed out waiting for a matching tuple"),
+            Error::Closed => write!(f, "connection closed"),
+            Error::InvalidAddress(s) => write!(f, "invalid address: {}", s),
+            Error::Protocol(s) => write!(f, "protocol error: {}", s),
+            Error::InvalidPattern(s) => write!(f, "invalid pattern: {}", s),
+            Error::Remote(code, s) => write!(f, "remote error ({:?}): {}", code, s),
+            Error::Rejected(reason, s) => write!(f, "connection rejected ({}): {}", reason, s),
+            Error::Serialization(e) => write!(f, "serialization error: {}", e),
+            Error::Tls(e) => write!(f, "tls error: {}", e),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Field(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldError::OutOfBounds { index, len } => {
+                write!(
+                    f,
+                    "field {} out of bounds for tuple of {} fields",
+                    index, len
+                )
+            }
+            FieldError::TypeMismatch {
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "field {} holds a {} but a {} was requested",
+                index, found, expected
+            ),
+            FieldError::ArityMismatch { expected, found } => write!(
+                f,
+                "expected a tuple of {} fields but it has {}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Serialization(e) | Error::Tls(e) => Some(&**e),
+            Error::Io(e) => Some(e),
+            Error::InvalidAddress(e) => Some(e),
+            Error::Field(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Serialization(Box::new(e))
+    }
+}
+
+impl From<UriError> for Error {
+    fn from(e: UriError) -> Self {
+        Error::InvalidAddress(e)
+    }
+}
+
+impl From<FieldError> for Error {
+    fn from(e: FieldError) -> Self {
+        Error::Field(e)
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::mpsc::{Sender, SyncSender};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{new_template, Message, MessageType, Template, Tuple};
+
+/// A change to a space, reported to the subscribers it concerns, see `LocalSpace::subscribe`
+#[derive(Clone, Debug)]
+pub enum SpaceEvent {
+    /// The tuple was put into the space
+    Put(Tuple),
+    /// The tuple was removed by `get`, `getp` or one of their variants
+    Removed(Tuple),
+    /// The tuples were removed at once by `getall` or `getall_where`. Subscribers only get the
+    /// ones matching their template.
+    RemovedAll(Vec<Tuple>),
+}
+
+/// Which change an `Event` message from a gate reports, the tuples are those of the message
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum EventKind {
+    Put,
+    Removed,
+    RemovedAll,
+}
+
+impl SpaceEvent {
+    /// The event as seen by a subscriber with the template, or None if it concerns none of the
+    /// tuples the subscriber is interested in
+    pub(crate) fn matching(&self, template: &Template) -> Option<SpaceEvent> {
+        match self {
+            SpaceEvent::Put(tuple) | SpaceEvent::Removed(tuple) => {
+                template.query(tuple).then(|| self.clone())
+            }
+            SpaceEvent::RemovedAll(tuples) => {
+                let matching: Vec<Tuple> = tuples
+                    .iter()
+                    .filter(|t| template.query(t))
+                    .cloned()
+                    .collect();
+                (!matching.is_empty()).then_some(SpaceEvent::RemovedAll(matching))
+            }
+        }
+    }
+
+    /// The message a gate pushes to the subscription made by the request with the id
+    pub(crate) fn into_message(self, id: u64) -> Message {
+        let (kind, tuple) = match self {
+            SpaceEvent::Put(tuple) => (EventKind::Put, vec![tuple]),
+            SpaceEvent::Removed(tuple) => (EventKind::Removed, vec![tuple]),
+            SpaceEvent::RemovedAll(tuples) => (EventKind::RemovedAll, tuples),
+        };
+        Message {
+            id,
+            action: MessageType::Event(kind),
+            tuple,
+            template: new_template!(),
+        }
+    }
+
+    /// The event an `Event` message reports, or None if the message is not a valid event
+    pub(crate) fn from_message(mut message: Message) -> Option<SpaceEvent> {
+        match message.action {
+            MessageType::Event(EventKind::Put) if message.tuple.len() == 1 => {
+                Some(SpaceEvent::Put(message.tuple.remove(0)))
+            }
+            MessageType::Event(EventKind::Removed) if message.tuple.len() == 1 => {
+                Some(SpaceEvent::Removed(message.tuple.remove(0)))
+            }
+            MessageType::Event(EventKind::RemovedAll) => {
+                Some(SpaceEvent::RemovedAll(message.tuple))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A receiver of the changes to a local space concerning tuples matching its template
+pub(crate) struct Subscriber {
+    pub(crate) template: Template,
+    pub(crate) events: Events,
+}
+
+/// The channel a subscriber receives its events through
+pub(crate) enum Events {
+    Unbounded(Sender<SpaceEvent>),
+    /// Dropped once it holds as many events as it can
+    Bounded(SyncSender<SpaceEvent>),
+}
+
+impl Events {
+    /// Hands the event to the subscriber, returning false if it is to be dropped
+    pub(crate) fn send(&self, event: SpaceEvent) -> bool {
+        match self {
+            Events::Unbounded(tx) => tx.send(event).is_ok(),
+            Events::Bounded(tx) => tx.try_send(event).is_ok(),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::io::{Read, Write};
+
+use crate::{Codec, Error, Message, Result};
+
+/// Largest payload accepted in a single frame. Frames announcing a bigger payload are rejected
+/// before any of it is read.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+const HEADER_SIZE: usize = 4;
+
+/// The reading end of a stream speaking the framed wire protocol used between gates and remote
+/// spaces, messages are written with `write_message`.
+///
+/// Every message is sent as a 4 byte big endian length followed by that many bytes, encoded with
+/// the codec of the connection.
+/// Bytes read past the end of a frame are kept for the next call to `recv`, so messages split
+/// over several segments or coalesced into one read are both handled.
+pub(crate) struct FramedStream<S: Read> {
+    stream: S,
+    codec: Codec,
+    buffer: Vec<u8>,
+}
+
+impl<S: Read> FramedStream<S> {
+    pub(crate) fn new(stream: S, codec: Codec) -> FramedStream<S> {
+        FramedStream {
+            stream,
+            codec,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Reads the next frame and deserializes it into a message
+    ///
+    /// # Errors
+    /// Read timeouts are passed on to the caller as `Error::Io`, any partially received frame is
+    /// kept and completed by the next call.
+    pub(crate) fn recv(&mut self) -> Result<Message> {
+        let payload = self.read_frame()?;
+        self.codec.decode(&payload)
+    }
+
+    /// Reads the payload of the next frame without deserializing it
+    pub(crate) fn read_frame(&mut self) -> Result<Vec<u8>> {
+        loop {
+            if let Some(payload) = take_frame(&mut self.buffer)? {
+                return Ok(payload);
+            }
+            let mut chunk = [0; 4096];
+            let n = self.stream.read(&mut chunk[..])?;
+            if n == 0 {
+                return Err(Error::Closed);
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+/// Removes the first complete frame from the buffer and returns its payload
+pub(crate) fn take_frame(buffer: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+    if buffer.len() < HEADER_SIZE {
+        return Ok(None);
+    }
+    let mut header = [0; HEADER_SIZE];
+    header.copy_from_slice(&buffer[..HEADER_SIZE]);
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(Error::Protocol(format!(
+            "frame of {} bytes exceeds limit of {}",
+            len, MAX_FRAME_SIZE
+        )));
+    }
+    if buffer.len() < HEADER_SIZE + len {
+        return Ok(None);
+    }
+    let payload = buffer[HEADER_SIZE..HEADER_SIZE + len].to_vec();
+    buffer.drain(..HEADER_SIZE + len);
+    Ok(Some(payload))
+}
+
+/// Prefixes the payload with its length
+pub(crate) fn encode_frame(payload: &[u8]) -> Result<Vec<u8>> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(Error::Protocol(format!(
+            "frame of {} bytes exceeds limit of {}",
+            payload.len(),
+            MAX_FRAME_SIZE
+        )));
+    }
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+/// Serializes the message and writes it as a single frame
+pub(crate) fn write_message<W: Write>(stream: &mut W, codec: Codec, m: &Message) -> Result<()> {
+    write_frame(stream, &codec.encode(m)?)
+}
+
+/// Writes the payload prefixed with its length and flushes the stream
+pub(crate) fn write_frame<W: Write>(stream: &mut W, payload: &[u8]) -> Result<()> {
+    stream.write_all(&encode_frame(payload)?)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// The async counterpart of `FramedStream`, speaking the same wire protocol
+#[cfg(feature = "tokio")]
+pub(crate) struct AsyncFramedStream<S> {
+    stream: S,
+    codec: Codec,
+    buffer: Vec<u8>,
+}
+
+#[cfg(feature = "tokio")]
+impl<S> AsyncFramedStream<S> {
+    pub(crate) fn new(stream: S, codec: Codec) -> AsyncFramedStream<S> {
+        AsyncFramedStream {
+            stream,
+            codec,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: tokio::io::AsyncWrite + Unpin> AsyncFramedStream<S> {
+    /// Serializes the message and writes it as a single frame
+    pub(crate) async fn send(&mut self, m: &Message) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+        let frame = encode_frame(&self.codec.encode(m)?)?;
+        self.stream.write_all(&frame).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: tokio::io::AsyncRead + Unpin> AsyncFramedStream<S> {
+    /// Reads the next frame and deserializes it into a message
+    ///
+    /// This is cancel safe, bytes read before the future is dropped are kept for the next call.
+    pub(crate) async fn recv(&mut self) -> Result<Message> {
+        let payload = self.read_frame().await?;
+        self.codec.decode(&payload)
+    }
+
+    /// Reads the payload of the next frame without deserializing it, as cancel safe as `recv`
+    pub(crate) async fn read_frame(&mut self) -> Result<Vec<u8>> {
+        use tokio::io::AsyncReadExt;
+        loop {
+            if let Some(payload) = take_frame(&mut self.buffer)? {
+                return Ok(payload);
+            }
+            let mut chunk = [0; 4096];
+            let n = self.stream.read(&mut chunk[..]).await?;
+            if n == 0 {
+                return Err(Error::Closed);
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
@@ -1,17 +1,42 @@
 use std::{
+    collections::HashMap,
     io::{Read, Write},
-    net::{TcpListener, TcpStream},
     sync::{
-        mpsc::{self, Receiver, Sender},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
         Arc, Mutex,
     },
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use serde::{Deserialize, Serialize};
 
-use crate::{new_template, space::Space, Repository, Template, Tuple};
+use crate::{
+    batch::{self, Step},
+    frame::{write_message, FramedStream},
+    handshake::{self, Admission},
+    new_template,
+    space::Space,
+    transport::{Listener, Stream},
+    Codec, Error, EventKind, Permissions, Repository, Result, Template, Tuple,
+};
+
+/// How often an idle gate checks for new clients
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How often a thread forwarding events checks whether its subscription has ended
+const FORWARD_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many requests waiting for a tuple a connection may have at once
+pub(crate) const MAX_WAITING: usize = 64;
+
+/// How many events a subscription may have waiting to be sent before the gate drops it
+pub(crate) const SUBSCRIPTION_BACKLOG: usize = 1024;
+
+/// Runs on every accepted connection before the client is greeted, to set up TLS or pass the
+/// connection on as it is
+pub(crate) type Upgrade = Box<dyn Fn(Box<dyn Stream>) -> Result<Box<dyn Stream>> + Send + Sync>;
 
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub enum MessageType {
@@ -22,96 +47,136 @@ pub enum MessageType {
     Queryp,
     Queryall,
     Put,
-    Error,
+    GetTimeout(Duration),
+    QueryTimeout(Duration),
+    /// Puts the tuple of the message for as long as the duration
+    PutWithTtl(Duration),
+    /// Renews the leases of the tuples matching the template, replying with the tuples renewed
+    Renew(Duration),
+    /// Subscribes to the changes to the space concerning tuples matching the template. After
+    /// replying the gate pushes an `Event` with the id of the request for every change, until
+    /// the client unsubscribes.
+    Subscribe,
+    /// Ends the subscription made by the request with the same id
+    Unsubscribe,
+    /// A change pushed to a subscription, carrying the tuples it concerns
+    Event(EventKind),
+    /// Makes the steps in order as one transaction, see `Space::batch`. Replies with
+    /// `Committed`, or if a step fails with its error after undoing the steps before it.
+    Transaction(Vec<Step>),
+    /// The reply to a `Transaction`, with how many of the tuples of the reply each step
+    /// returned
+    Committed(Vec<usize>),
+    Error {
+        code: ErrorCode,
+        reason: String,
+    },
+    NoMatch,
+    TimedOut,
     Ok,
 }
+/// Reason codes carried by `MessageType::Error` replies from a gate
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ErrorCode {
+    /// The frame could not be decoded into a message
+    Malformed,
+    /// The framing was violated, the gate closes the connection after replying
+    Protocol,
+    /// The message was decoded but is not a valid request
+    InvalidRequest,
+    /// The space failed to execute the request
+    SpaceFailure,
+    /// The client is not permitted to make the request on this space
+    Forbidden,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Message {
+    /// Chosen by the client for each request and copied into the reply, so replies can be matched
+    /// to requests when several are outstanding on one connection. Error replies to frames that
+    /// could not be decoded carry 0.
+    #[serde(default)]
+    pub id: u64,
     pub action: MessageType,
     pub tuple: Vec<Tuple>,
     pub template: Template,
 }
 
+impl Message {
+    /// Marks the message as the reply to the request with the given id
+    pub(crate) fn answering(mut self, id: u64) -> Message {
+        self.id = id;
+        self
+    }
+}
+
 pub struct Gate {
     pub handle: Mutex<Sender<()>>,
     repo: Arc<Repository>,
     connections: Mutex<Vec<Sender<()>>>,
     children: Mutex<Vec<JoinHandle<()>>>,
+    /// Numbers the clients that leave picking their id to the gate
+    next_client: AtomicU64,
+    upgrade: Arc<Upgrade>,
     pub join: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl Gate {
-    pub(crate) fn new_gate(addr: String, repo: Arc<Repository>) -> std::io::Result<Arc<Gate>> {
+    pub(crate) fn new_gate(
+        listener: Box<dyn Listener>,
+        repo: Arc<Repository>,
+        upgrade: Upgrade,
+    ) -> Arc<Gate> {
         let (tx, rx) = mpsc::channel();
-        match TcpListener::bind(addr) {
-            Ok(listener) => {
-                let gate = Arc::new(Gate {
-                    handle: Mutex::new(tx),
-                    repo,
-                    connections: Mutex::new(Vec::new()),
-                    children: Mutex::new(Vec::new()),
-                    join: Mutex::new(None),
-                });
-                let clone = Arc::clone(&gate);
-                Gate::start(clone, listener, rx);
-                Ok(gate)
-            }
-            Err(e) => Err(e),
-        }
+        let gate = Arc::new(Gate {
+            handle: Mutex::new(tx),
+            repo,
+            connections: Mutex::new(Vec::new()),
+            children: Mutex::new(Vec::new()),
+            next_client: AtomicU64::new(1),
+            upgrade: Arc::new(upgrade),
+            join: Mutex::new(None),
+        });
+        let clone = Arc::clone(&gate);
+        Gate::start(clone, listener, rx);
+        gate
     }
-    fn start(gate: Arc<Gate>, listener: TcpListener, rx: Receiver<()>) {
+    fn start(gate: Arc<Gate>, listener: Box<dyn Listener>, rx: Receiver<()>) {
         let gateclone = Arc::clone(&gate);
         let handle = thread::spawn(move || {
-            listener
-                .set_nonblocking(true)
-                .expect("Cannot set nonblocking");
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(mut s) => {
+            loop {
+                match listener.accept() {
+                    Ok(s) => {
                         let (tx, rx) = mpsc::channel();
-                        let mut buffer = [0; 1024];
-                        let space_string: String;
-                        match s.read(&mut buffer) {
-                            Ok(n) => {
-                                let inc_string = String::from_utf8_lossy(&buffer[..n]);
-                                space_string = inc_string.to_string();
-                            }
-                            Err(_) => {
-                                s.write_all("f".as_bytes()).ok();
-                                continue;
-                            }
+                        // A client that never greets the gate must not hold up its connection
+                        // thread forever
+                        if s.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).is_err() {
+                            continue;
                         }
-                        let space = match gate.repo.get_space(space_string) {
-                            Some(space) => {
-                                s.write_all("t".as_bytes()).ok();
-                                space
+                        let repo = Arc::clone(&gate.repo);
+                        let upgrade = Arc::clone(&gate.upgrade);
+                        let client = gate.next_client.fetch_add(1, Ordering::Relaxed);
+                        gate.connections.lock().unwrap().push(tx);
+                        thread::spawn(move || {
+                            let stream = match upgrade(s) {
+                                Ok(stream) => stream,
+                                Err(_) => return,
+                            };
+                            if let Some(mut c) = Connection::open(stream, &repo, client, rx) {
+                                c.handle_connection();
                             }
-                            None => {
-                                s.write_all("f".as_bytes()).ok();
-                                continue;
-                            }
-                        };
-                        let mut c = Connection {
-                            signal: rx,
-                            stream: s,
-                            space,
-                        };
-                        let mut cons = gate.connections.lock().unwrap();
-                        cons.push(tx);
-                        thread::spawn(move || c.handle_connection());
+                        });
                     }
-                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        match rx.recv_timeout(Duration::from_millis(10)) {
-                            Ok(_) => {
-                                break;
-                            }
-                            Err(_) => {
-                                thread::sleep(Duration::from_millis(2000));
-                                continue;
-                            }
+                    // Either no client is waiting or the accept failed, in both cases try again
+                    // once the gate has had a chance to notice it is being closed
+                    Err(_) => match rx.recv_timeout(ACCEPT_INTERVAL) {
+                        Ok(_) => {
+                            break;
                         }
-                    }
-                    Err(e) => panic!("encountered IO error: {}", e),
+                        Err(_) => {
+                            continue;
+                        }
+                    },
                 }
             }
             let cons = gate.connections.lock().unwrap();
@@ -129,146 +194,460 @@ impl Gate {
     }
 }
 
-struct Connection {
+/// A client that never greets the gate is dropped after this long
+pub(crate) const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(5000);
+
+pub(crate) struct Connection {
     signal: Receiver<()>,
-    stream: TcpStream,
+    stream: FramedStream<Box<dyn Stream>>,
+    /// Shared with the threads executing blocking requests, which reply when they finish
+    writer: Arc<Mutex<Box<dyn Stream>>>,
+    codec: Codec,
     space: Arc<dyn Space>,
+    /// What the client was granted when it connected
+    permissions: Permissions,
+    subscriptions: Subscriptions,
+    waiting: Waiting,
 }
 
 impl Connection {
+    /// Greets the client and opens a connection to the space it asks for
+    fn open(
+        mut stream: Box<dyn Stream>,
+        repo: &Repository,
+        client: u64,
+        signal: Receiver<()>,
+    ) -> Option<Connection> {
+        let certificate = stream.peer_certificate();
+        let admission = Connection::greet(&mut stream, repo, certificate.as_deref(), client)?;
+        let writer = Arc::new(Mutex::new(stream.try_clone().ok()?));
+        Some(Connection {
+            signal,
+            stream: FramedStream::new(stream, admission.codec),
+            writer,
+            codec: admission.codec,
+            space: admission.space,
+            permissions: admission.permissions,
+            subscriptions: Subscriptions::default(),
+            waiting: Waiting::default(),
+        })
+    }
+
+    /// Reads the greeting of the client and answers it
+    fn greet<S: Read + Write>(
+        stream: &mut S,
+        repo: &Repository,
+        certificate: Option<&[u8]>,
+        client: u64,
+    ) -> Option<Admission> {
+        let mut buffer = Vec::new();
+        let hello = loop {
+            match handshake::parse_hello(&mut buffer) {
+                Ok(Some(hello)) => break hello,
+                Ok(None) => {}
+                Err(e) => {
+                    stream.write_all(&handshake::malformed(&e)).ok();
+                    return None;
+                }
+            }
+            let mut chunk = [0; 1024];
+            match stream.read(&mut chunk) {
+                Ok(0) | Err(_) => return None,
+                Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+            }
+        };
+        let (reply, admission) = handshake::admit(repo, hello, certificate, client);
+        stream.write_all(&reply).ok()?;
+        admission
+    }
+
     fn handle_connection(&mut self) {
-        let mut buffer = [0; 1024];
-        self.stream
+        if self
+            .stream
+            .get_ref()
             .set_read_timeout(Some(Duration::from_millis(5000)))
-            .expect("could not set timeout");
+            .is_err()
+        {
+            return;
+        }
         loop {
-            match self.signal.recv_timeout(Duration::from_millis(10)) {
-                Ok(_) => break,
-                Err(_) => {}
+            if self.signal.try_recv().is_ok() {
+                break;
             }
-            match self.stream.read(&mut buffer[..]) {
-                Ok(n) => {
-                    let inc_string = String::from_utf8_lossy(&buffer[..n]);
-                    if inc_string.len() < 1 {
-                        break;
+            let response = match self.stream.recv() {
+                Ok(message) if !self.permissions.allow(&message.action) => {
+                    Connection::forbidden(message)
+                }
+                Ok(message) if message.action == MessageType::Subscribe => {
+                    let writer = Arc::clone(&self.writer);
+                    let codec = self.codec;
+                    self.subscriptions
+                        .subscribe(&*self.space, message, move |m| {
+                            Connection::send(&writer, codec, &m).is_ok()
+                        })
+                }
+                Ok(message) if message.action == MessageType::Unsubscribe => {
+                    self.subscriptions.unsubscribe(message)
+                }
+                Ok(message) if Connection::blocks(&message.action) => {
+                    let id = message.id;
+                    match self.waiting.start() {
+                        // Requests that wait for a tuple must not hold up the rest of the connection
+                        Some(request) => {
+                            let space = Arc::clone(&self.space);
+                            let writer = Arc::clone(&self.writer);
+                            let codec = self.codec;
+                            thread::spawn(move || {
+                                request.execute(&*space, message, |m| {
+                                    Connection::send(&writer, codec, m).is_ok()
+                                })
+                            });
+                            continue;
+                        }
+                        None => Connection::too_many_waiting(id),
                     }
-                    let response = self.handle_message(inc_string.to_string());
-                    let r_json = serde_json::to_string(&response).expect("Serialization error");
-                    self.stream.flush().expect("Could not flush stream");
-                    self.stream
-                        .write_all(r_json.as_bytes())
-                        .expect("Connection error");
-                    self.stream.flush().expect("Could not flush stream");
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
-                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Ok(message) => self.handle_message(message),
+                Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+                Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                // The frame was read in full, so the connection can carry on after replying
+                Err(Error::Serialization(e)) => {
+                    Connection::error_reply(ErrorCode::Malformed, e.to_string())
+                }
+                // The frame boundaries are lost, so the connection is closed after replying
+                Err(Error::Protocol(reason)) => {
+                    let _ = Connection::send(
+                        &self.writer,
+                        self.codec,
+                        &Connection::error_reply(ErrorCode::Protocol, reason),
+                    );
+                    break;
+                }
                 Err(_e) => break,
+            };
+            if Connection::send(&self.writer, self.codec, &response).is_err() {
+                break;
             }
         }
+        // Threads still executing requests hold the stream, so it is not closed by dropping it
+        let _ = self.writer.lock().unwrap().shutdown();
     }
 
-    fn handle_message(&mut self, inc: String) -> Message {
-        let message = match serde_json::from_str::<Message>(&inc) {
-            Ok(m) => m,
-            Err(e) => panic!(
-                "Malformed message, panicking with message: {}, Message was: ({})",
-                e, inc
-            ),
-        };
-        match message.action {
-            MessageType::Get => self.handle_get(message),
-            MessageType::Getp => self.handle_getp(message),
-            MessageType::Getall => self.handle_getall(message),
-            MessageType::Query => self.handle_query(message),
-            MessageType::Queryp => self.handle_queryp(message),
-            MessageType::Queryall => self.handle_queryall(message),
-            MessageType::Put => self.handle_put(message),
-            m => self.handle_echo(m),
-        }
+    /// Requests that wait for a matching tuple
+    pub(crate) fn blocks(action: &MessageType) -> bool {
+        matches!(
+            action,
+            MessageType::Get
+                | MessageType::Query
+                | MessageType::GetTimeout(_)
+                | MessageType::QueryTimeout(_)
+        )
     }
 
-    fn handle_get(&mut self, message: Message) -> Message {
-        let mut tuple = Vec::new();
-        tuple.push(self.space.get(message.template).unwrap());
-        Message {
-            action: MessageType::Ok,
-            tuple,
-            template: new_template!(),
+    /// How a request that blocks waits: whether it takes the tuple, and until when
+    pub(crate) fn waits(action: &MessageType) -> Option<(bool, Option<Instant>)> {
+        match action {
+            MessageType::Get => Some((true, None)),
+            MessageType::Query => Some((false, None)),
+            // A timeout too long to be represented never runs out
+            MessageType::GetTimeout(timeout) => Some((true, Instant::now().checked_add(*timeout))),
+            MessageType::QueryTimeout(timeout) => {
+                Some((false, Instant::now().checked_add(*timeout)))
+            }
+            _ => None,
         }
     }
-    fn handle_getp(&mut self, message: Message) -> Message {
-        let mut action = MessageType::Ok;
-        let mut tuple = Vec::new();
-        match self.space.getp(message.template) {
-            Ok(t) => tuple.push(t),
-            Err(_) => {
-                action = MessageType::Error;
+
+    fn send(writer: &Mutex<Box<dyn Stream>>, codec: Codec, m: &Message) -> Result<()> {
+        let mut stream = writer.lock().unwrap();
+        write_message(&mut *stream, codec, m)
+    }
+
+    fn handle_message(&mut self, message: Message) -> Message {
+        Connection::dispatch(&*self.space, message)
+    }
+
+    /// Executes the request on the space and builds the reply
+    pub(crate) fn dispatch(space: &dyn Space, message: Message) -> Message {
+        let id = message.id;
+        Connection::execute(space, message).answering(id)
+    }
+
+    fn execute(space: &dyn Space, message: Message) -> Message {
+        let result = match message.action {
+            MessageType::Get => space.get(message.template).map(|t| vec![t]),
+            MessageType::Getp => space.getp(message.template).map(|t| vec![t]),
+            MessageType::Getall => space.getall(message.template),
+            MessageType::Query => space.query(message.template).map(|t| vec![t]),
+            MessageType::Queryp => space.queryp(message.template).map(|t| vec![t]),
+            MessageType::Queryall => space.queryall(message.template),
+            MessageType::Put => match Connection::put_tuple(message.tuple) {
+                Ok(tuple) => space.put_tuple(tuple).map(|_| Vec::new()),
+                Err(reply) => return reply,
+            },
+            MessageType::GetTimeout(timeout) => space
+                .get_timeout(message.template, timeout)
+                .map(|t| vec![t]),
+            MessageType::QueryTimeout(timeout) => space
+                .query_timeout(message.template, timeout)
+                .map(|t| vec![t]),
+            MessageType::PutWithTtl(ttl) => match Connection::put_tuple(message.tuple) {
+                Ok(tuple) => space.put_with_ttl(tuple, ttl).map(|_| Vec::new()),
+                Err(reply) => return reply,
+            },
+            MessageType::Renew(ttl) => space.renew(message.template, ttl),
+            MessageType::Transaction(steps) => {
+                return Connection::transaction(space, steps, message.tuple, message.template)
             }
+            m => return Connection::not_a_request(m),
         };
-        Message {
-            action,
-            tuple,
-            template: new_template!(),
-        }
+        Connection::reply(result)
     }
-    fn handle_query(&mut self, message: Message) -> Message {
-        let mut tuple = Vec::new();
-        tuple.push(self.space.query(message.template).unwrap());
-        Message {
-            action: MessageType::Ok,
-            tuple,
-            template: new_template!(),
+
+    /// Takes the tuple out of a put request, which must carry exactly one
+    pub(crate) fn put_tuple(mut tuples: Vec<Tuple>) -> std::result::Result<Tuple, Message> {
+        if tuples.len() != 1 {
+            return Err(Connection::error_reply(
+                ErrorCode::InvalidRequest,
+                format!("put expects one tuple but got {}", tuples.len()),
+            ));
         }
+        Ok(tuples.remove(0))
+    }
+
+    /// Refuses a request that would wait while as many requests as allowed are waiting already
+    pub(crate) fn too_many_waiting(id: u64) -> Message {
+        Connection::error_reply(
+            ErrorCode::InvalidRequest,
+            format!("at most {} requests can wait at once", MAX_WAITING),
+        )
+        .answering(id)
+    }
+
+    /// Refuses a request the client has no permission for
+    pub(crate) fn forbidden(message: Message) -> Message {
+        Connection::error_reply(
+            ErrorCode::Forbidden,
+            format!("{:?} is not permitted on this space", message.action),
+        )
+        .answering(message.id)
+    }
+
+    pub(crate) fn not_a_request(action: MessageType) -> Message {
+        Connection::error_reply(
+            ErrorCode::InvalidRequest,
+            format!("{:?} is not a request", action),
+        )
     }
-    fn handle_queryp(&mut self, message: Message) -> Message {
-        let mut action = MessageType::Ok;
-        let mut tuple = Vec::new();
-        match self.space.queryp(message.template) {
-            Ok(t) => tuple.push(t),
-            Err(_) => {
-                action = MessageType::Error;
+
+    /// Turns the result of a space operation into the reply sent back to the client
+    pub(crate) fn reply(result: Result<Vec<Tuple>>) -> Message {
+        let action = match result {
+            Ok(tuple) => {
+                return Message {
+                    id: 0,
+                    action: MessageType::Ok,
+                    tuple,
+                    template: new_template!(),
+                }
             }
+            Err(Error::NoMatch) => MessageType::NoMatch,
+            Err(Error::TimedOut) => MessageType::TimedOut,
+            Err(e) => return Connection::error_reply(ErrorCode::SpaceFailure, e.to_string()),
         };
         Message {
+            id: 0,
             action,
-            tuple,
+            tuple: Vec::new(),
             template: new_template!(),
         }
     }
-    fn handle_getall(&mut self, message: Message) -> Message {
-        let tuple = self.space.getall(message.template).unwrap();
-        Message {
-            action: MessageType::Ok,
-            tuple,
-            template: new_template!(),
+
+    /// Makes the steps of a transaction request on the space and builds the reply
+    pub(crate) fn transaction(
+        space: &dyn Space,
+        steps: Vec<Step>,
+        tuples: Vec<Tuple>,
+        template: Template,
+    ) -> Message {
+        let operations = match batch::decode(steps, tuples, template) {
+            Ok(operations) => operations,
+            Err(reason) => return Connection::error_reply(ErrorCode::InvalidRequest, reason),
+        };
+        match space.batch(operations) {
+            Ok(results) => Message {
+                id: 0,
+                action: MessageType::Committed(results.iter().map(Vec::len).collect()),
+                tuple: results.into_iter().flatten().collect(),
+                template: new_template!(),
+            },
+            Err(e) => Connection::reply(Err(e)),
         }
     }
 
-    fn handle_echo(&self, action: MessageType) -> Message {
+    pub(crate) fn error_reply(code: ErrorCode, reason: String) -> Message {
         Message {
-            action,
+            id: 0,
+            action: MessageType::Error { code, reason },
             tuple: Vec::new(),
             template: new_template!(),
         }
     }
+}
 
-    fn handle_queryall(&mut self, message: Message) -> Message {
-        let tuple = self.space.queryall(message.template).unwrap();
-        Message {
-            action: MessageType::Ok,
-            tuple,
-            template: new_template!(),
+/// Counts the requests of a connection waiting for a tuple, and cancels them when the
+/// connection ends
+#[derive(Default)]
+struct Waiting {
+    count: Arc<AtomicUsize>,
+    /// Set once the connection ended
+    ended: Arc<AtomicBool>,
+}
+
+impl Waiting {
+    /// Counts a new request, unless as many as allowed are waiting already
+    fn start(&self) -> Option<WaitingRequest> {
+        self.count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < MAX_WAITING).then_some(n + 1)
+            })
+            .ok()?;
+        Some(WaitingRequest {
+            count: Arc::clone(&self.count),
+            ended: Arc::clone(&self.ended),
+        })
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.ended.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A request waiting for a tuple, no longer counted once dropped
+struct WaitingRequest {
+    count: Arc<AtomicUsize>,
+    ended: Arc<AtomicBool>,
+}
+
+impl WaitingRequest {
+    /// Executes the request and hands the reply to `send`. A tuple taken for a reply that could
+    /// not be sent goes back in its old place.
+    fn execute<F>(self, space: &dyn Space, message: Message, send: F)
+    where
+        F: FnOnce(&Message) -> bool,
+    {
+        let local = match space.as_local() {
+            Some(local) => local,
+            None => {
+                send(&Connection::dispatch(space, message));
+                return;
+            }
+        };
+        let (destroy, deadline) = match Connection::waits(&message.action) {
+            Some(waits) => waits,
+            None => {
+                send(&Connection::not_a_request(message.action).answering(message.id));
+                return;
+            }
+        };
+        let result = local.wait_for_connection(&message.template, destroy, deadline, &self.ended);
+        let taken = match &result {
+            Ok((id, tuple)) if destroy => Some((*id, tuple.clone())),
+            _ => None,
+        };
+        let reply = Connection::reply(result.map(|(_, tuple)| vec![tuple])).answering(message.id);
+        if !send(&reply) {
+            if let Some((id, tuple)) = taken {
+                local.restore(id, tuple);
+            }
         }
     }
+}
+
+impl Drop for WaitingRequest {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
 
-    fn handle_put(&mut self, message: Message) -> Message {
-        self.space
-            .put(message.tuple.get(0).unwrap().clone())
-            .unwrap();
-        Message {
-            action: MessageType::Ok,
-            tuple: Vec::new(),
-            template: new_template!(),
+/// The subscriptions of a connection by the id of the request that made them, which all end
+/// when the connection does
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    /// Cleared to stop the thread forwarding the events of the subscription
+    active: HashMap<u64, Arc<AtomicBool>>,
+}
+
+impl Subscriptions {
+    /// Subscribes to the space and starts a thread handing the events to `send` until the
+    /// client unsubscribes or sending fails. A subscription whose events pile up because they
+    /// are not sent fast enough is dropped, which the client is told of with an error carrying
+    /// the id of the subscription. Returns the reply to the request, which only local spaces
+    /// accept.
+    pub(crate) fn subscribe<F>(
+        &mut self,
+        space: &dyn Space,
+        message: Message,
+        mut send: F,
+    ) -> Message
+    where
+        F: FnMut(Message) -> bool + Send + 'static,
+    {
+        let id = message.id;
+        let local = match space.as_local() {
+            Some(local) => local,
+            None => {
+                return Connection::error_reply(
+                    ErrorCode::InvalidRequest,
+                    String::from("only local spaces can be subscribed to"),
+                )
+                .answering(id)
+            }
+        };
+        let events = local.subscribe_bounded(message.template, SUBSCRIPTION_BACKLOG);
+        let active = Arc::new(AtomicBool::new(true));
+        self.active.insert(id, Arc::clone(&active));
+        thread::spawn(move || {
+            while active.load(Ordering::Relaxed) {
+                let event = match events.recv_timeout(FORWARD_INTERVAL) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        if active.load(Ordering::Relaxed) {
+                            let reason = String::from("the subscription was dropped");
+                            send(
+                                Connection::error_reply(ErrorCode::SpaceFailure, reason)
+                                    .answering(id),
+                            );
+                        }
+                        return;
+                    }
+                };
+                if !active.load(Ordering::Relaxed) || !send(event.into_message(id)) {
+                    return;
+                }
+            }
+        });
+        Connection::reply(Ok(Vec::new())).answering(id)
+    }
+
+    /// Ends the subscription made by the request with the id of the message. Subscriptions
+    /// that ended already are acknowledged as well.
+    pub(crate) fn unsubscribe(&mut self, message: Message) -> Message {
+        if let Some(active) = self.active.remove(&message.id) {
+            active.store(false, Ordering::Relaxed);
+        }
+        Connection::reply(Ok(Vec::new())).answering(message.id)
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        for active in self.active.values() {
+            active.store(false, Ordering::Relaxed);
         }
     }
 }
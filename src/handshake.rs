@@ -0,0 +1,250 @@
+use std::{fmt::Display, io::Read, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    frame::{encode_frame, take_frame},
+    Codec, Error, Permissions, Repository, Result, Space,
+};
+
+/// The version of the protocol spoken between remote spaces and gates
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The first frame sent by a remote space, asking the gate for a connection to a space.
+///
+/// Hellos are always json encoded, the codec they name is used for every frame after the
+/// `Welcome`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Hello {
+    /// Must be the `PROTOCOL_VERSION` of the gate
+    pub version: u32,
+    /// The name of the codec to use for messages
+    pub codec: String,
+    /// Identifies the client to the gate. Left empty, the gate picks one
+    #[serde(default)]
+    pub client_id: String,
+    /// The name of the space in the repository of the gate
+    pub space: String,
+    #[serde(default)]
+    pub credentials: Option<Credentials>,
+}
+
+/// Proof of identity sent with a `Hello`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Credentials {
+    /// A secret shared between the gate and its clients
+    Token(String),
+    User {
+        name: String,
+        password: String,
+    },
+}
+
+/// The reply of a gate to a `Hello`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Welcome {
+    /// The connection is open, messages can be sent with the codec of the hello
+    Accepted { version: u32, client_id: String },
+    /// The gate closes the connection after sending this
+    Rejected { reason: Rejection, message: String },
+}
+
+/// Why a gate refused a connection
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// No space with the requested name exists in the repository
+    UnknownSpace,
+    /// The client speaks another version of the protocol
+    VersionMismatch,
+    /// The gate does not know the requested codec
+    UnsupportedCodec,
+    /// The credentials were missing or wrong
+    Unauthorized,
+    /// The hello could not be decoded
+    Malformed,
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::UnknownSpace => write!(f, "unknown space"),
+            Rejection::VersionMismatch => write!(f, "version mismatch"),
+            Rejection::UnsupportedCodec => write!(f, "unsupported codec"),
+            Rejection::Unauthorized => write!(f, "unauthorized"),
+            Rejection::Malformed => write!(f, "malformed hello"),
+        }
+    }
+}
+
+/// Settings for connecting a remote space to a gate
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// let options = ConnectOptions {
+///     codec: Codec::MessagePack,
+///     client_id: Some(String::from("worker-1")),
+///     ..Default::default()
+/// };
+/// assert!(options.credentials.is_none());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ConnectOptions {
+    pub codec: Codec,
+    /// Left out, the gate picks an id for the connection
+    pub client_id: Option<String>,
+    pub credentials: Option<Credentials>,
+    /// Gives up connecting to the gate, and waiting for its welcome, after this long
+    pub timeout: Option<Duration>,
+    /// Connects over TLS, checking the certificate of the gate against the trusted CAs
+    #[cfg(feature = "tls")]
+    pub tls: Option<crate::TlsConnector>,
+}
+
+impl ConnectOptions {
+    /// The hello frame asking for the space
+    pub(crate) fn hello(&self, space: String) -> Result<Vec<u8>> {
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            codec: self.codec.name().to_string(),
+            client_id: self.client_id.clone().unwrap_or_default(),
+            space,
+            credentials: self.credentials.clone(),
+        };
+        encode_frame(&serde_json::to_vec(&hello)?)
+    }
+}
+
+/// Reads a whole frame, for the client side of the handshake where nothing follows the welcome
+pub(crate) fn read_frame<R: Read>(stream: &mut R) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    loop {
+        if let Some(payload) = take_frame(&mut buffer)? {
+            return Ok(payload);
+        }
+        let mut chunk = [0; 256];
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Err(Error::Closed);
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// The async counterpart of `read_frame`
+#[cfg(feature = "tokio")]
+pub(crate) async fn read_frame_async<R: tokio::io::AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+    let mut buffer = Vec::new();
+    loop {
+        if let Some(payload) = take_frame(&mut buffer)? {
+            return Ok(payload);
+        }
+        let mut chunk = [0; 256];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(Error::Closed);
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// Checks the welcome of the gate and returns the id of the connection
+pub(crate) fn check_welcome(payload: &[u8]) -> Result<String> {
+    match serde_json::from_slice::<Welcome>(payload)? {
+        Welcome::Accepted { client_id, .. } => Ok(client_id),
+        Welcome::Rejected {
+            reason: Rejection::UnknownSpace,
+            ..
+        } => Err(Error::SpaceNotFound),
+        Welcome::Rejected { reason, message } => Err(Error::Rejected(reason, message)),
+    }
+}
+
+/// Takes the hello of a client from the bytes received so far, if they hold all of it
+pub(crate) fn parse_hello(buffer: &mut Vec<u8>) -> Result<Option<Hello>> {
+    match take_frame(buffer)? {
+        Some(payload) => Ok(Some(serde_json::from_slice(&payload)?)),
+        None => Ok(None),
+    }
+}
+
+/// An accepted connection
+pub(crate) struct Admission {
+    pub(crate) space: Arc<dyn Space>,
+    pub(crate) codec: Codec,
+    pub(crate) permissions: Permissions,
+}
+
+/// Decides whether to open the connection, and returns the bytes to answer the client with.
+///
+/// The certificate is the one a TLS client authenticated with, if any.
+pub(crate) fn admit(
+    repo: &Repository,
+    hello: Hello,
+    certificate: Option<&[u8]>,
+    next_client: u64,
+) -> (Vec<u8>, Option<Admission>) {
+    let rejected = |reason, message| (welcome(&Welcome::Rejected { reason, message }), None);
+    if hello.version != PROTOCOL_VERSION {
+        return rejected(
+            Rejection::VersionMismatch,
+            format!(
+                "the gate speaks version {} but the client {}",
+                PROTOCOL_VERSION, hello.version
+            ),
+        );
+    }
+    let codec = match Codec::from_name(&hello.codec) {
+        Some(codec) => codec,
+        None => {
+            return rejected(
+                Rejection::UnsupportedCodec,
+                format!("the gate does not support the {} codec", hello.codec),
+            )
+        }
+    };
+    // Clients learn which spaces exist only once they are authenticated
+    let permissions = match repo.admit(hello.credentials.as_ref(), certificate, &hello.space) {
+        Ok(permissions) => permissions,
+        Err(message) => return rejected(Rejection::Unauthorized, message),
+    };
+    let space = match repo.get_space(hello.space.clone()) {
+        Some(space) => space,
+        None => {
+            return rejected(
+                Rejection::UnknownSpace,
+                format!("no space named {}", hello.space),
+            )
+        }
+    };
+    let client_id = match hello.client_id.is_empty() {
+        true => format!("client-{}", next_client),
+        false => hello.client_id,
+    };
+    let accepted = Welcome::Accepted {
+        version: PROTOCOL_VERSION,
+        client_id,
+    };
+    let admission = Admission {
+        space,
+        codec,
+        permissions,
+    };
+    (welcome(&accepted), Some(admission))
+}
+
+/// Rejects a client that did not greet the gate properly
+pub(crate) fn malformed(e: &Error) -> Vec<u8> {
+    welcome(&Welcome::Rejected {
+        reason: Rejection::Malformed,
+        message: format!("could not read the hello: {}", e),
+    })
+}
+
+fn welcome(welcome: &Welcome) -> Vec<u8> {
+    // A welcome is far smaller than the frame limit
+    encode_frame(&serde_json::to_vec(welcome).unwrap()).unwrap()
+}
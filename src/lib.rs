@@ -1,19 +1,85 @@
-mod drain_filter;
+#[cfg(feature = "tokio")]
+mod async_gate;
+#[cfg(feature = "tokio")]
+mod async_space;
+mod auth;
+mod batch;
+mod client;
+mod codec;
+mod error;
+mod event;
+mod frame;
 mod gate;
+mod handshake;
 mod macros;
+mod memory;
 mod query;
 mod repository;
 mod space;
+mod store;
+#[cfg(feature = "tls")]
+mod tls;
+mod transport;
 mod tuple;
+mod uri;
+mod wal;
 
+pub use crate::auth::Authenticator;
+pub use crate::auth::Permissions;
+pub use crate::auth::EVERYONE;
+pub use crate::batch::Operation;
+pub use crate::batch::Shape;
+pub use crate::batch::Step;
+pub use crate::codec::Codec;
+pub use crate::error::Error;
+pub use crate::error::FieldError;
+pub use crate::error::Result;
+pub use crate::event::EventKind;
+pub use crate::event::SpaceEvent;
+pub use crate::frame::MAX_FRAME_SIZE;
+pub use crate::gate::ErrorCode;
 pub use crate::gate::Message;
 pub use crate::gate::MessageType;
+pub use crate::handshake::ConnectOptions;
+pub use crate::handshake::Credentials;
+pub use crate::handshake::Hello;
+pub use crate::handshake::Rejection;
+pub use crate::handshake::Welcome;
+pub use crate::handshake::PROTOCOL_VERSION;
+pub use crate::query::any;
+pub use crate::query::one_of;
+pub use crate::query::regex;
+pub use crate::query::starts_with;
+pub use crate::query::Arity;
 pub use crate::query::FieldType;
+pub use crate::query::OrderedFieldType;
+pub use crate::query::Pattern;
+pub use crate::query::RangeFieldType;
 pub use crate::query::Template;
 pub use crate::query::TemplateType;
 pub use crate::repository::Repository;
 pub use crate::space::LocalSpace;
 pub use crate::space::RemoteSpace;
 pub use crate::space::Space;
+pub use crate::space::SpaceType;
+pub use crate::space::Transaction;
+#[cfg(feature = "tls")]
+pub use crate::tls::TlsAcceptor;
+#[cfg(feature = "tls")]
+pub use crate::tls::TlsConnector;
+pub use crate::tuple::FromTuple;
+pub use crate::tuple::IntoTuple;
 pub use crate::tuple::Tuple;
 pub use crate::tuple::TupleField;
+pub use crate::uri::Endpoint;
+pub use crate::uri::SpaceUri;
+pub use crate::uri::UriError;
+
+/// Async spaces for use with tokio, enabled by the `tokio` feature.
+///
+/// These live in their own module as `AsyncSpace` shares its method names with `Space`.
+#[cfg(feature = "tokio")]
+pub mod asynchronous {
+    pub use crate::async_space::AsyncRemoteSpace;
+    pub use crate::async_space::AsyncSpace;
+}
@@ -28,6 +28,35 @@ macro_rules! new_template {
 #[macro_export]
 macro_rules! implement_tuplefield_for {
     (  $x:ty  ) => {
+        $crate::implement_tuplefield_for!(@impl $x;);
+    };
+    (  $x:ty, ordered  ) => {
+        $crate::implement_tuplefield_for!(@impl $x;
+            fn compare(&self, element: &dyn TupleField) -> Option<std::cmp::Ordering> {
+                match (*element).as_any().downcast_ref::<Self>() {
+                    Some(e) => e.partial_cmp(self),
+                    None => None,
+                }
+            }
+        );
+    };
+    (  $x:ty, ordered, hashed  ) => {
+        $crate::implement_tuplefield_for!(@impl $x;
+            fn compare(&self, element: &dyn TupleField) -> Option<std::cmp::Ordering> {
+                match (*element).as_any().downcast_ref::<Self>() {
+                    Some(e) => e.partial_cmp(self),
+                    None => None,
+                }
+            }
+            fn index_key(&self) -> Option<u64> {
+                use std::hash::{Hash, Hasher};
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                self.hash(&mut hasher);
+                Some(hasher.finish())
+            }
+        );
+    };
+    ( @impl $x:ty; $( $extra:tt )* ) => {
         #[typetag::serde]
         impl TupleField for $x {
             fn as_any(&self) -> &dyn Any {
@@ -46,8 +75,10 @@ macro_rules! implement_tuplefield_for {
                         Some(_) => true,
                         None => false,
                     },
+                    _ => false,
                 }
             }
+            $( $extra )*
         }
     };
 }
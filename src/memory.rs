@@ -0,0 +1,173 @@
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    sync::{
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::transport::{Listener, Stream};
+
+/// Bytes written by one end of an in-memory connection and not yet read by the other
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    readable: Condvar,
+}
+
+#[derive(Default)]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Pipe {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.readable.notify_all();
+    }
+}
+
+/// One end of a connection within the process, behaving like a socket: reads block until the
+/// peer writes, and once either end shuts down the peer reads what is left and then the end
+/// of the stream.
+pub(crate) struct MemoryStream {
+    end: Arc<End>,
+}
+
+/// One end of a connection, shared by every handle to it and closed when the last one is dropped
+struct End {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    /// Like the timeout of a socket
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl End {
+    fn close(&self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+impl Drop for End {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl MemoryStream {
+    /// Both ends of a new connection
+    pub(crate) fn pair() -> (MemoryStream, MemoryStream) {
+        let (a, b) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+        let end = |incoming, outgoing| MemoryStream {
+            end: Arc::new(End {
+                incoming,
+                outgoing,
+                read_timeout: Mutex::new(None),
+            }),
+        };
+        (end(Arc::clone(&a), Arc::clone(&b)), end(b, a))
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let end = &*self.end;
+        let deadline = end
+            .read_timeout
+            .lock()
+            .unwrap()
+            .and_then(|t| Instant::now().checked_add(t));
+        let mut state = end.incoming.state.lock().unwrap();
+        while state.bytes.is_empty() && !state.closed {
+            state = match deadline {
+                None => end.incoming.readable.wait(state).unwrap(),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Err(ErrorKind::WouldBlock.into());
+                    }
+                    end.incoming.readable.wait_timeout(state, left).unwrap().0
+                }
+            };
+        }
+        let n = buf.len().min(state.bytes.len());
+        for (slot, byte) in buf.iter_mut().zip(state.bytes.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.end.outgoing.state.lock().unwrap();
+        if state.closed {
+            return Err(ErrorKind::BrokenPipe.into());
+        }
+        state.bytes.extend(buf);
+        self.end.outgoing.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for MemoryStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(MemoryStream {
+            end: Arc::clone(&self.end),
+        }))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self.end.read_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.end.close();
+        Ok(())
+    }
+}
+
+/// Hands the gate end of every connection made with `connect` to a gate
+pub(crate) struct MemoryListener {
+    connections: Receiver<Box<dyn Stream>>,
+}
+
+impl MemoryListener {
+    /// The listener, and the sender clients connect through
+    pub(crate) fn new() -> (MemoryListener, Sender<Box<dyn Stream>>) {
+        let (tx, rx) = mpsc::channel();
+        (MemoryListener { connections: rx }, tx)
+    }
+}
+
+impl Listener for MemoryListener {
+    fn accept(&self) -> io::Result<Box<dyn Stream>> {
+        match self.connections.try_recv() {
+            Ok(stream) => Ok(stream),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {
+                Err(ErrorKind::WouldBlock.into())
+            }
+        }
+    }
+}
+
+/// Opens a connection to the gate listening on the other end of the sender
+pub(crate) fn connect(gate: &Sender<Box<dyn Stream>>) -> io::Result<Box<dyn Stream>> {
+    let (client, server) = MemoryStream::pair();
+    match gate.send(Box::new(server)) {
+        Ok(()) => Ok(Box::new(client)),
+        Err(_) => Err(io::Error::new(
+            ErrorKind::ConnectionRefused,
+            "the in-memory gate is closed",
+        )),
+    }
+}
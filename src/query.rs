@@ -1,37 +1,154 @@
-use serde::{Deserialize, Serialize};
-use typetag::serde;
+use std::{
+    any::Any,
+    cmp::Ordering,
+    ops::{Range, RangeInclusive},
+};
 
-use crate::{Tuple, TupleField};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Error, Tuple, TupleField};
 
 #[typetag::serde(tag = "Template")]
 pub trait TemplateField {
+    #[allow(clippy::borrowed_box)]
     fn query(&self, element: &Box<dyn TupleField>, matching: &TemplateType) -> bool;
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub enum TemplateType {
+    /// Matches fields of the same type and value
     Actual,
+    /// Matches fields of the same type
     Formal,
+    /// Matches any field regardless of type and value
+    Any,
+    /// Matches fields of the same type greater than the template value
+    Greater,
+    /// Matches fields of the same type greater than or equal to the template value
+    GreaterEqual,
+    /// Matches fields of the same type less than the template value
+    Less,
+    /// Matches fields of the same type less than or equal to the template value
+    LessEqual,
+    /// Matches fields of the same type from the template value up to `end`
+    Range {
+        end: Box<dyn TupleField>,
+        inclusive: bool,
+    },
+    /// Matches fields equal to one of the listed values
+    OneOf(Vec<Box<dyn TupleField>>),
+    /// Matches strings starting with the template value
+    StartsWith,
+    /// Matches strings matching the regular expression
+    Regex(Pattern),
+}
+
+/// A compiled regular expression, sent over the wire as its source string
+#[derive(Clone, Debug)]
+pub struct Pattern(Regex);
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern)
+            .map(Pattern)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Placeholder value for template fields whose matching does not depend on a value
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Wildcard;
+
+#[typetag::serde(name = "rspaces::Wildcard")]
+impl TupleField for Wildcard {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn box_clone(&self) -> Box<dyn TupleField> {
+        Box::new(Wildcard)
+    }
+    fn query(&self, _element: &Box<dyn TupleField>, _matching: &TemplateType) -> bool {
+        false
+    }
+}
+
+/// How the number of fields in a template is compared to the number of fields in a tuple
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Arity {
+    /// The tuple must have exactly as many fields as the template
+    #[default]
+    Exact,
+    /// The template only has to match the leading fields of the tuple, any remaining fields are ignored
+    Prefix,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Template {
     pub fields: Vec<(Box<dyn TupleField>, TemplateType)>,
+    #[serde(default)]
+    pub arity: Arity,
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Template::new()
+    }
 }
 
 impl Template {
     pub fn new() -> Template {
-        Template { fields: Vec::new() }
+        Template {
+            fields: Vec::new(),
+            arity: Arity::Exact,
+        }
     }
     pub fn new_fields(fields: Vec<(Box<dyn TupleField>, TemplateType)>) -> Template {
-        Template { fields }
+        Template {
+            fields,
+            arity: Arity::Exact,
+        }
+    }
+
+    /// Turns the template into a prefix template, matching any tuple whose leading fields match
+    /// the template regardless of how many fields follow.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # let space = LocalSpace::new_sequential();
+    /// space.put(new_tuple!(5, 'a', true));
+    ///
+    /// //Templates match on the number of fields by default
+    /// assert!(space.queryp(new_template!(5.actual())).is_err());
+    ///
+    /// //Prefix templates ignore the fields after the template
+    /// assert!(space.queryp(new_template!(5.actual()).prefix()).is_ok());
+    /// ```
+    pub fn prefix(mut self) -> Template {
+        self.arity = Arity::Prefix;
+        self
     }
 }
 
 impl Template {
     pub fn query(&self, tuple: &Tuple) -> bool {
+        let arity_matches = match self.arity {
+            Arity::Exact => self.fields.len() == tuple.fields.len(),
+            Arity::Prefix => self.fields.len() <= tuple.fields.len(),
+        };
+        if !arity_matches {
+            return false;
+        }
         for (q, e) in self.fields.iter().zip(tuple.fields.iter()) {
-            if !q.0.query(e, &q.1) {
+            if !field_matches(&*q.0, &q.1, e) {
                 return false;
             }
         }
@@ -39,6 +156,54 @@ impl Template {
     }
 }
 
+#[allow(clippy::borrowed_box)]
+fn field_matches(
+    value: &dyn TupleField,
+    matching: &TemplateType,
+    element: &Box<dyn TupleField>,
+) -> bool {
+    match matching {
+        TemplateType::Actual | TemplateType::Formal => value.query(element, matching),
+        TemplateType::Any => true,
+        TemplateType::Greater => value.compare(&**element) == Some(Ordering::Greater),
+        TemplateType::GreaterEqual => matches!(
+            value.compare(&**element),
+            Some(Ordering::Greater | Ordering::Equal)
+        ),
+        TemplateType::Less => value.compare(&**element) == Some(Ordering::Less),
+        TemplateType::LessEqual => matches!(
+            value.compare(&**element),
+            Some(Ordering::Less | Ordering::Equal)
+        ),
+        TemplateType::Range { end, inclusive } => {
+            let above_start = matches!(
+                value.compare(&**element),
+                Some(Ordering::Greater | Ordering::Equal)
+            );
+            let below_end = match end.compare(&**element) {
+                Some(Ordering::Less) => true,
+                Some(Ordering::Equal) => *inclusive,
+                _ => false,
+            };
+            above_start && below_end
+        }
+        TemplateType::OneOf(values) => values
+            .iter()
+            .any(|v| v.query(element, &TemplateType::Actual)),
+        TemplateType::StartsWith => match (
+            value.as_any().downcast_ref::<String>(),
+            element.as_any().downcast_ref::<String>(),
+        ) {
+            (Some(prefix), Some(e)) => e.starts_with(prefix.as_str()),
+            _ => false,
+        },
+        TemplateType::Regex(pattern) => match element.as_any().downcast_ref::<String>() {
+            Some(e) => pattern.0.is_match(e),
+            None => false,
+        },
+    }
+}
+
 pub trait FieldType: Sized + PartialEq + TupleField + 'static {
     fn formal(self) -> (Box<dyn TupleField>, TemplateType) {
         (Box::new(self), TemplateType::Formal)
@@ -48,3 +213,144 @@ pub trait FieldType: Sized + PartialEq + TupleField + 'static {
     }
 }
 impl<T: PartialEq + TupleField + 'static> FieldType for T {}
+
+/// Comparison template fields for types with an ordering
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// # let space = LocalSpace::new_sequential();
+/// space.put(new_tuple!(String::from("job"), 2));
+/// space.put(new_tuple!(String::from("job"), 5));
+///
+/// //Find a job with a priority greater than 3
+/// let template = new_template!(String::from("job").actual(), 3.greater());
+/// let tuple = space.getp(template).unwrap();
+/// assert_eq!(5, *tuple.get_field::<i32>(1));
+///
+/// //The comparisons of PartialOrd are still available
+/// assert!(5.gt(&3));
+/// ```
+pub trait OrderedFieldType: FieldType + PartialOrd {
+    fn greater(self) -> (Box<dyn TupleField>, TemplateType) {
+        (Box::new(self), TemplateType::Greater)
+    }
+    fn at_least(self) -> (Box<dyn TupleField>, TemplateType) {
+        (Box::new(self), TemplateType::GreaterEqual)
+    }
+    fn less(self) -> (Box<dyn TupleField>, TemplateType) {
+        (Box::new(self), TemplateType::Less)
+    }
+    fn at_most(self) -> (Box<dyn TupleField>, TemplateType) {
+        (Box::new(self), TemplateType::LessEqual)
+    }
+}
+impl<T: FieldType + PartialOrd> OrderedFieldType for T {}
+
+/// Range template fields, matching values inside the range
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// # let space = LocalSpace::new_sequential();
+/// space.put(new_tuple!(12));
+/// space.put(new_tuple!(7));
+///
+/// let tuple = space.getp(new_template!((5..10).range())).unwrap();
+/// assert_eq!(7, *tuple.get_field::<i32>(0));
+/// assert!(space.getp(new_template!((5..=11).range())).is_err());
+/// ```
+pub trait RangeFieldType {
+    fn range(self) -> (Box<dyn TupleField>, TemplateType);
+}
+
+impl<T: FieldType + PartialOrd> RangeFieldType for Range<T> {
+    fn range(self) -> (Box<dyn TupleField>, TemplateType) {
+        (
+            Box::new(self.start),
+            TemplateType::Range {
+                end: Box::new(self.end),
+                inclusive: false,
+            },
+        )
+    }
+}
+
+impl<T: FieldType + PartialOrd> RangeFieldType for RangeInclusive<T> {
+    fn range(self) -> (Box<dyn TupleField>, TemplateType) {
+        let (start, end) = self.into_inner();
+        (
+            Box::new(start),
+            TemplateType::Range {
+                end: Box::new(end),
+                inclusive: true,
+            },
+        )
+    }
+}
+
+/// Template field matching any field regardless of its type and value
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// # let space = LocalSpace::new_sequential();
+/// space.put(new_tuple!(5, 'a'));
+/// assert!(space.queryp(new_template!(5.actual(), any())).is_ok());
+/// ```
+pub fn any() -> (Box<dyn TupleField>, TemplateType) {
+    (Box::new(Wildcard), TemplateType::Any)
+}
+
+/// Template field matching fields equal to one of the given values
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// # let space = LocalSpace::new_sequential();
+/// space.put(new_tuple!('b'));
+/// assert!(space.queryp(new_template!(one_of(['a', 'b']))).is_ok());
+/// assert!(space.queryp(new_template!(one_of(['c', 'd']))).is_err());
+/// ```
+pub fn one_of<T: FieldType, I: IntoIterator<Item = T>>(
+    values: I,
+) -> (Box<dyn TupleField>, TemplateType) {
+    let values = values
+        .into_iter()
+        .map(|v| Box::new(v) as Box<dyn TupleField>)
+        .collect();
+    (Box::new(Wildcard), TemplateType::OneOf(values))
+}
+
+/// Template field matching strings starting with the given prefix
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// # let space = LocalSpace::new_sequential();
+/// space.put(new_tuple!(String::from("job-42")));
+/// assert!(space.queryp(new_template!(starts_with("job-"))).is_ok());
+/// ```
+pub fn starts_with(prefix: &str) -> (Box<dyn TupleField>, TemplateType) {
+    (Box::new(prefix.to_string()), TemplateType::StartsWith)
+}
+
+/// Template field matching strings matching the given regular expression
+///
+/// # Errors
+/// Returns `Error::InvalidPattern` if the regular expression can not be compiled
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// # let space = LocalSpace::new_sequential();
+/// space.put(new_tuple!(String::from("job-42")));
+/// let template = new_template!(regex(r"^job-\d+$").unwrap());
+/// assert!(space.queryp(template).is_ok());
+/// ```
+pub fn regex(pattern: &str) -> Result<(Box<dyn TupleField>, TemplateType), Error> {
+    match Regex::new(pattern) {
+        Ok(r) => Ok((Box::new(Wildcard), TemplateType::Regex(Pattern(r)))),
+        Err(e) => Err(Error::InvalidPattern(e.to_string())),
+    }
+}
@@ -1,13 +1,45 @@
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
+    io::{self, BufRead, ErrorKind, Write},
+    sync::{mpsc::Sender, Arc, Mutex},
 };
 
-use crate::{gate::Gate, Space};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "tokio")]
+use crate::async_gate::AsyncGate;
+#[cfg(feature = "tls")]
+use crate::TlsAcceptor;
+use crate::{
+    auth::Access,
+    gate::Gate,
+    memory::{self, MemoryListener},
+    transport::{self, unsupported, Stream},
+    Authenticator, Credentials, Endpoint, Error, Operation, Permissions, Result, Space, Template,
+    Tuple,
+};
+
+/// A line of a repository export, a tuple along with the name of its space
+#[derive(Serialize, Deserialize)]
+struct Exported<N, T> {
+    space: N,
+    tuple: T,
+}
 
 pub struct Repository {
     spaces: Mutex<HashMap<String, Arc<dyn Space>>>,
+    access: Mutex<Access>,
     gates: Mutex<HashMap<String, Arc<Gate>>>,
+    /// Where remote spaces connect to the in-memory gates
+    memory_gates: Mutex<HashMap<String, Sender<Box<dyn Stream>>>>,
+    #[cfg(feature = "tokio")]
+    async_gates: Mutex<HashMap<String, AsyncGate>>,
+}
+
+impl Default for Repository {
+    fn default() -> Self {
+        Repository::new()
+    }
 }
 
 impl Repository {
@@ -15,7 +47,11 @@ impl Repository {
     pub fn new() -> Repository {
         Repository {
             spaces: Mutex::new(HashMap::new()),
+            access: Mutex::new(Access::default()),
             gates: Mutex::new(HashMap::new()),
+            memory_gates: Mutex::new(HashMap::new()),
+            #[cfg(feature = "tokio")]
+            async_gates: Mutex::new(HashMap::new()),
         }
     }
 
@@ -81,6 +117,147 @@ impl Repository {
         s.remove_entry(&name);
     }
 
+    /// Copies the tuples of every space in the repository, keyed by the name of the space.
+    /// Each space is copied at once, but the spaces are copied one after another.
+    ///
+    /// # Errors
+    /// Fails like `queryall` if a space can not be read
+    pub fn snapshot(&self) -> Result<HashMap<String, Vec<Tuple>>> {
+        let spaces: Vec<_> = self
+            .spaces
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, space)| (name.clone(), Arc::clone(space)))
+            .collect();
+        let mut snapshot = HashMap::new();
+        for (name, space) in spaces {
+            // A template of no fields matching by prefix matches every tuple
+            snapshot.insert(name, space.queryall(Template::new().prefix())?);
+        }
+        Ok(snapshot)
+    }
+
+    /// Writes a snapshot of every space as json, one tuple per line along with the name of its
+    /// space. Spaces are written in the order of their names.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # use std::sync::Arc;
+    /// let repo = Repository::new();
+    /// let jobs = Arc::new(LocalSpace::new_queue());
+    /// repo.add_space(String::from("jobs"), Arc::clone(&jobs));
+    /// jobs.put(new_tuple!(1)).unwrap();
+    /// let mut exported = Vec::new();
+    /// repo.export_json(&mut exported).unwrap();
+    ///
+    /// let copy = Repository::new();
+    /// copy.add_space(String::from("jobs"), Arc::new(LocalSpace::new_queue()));
+    /// assert_eq!(1, copy.import_json(&exported[..]).unwrap());
+    /// ```
+    pub fn export_json<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut snapshot: Vec<_> = self.snapshot()?.into_iter().collect();
+        snapshot.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (space, tuples) in &snapshot {
+            for tuple in tuples {
+                serde_json::to_writer(&mut writer, &Exported { space, tuple })?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(writer.flush()?)
+    }
+
+    /// Puts the tuples written by `export_json` into the spaces of the repository with the same
+    /// names, and returns how many there were. Empty lines are skipped.
+    ///
+    /// The whole input is read before any tuple is put. Each space then takes its tuples in a
+    /// single batch, so it gets all of them or none, and the spaces are filled in the order they
+    /// first appear in the input.
+    ///
+    /// # Errors
+    /// Returns `Error::Serialization` if a line can not be read and `Error::SpaceNotFound` if
+    /// the repository has no space of a name in the export. Both are checked before any tuple
+    /// is put. Otherwise fails like `batch` if a space does not take its tuples, in which case
+    /// the spaces filled before it keep theirs.
+    pub fn import_json<R: BufRead>(&self, reader: R) -> Result<usize> {
+        let mut imports: Vec<(String, Arc<dyn Space>, Vec<Operation>)> = Vec::new();
+        let mut n = 0;
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line: Exported<String, Tuple> = serde_json::from_str(&line)?;
+            let put = Operation::Put(line.tuple);
+            n += 1;
+            match imports.iter_mut().find(|(name, _, _)| *name == line.space) {
+                Some((_, _, puts)) => puts.push(put),
+                None => {
+                    let space = self
+                        .get_space(line.space.clone())
+                        .ok_or(Error::SpaceNotFound)?;
+                    imports.push((line.space, space, vec![put]));
+                }
+            }
+        }
+        for (_, space, puts) in imports {
+            space.batch(puts)?;
+        }
+        Ok(n)
+    }
+
+    /// Makes the gates of the repository require credentials from connecting clients.
+    ///
+    /// Once set, clients can only use spaces they have been granted permissions on with `grant`.
+    /// Permissions are looked up when a client connects.
+    pub fn set_authenticator(&self, authenticator: Authenticator) {
+        self.access.lock().unwrap().authenticator = Some(authenticator);
+    }
+
+    /// Adds to the permissions of a principal on a space. Grants to `EVERYONE` apply to every
+    /// authenticated client.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// let repo = Repository::new();
+    /// repo.set_authenticator(Authenticator::token("secret", "reader"));
+    /// repo.grant("reader", "results", Permissions::READ);
+    /// repo.grant(EVERYONE, "jobs", Permissions::PUT);
+    /// ```
+    pub fn grant(&self, principal: &str, space: &str, permissions: Permissions) {
+        let mut access = self.access.lock().unwrap();
+        let granted = access
+            .grants
+            .entry(principal.to_string())
+            .or_default()
+            .entry(space.to_string())
+            .or_default();
+        *granted = *granted | permissions;
+    }
+
+    /// Removes all permissions of a principal on a space
+    pub fn revoke(&self, principal: &str, space: &str) {
+        let mut access = self.access.lock().unwrap();
+        if let Some(spaces) = access.grants.get_mut(principal) {
+            spaces.remove(space);
+        }
+    }
+
+    /// Checks the credentials of a client connecting to the space and returns what it may do there
+    pub(crate) fn admit(
+        &self,
+        credentials: Option<&Credentials>,
+        certificate: Option<&[u8]>,
+        space: &str,
+    ) -> std::result::Result<Permissions, String> {
+        self.access
+            .lock()
+            .unwrap()
+            .admit(credentials, certificate, space)
+    }
+
     /// Adds a new gate for a repository
     ///
     /// Remember to close gate again after adding it to the repository
@@ -90,7 +267,8 @@ impl Repository {
     ///
     /// Second argument is the gates identifier for this repo
     ///
-    /// Third argument is a connection string used to bind to a specific socket address.
+    /// Third argument is a connection string used to bind to a specific socket address, like
+    /// `127.0.0.1:3800`, or to a unix socket, like `unix:///tmp/rspaces.sock`. See `Endpoint`.
     ///
     ///
     /// # Example
@@ -107,18 +285,108 @@ impl Repository {
     ///    "127.0.0.1:3800".to_string(),
     ///);
     /// ```
-    pub fn add_gate(repo: Arc<Repository>, name: String, addr: String) -> std::io::Result<()> {
+    pub fn add_gate(repo: Arc<Repository>, name: String, addr: String) -> Result<()> {
+        let listener = match addr.parse()? {
+            Endpoint::Tls(_) => return Err(unsupported("tls gates are added with add_gate_tls")),
+            endpoint => transport::listen(&endpoint)?,
+        };
         let clone = Arc::clone(&repo);
         let mut gates = repo.gates.lock().unwrap();
-        match Gate::new_gate(addr, clone) {
-            Ok(gate) => {
-                gates.insert(name, gate);
-                Ok(())
-            }
-            Err(e) => Err(e),
+        gates.insert(name, Gate::new_gate(listener, clone, Box::new(Ok)));
+        Ok(())
+    }
+
+    /// Adds a new gate for a repository like `add_gate`, but every client connects over TLS.
+    /// Remote spaces connect to it with `RemoteSpace::new_tls`.
+    #[cfg(feature = "tls")]
+    pub fn add_gate_tls(
+        repo: Arc<Repository>,
+        name: String,
+        addr: String,
+        tls: TlsAcceptor,
+    ) -> Result<()> {
+        let listener = match addr.parse()? {
+            Endpoint::Unix(_) => return Err(unsupported("TLS is not supported over unix sockets")),
+            endpoint => transport::listen(&endpoint)?,
+        };
+        let clone = Arc::clone(&repo);
+        let mut gates = repo.gates.lock().unwrap();
+        let gate = Gate::new_gate(listener, clone, Box::new(move |s| tls.accept(s)));
+        gates.insert(name, gate);
+        Ok(())
+    }
+
+    /// Adds a new gate for a repository that is reached from within the process with
+    /// `RemoteSpace::connect_in_memory` instead of over a socket. Clients are greeted and served
+    /// like those of a gate added with `add_gate`, so tests of distributed code can run in
+    /// parallel without picking free ports.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # use std::sync::Arc;
+    /// let repo = Arc::new(Repository::new());
+    /// repo.add_space(String::from("space"), Arc::new(LocalSpace::new_sequential()));
+    /// Repository::add_gate_in_memory(Arc::clone(&repo), String::from("gate"));
+    ///
+    /// let space =
+    ///     RemoteSpace::connect_in_memory(&repo, String::from("gate"), String::from("space"))
+    ///         .unwrap();
+    /// space.put(new_tuple!(1)).unwrap();
+    /// let tuple = space.get(new_template!(1.actual())).unwrap();
+    /// assert_eq!(1, *tuple.get_field::<i32>(0));
+    ///
+    /// repo.close_gate(String::from("gate"));
+    /// ```
+    pub fn add_gate_in_memory(repo: Arc<Repository>, name: String) {
+        let (listener, connections) = MemoryListener::new();
+        let clone = Arc::clone(&repo);
+        let gate = Gate::new_gate(Box::new(listener), clone, Box::new(Ok));
+        repo.gates.lock().unwrap().insert(name.clone(), gate);
+        repo.memory_gates.lock().unwrap().insert(name, connections);
+    }
+
+    /// Opens a connection to the in-memory gate of the repository with the name
+    pub(crate) fn connect_in_memory(&self, gate: &str) -> Result<Box<dyn Stream>> {
+        match self.memory_gates.lock().unwrap().get(gate) {
+            Some(gate) => Ok(memory::connect(gate)?),
+            None => Err(io::Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("the repository has no in-memory gate named {}", gate),
+            )
+            .into()),
         }
     }
 
+    /// Adds a new gate for a repository, running on the current tokio runtime
+    ///
+    /// The gate speaks the same protocol as the ones opened by `add_gate`, but serves every
+    /// connection from a task instead of a thread. Close it again with `close_gate`.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # use std::sync::Arc;
+    /// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+    /// let repo = Arc::new(Repository::new());
+    /// Repository::add_async_gate(Arc::clone(&repo), String::from("gate"), "127.0.0.1:3790".to_string())
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// repo.close_gate(String::from("gate"));
+    /// # });
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn add_async_gate(repo: Arc<Repository>, name: String, addr: String) -> Result<()> {
+        let addr = match addr.parse()? {
+            Endpoint::Tcp(addr) => addr,
+            _ => return Err(unsupported("async gates only listen on plain tcp")),
+        };
+        let gate = AsyncGate::bind(addr, Arc::clone(&repo)).await?;
+        repo.async_gates.lock().unwrap().insert(name, gate);
+        Ok(())
+    }
+
     /// Closes a gate connected to a repository. Ensures that the gate is shutdown gracefully.
     ///
     /// # Arguments
@@ -142,6 +410,12 @@ impl Repository {
     /// repo.close_gate(String::from("gate"));
     /// ```
     pub fn close_gate(&self, name: String) {
+        #[cfg(feature = "tokio")]
+        if let Some(gate) = self.async_gates.lock().unwrap().remove(&name) {
+            gate.close();
+            return;
+        }
+        self.memory_gates.lock().unwrap().remove(&name);
         let gates = self.gates.lock().unwrap();
         let gate = match gates.get(&name) {
             Some(gate) => gate,
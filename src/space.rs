@@ -1,19 +1,38 @@
-use std::io::Error;
-use std::io::Read;
-use std::io::Write;
-use std::net::TcpStream;
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::mpsc::Sender;
-use std::sync::Mutex;
-use std::sync::MutexGuard;
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
 use rand::thread_rng;
 use rand::Rng;
 
-use crate::drain_filter::drain_filter;
+use crate::batch::{self, Operation};
+use crate::client::Client;
+use crate::event::{Events, Subscriber};
+use crate::handshake;
 use crate::new_template;
+use crate::store::TupleStore;
+use crate::transport::{self, Stream};
+use crate::Codec;
+use crate::ConnectOptions;
+use crate::Error;
+use crate::IntoTuple;
 use crate::Message;
 use crate::MessageType;
+use crate::Repository;
+use crate::Result;
+use crate::SpaceEvent;
+use crate::SpaceUri;
 use crate::Template;
 use crate::Tuple;
 
@@ -42,12 +61,12 @@ pub trait Space: Send + Sync {
     /// assert_eq!('a', *tuple.get_field::<char>(1));
     ///
     /// ```
-    fn get(&self, template: Template) -> std::io::Result<Tuple>;
+    fn get(&self, template: Template) -> Result<Tuple>;
 
     /// Tries to get a matching tuple from the space by removing it without blocking
     ///
     /// # Errors
-    /// This will return an error if no tuple is found, but also if an io error occurs in a remotespace
+    /// This will return `Error::NoMatch` if no tuple is found, but also other errors if the connection fails in a remotespace
     ///
     ///
     /// # Example
@@ -70,24 +89,71 @@ pub trait Space: Send + Sync {
     ///
     /// ```
     ///
-    fn getp(&self, template: Template) -> std::io::Result<Tuple>;
+    fn getp(&self, template: Template) -> Result<Tuple>;
 
-    /// Puts the given tuple into the tuple space
+    /// Puts the given tuple into the tuple space. Rust tuples can be put directly, see `IntoTuple`.
+    ///
+    /// As `put` is generic it can not be called through a `&dyn Space` or `Box<dyn Space>`, use
+    /// `put_tuple` there instead. Shared spaces behind an `Arc` can still use `put`.
     /// # Example
     /// ```
     /// # use rspaces::*;
     /// # let space = LocalSpace::new_sequential();
     /// //Put the tuple (5, 'a') in the space
     /// space.put(new_tuple!(5, 'a'));
+    ///
+    /// //Put the tuple (6, 'b') without the macro
+    /// space.put((6, 'b')).unwrap();
+    ///
+    /// //Put the tuple (7, 'c') through a trait object
+    /// let dynamic: &dyn Space = &space;
+    /// dynamic.put_tuple(new_tuple!(7, 'c')).unwrap();
     /// ```
-    fn put(&self, tuple: Tuple) -> Result<(), std::io::Error>;
+    fn put(&self, tuple: impl IntoTuple) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.put_tuple(tuple.into_tuple())
+    }
+
+    /// Puts the given tuple into the tuple space. This is what spaces implement for `put`, and what
+    /// puts through a `&dyn Space`, which can not take the generic argument of `put`.
+    fn put_tuple(&self, tuple: Tuple) -> Result<()>;
+
+    /// Puts the given tuple into the tuple space for as long as the time to live.
+    ///
+    /// Once it expires the tuple is no longer found by get, query and their variants, and it is
+    /// removed from the space soon after. Its lease can be extended with `renew`. A time to live
+    /// too long to be represented never runs out, and the tuple is put like any other.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # use std::time::Duration;
+    /// # let space = LocalSpace::new_sequential();
+    /// space.put_with_ttl(new_tuple!(7, 'w'), Duration::from_millis(10)).unwrap();
+    /// assert!(space.queryp(new_template!(7.actual(), 'w'.actual())).is_ok());
+    ///
+    /// std::thread::sleep(Duration::from_millis(20));
+    /// assert!(space.queryp(new_template!(7.actual(), 'w'.actual())).is_err());
+    /// ```
+    fn put_with_ttl(&self, tuple: Tuple, ttl: Duration) -> Result<()>;
+
+    /// Renews the lease of every tuple matching the template that was put with a time to live,
+    /// so they expire the given time from now. Returns the tuples renewed, which is empty if
+    /// they have already expired. A time to live too long to be represented never runs out, so
+    /// the tuples no longer expire.
+    ///
+    /// Renewing reveals the tuples, so a remote space needs permission to both read from and
+    /// put to the space.
+    fn renew(&self, template: Template, ttl: Duration) -> Result<Vec<Tuple>>;
 
     /// Finds a tuple matching the template in the space, and returns it without removing it.
     ///
     /// This does not blcok the current thread and therefore returns an option, as theres no garantuee for finding a tuple
     ///
     /// # Errors
-    /// This will return an error if no tuple is found, but also if an io error occurs in a remotespace
+    /// This will return `Error::NoMatch` if no tuple is found, but also other errors if the connection fails in a remotespace
     ///
     ///
     /// # Example
@@ -110,7 +176,7 @@ pub trait Space: Send + Sync {
     ///
     /// ```
     ///
-    fn queryp(&self, query: Template) -> std::io::Result<Tuple>;
+    fn queryp(&self, query: Template) -> Result<Tuple>;
 
     /// Finds a tuple matching the template in the space, and returns it without removing it.
     ///
@@ -137,7 +203,53 @@ pub trait Space: Send + Sync {
     ///
     ///
     /// ```
-    fn query(&self, template: Template) -> std::io::Result<Tuple>;
+    fn query(&self, template: Template) -> Result<Tuple>;
+
+    /// Finds a tuple matching the template in the space, removes it from the space and returns it.
+    ///
+    /// Will block the current thread until a tuple is found or the timeout has passed
+    ///
+    /// # Errors
+    /// Returns `Error::TimedOut` if no tuple was found in time, and connection errors when used on a remotespace
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # use std::time::Duration;
+    /// # let space = LocalSpace::new_sequential();
+    /// space.put(new_tuple!(5, 'a'));
+    ///
+    /// let template = new_template!(5.actual(), 'b'.formal());
+    /// let tuple = space.get_timeout(template.clone(), Duration::from_millis(10)).unwrap();
+    /// assert_eq!('a', *tuple.get_field::<char>(1));
+    ///
+    /// //The space is now empty, so the next call gives up after the timeout
+    /// let err = space.get_timeout(template, Duration::from_millis(10)).unwrap_err();
+    /// assert!(matches!(err, Error::TimedOut));
+    /// ```
+    fn get_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple>;
+
+    /// Finds a tuple matching the template in the space, and returns it without removing it.
+    ///
+    /// Will block the current thread until a tuple is found or the timeout has passed
+    ///
+    /// # Errors
+    /// Returns `Error::TimedOut` if no tuple was found in time, and connection errors when used on a remotespace
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # use std::time::Duration;
+    /// # let space = LocalSpace::new_sequential();
+    /// let template = new_template!(5.actual(), 'b'.formal());
+    /// let err = space.query_timeout(template.clone(), Duration::from_millis(10)).unwrap_err();
+    /// assert!(matches!(err, Error::TimedOut));
+    ///
+    /// space.put(new_tuple!(5, 'a'));
+    /// let tuple = space.query_timeout(template, Duration::from_millis(10)).unwrap();
+    /// assert_eq!('a', *tuple.get_field::<char>(1));
+    /// ```
+    fn query_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple>;
     /// Gets all tuples in the space matching the template and removes them from the space
     ///
     /// # Error
@@ -164,7 +276,7 @@ pub trait Space: Send + Sync {
     /// }
     ///
     /// ```
-    fn getall(&self, template: Template) -> std::io::Result<Vec<Tuple>>;
+    fn getall(&self, template: Template) -> Result<Vec<Tuple>>;
     /// Gets all tuples in the space matching the template without removing them
     /// # Error
     /// Errors will only occur when used on a remotespace
@@ -190,14 +302,112 @@ pub trait Space: Send + Sync {
     /// }
     ///
     /// ```
-    fn queryall(&self, template: Template) -> std::io::Result<Vec<Tuple>>;
+    fn queryall(&self, template: Template) -> Result<Vec<Tuple>>;
+
+    /// Makes the operations in order as one transaction, so nobody sees the space in between.
+    /// If an operation fails, like a `Getp` finding no tuple, the changes made before it are
+    /// undone and its error is returned. Returns the tuples each operation returned, which is
+    /// none for puts.
+    ///
+    /// Remote spaces send the operations to the gate in one request. On a local space a closure
+    /// can decide on the operations as it goes, see `LocalSpace::transaction`.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # let space = LocalSpace::new_sequential();
+    /// space.put(new_tuple!('t')).unwrap();
+    ///
+    /// // Trade the token for a lock
+    /// let results = space
+    ///     .batch(vec![
+    ///         Operation::Getp(new_template!('t'.actual())),
+    ///         Operation::Put(new_tuple!('l')),
+    ///     ])
+    ///     .unwrap();
+    /// assert_eq!('t', *results[0][0].get_field::<char>(0));
+    ///
+    /// // The token is gone, so the second lock is not put
+    /// let err = space
+    ///     .batch(vec![
+    ///         Operation::Put(new_tuple!('l')),
+    ///         Operation::Getp(new_template!('t'.actual())),
+    ///     ])
+    ///     .unwrap_err();
+    /// assert!(matches!(err, Error::NoMatch));
+    /// assert_eq!(1, space.queryall(new_template!('l'.actual())).unwrap().len());
+    /// ```
+    fn batch(&self, operations: Vec<Operation>) -> Result<Vec<Vec<Tuple>>>;
+
+    /// Returns the space as a `LocalSpace` if it is one, so gates can subscribe to it and async
+    /// gates can wait on it without blocking a thread
+    fn as_local(&self) -> Option<&LocalSpace> {
+        None
+    }
 }
 
-enum SpaceType {
+/// Shared spaces, such as the ones a `Repository` hands out, are spaces themselves so `put` can
+/// take rust tuples through them
+impl<S: Space + ?Sized> Space for Arc<S> {
+    fn get(&self, template: Template) -> Result<Tuple> {
+        (**self).get(template)
+    }
+    fn getp(&self, template: Template) -> Result<Tuple> {
+        (**self).getp(template)
+    }
+    fn put_tuple(&self, tuple: Tuple) -> Result<()> {
+        (**self).put_tuple(tuple)
+    }
+    fn put_with_ttl(&self, tuple: Tuple, ttl: Duration) -> Result<()> {
+        (**self).put_with_ttl(tuple, ttl)
+    }
+    fn renew(&self, template: Template, ttl: Duration) -> Result<Vec<Tuple>> {
+        (**self).renew(template, ttl)
+    }
+    fn queryp(&self, template: Template) -> Result<Tuple> {
+        (**self).queryp(template)
+    }
+    fn query(&self, template: Template) -> Result<Tuple> {
+        (**self).query(template)
+    }
+    fn get_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple> {
+        (**self).get_timeout(template, timeout)
+    }
+    fn query_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple> {
+        (**self).query_timeout(template, timeout)
+    }
+    fn getall(&self, template: Template) -> Result<Vec<Tuple>> {
+        (**self).getall(template)
+    }
+    fn queryall(&self, template: Template) -> Result<Vec<Tuple>> {
+        (**self).queryall(template)
+    }
+    fn batch(&self, operations: Vec<Operation>) -> Result<Vec<Vec<Tuple>>> {
+        (**self).batch(operations)
+    }
+    fn as_local(&self) -> Option<&LocalSpace> {
+        (**self).as_local()
+    }
+}
+
+/// The longest the reaper sleeps, it wakes up earlier when a tuple expires before
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a reader waiting for a connection of a gate checks whether the connection ended
+const CANCEL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Which tuple `get` and `query` return when several match, see the constructors of `LocalSpace`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpaceType {
+    /// The oldest matching tuple
     Sequential,
+    /// The oldest tuple, if it matches
     Queue,
+    /// The newest tuple, if it matches
     Stack,
+    /// The newest matching tuple
     Pile,
+    /// Any matching tuple
     Random,
 }
 
@@ -221,8 +431,14 @@ enum SpaceType {
 /// assert_eq!('a', *tuple.get_field::<char>(1));
 /// ```
 pub struct LocalSpace {
-    v: Mutex<Vec<Tuple>>,
-    listeners: Mutex<Vec<Sender<()>>>,
+    /// Shared with the thread reaping expired tuples
+    v: Arc<Mutex<TupleStore>>,
+    /// Shared with the thread reaping expired tuples, which serves them when the head of a queue
+    /// or the top of a stack expires
+    waiters: Arc<Mutex<Waiters>>,
+    /// Wakes the thread reaping expired tuples, which the first tuple put with a time to live
+    /// starts
+    reaper: OnceLock<SyncSender<()>>,
     spacetype: SpaceType,
 }
 
@@ -236,8 +452,9 @@ impl LocalSpace {
     */
     pub fn new_sequential() -> LocalSpace {
         LocalSpace {
-            v: Mutex::new(Vec::new()),
-            listeners: Mutex::new(Vec::new()),
+            v: Arc::new(Mutex::new(TupleStore::new())),
+            waiters: Arc::new(Mutex::new(Waiters::new())),
+            reaper: OnceLock::new(),
             spacetype: SpaceType::Sequential,
         }
     }
@@ -249,8 +466,9 @@ impl LocalSpace {
     */
     pub fn new_queue() -> LocalSpace {
         LocalSpace {
-            v: Mutex::new(Vec::new()),
-            listeners: Mutex::new(Vec::new()),
+            v: Arc::new(Mutex::new(TupleStore::new())),
+            waiters: Arc::new(Mutex::new(Waiters::new())),
+            reaper: OnceLock::new(),
             spacetype: SpaceType::Queue,
         }
     }
@@ -262,8 +480,9 @@ impl LocalSpace {
     */
     pub fn new_stack() -> LocalSpace {
         LocalSpace {
-            v: Mutex::new(Vec::new()),
-            listeners: Mutex::new(Vec::new()),
+            v: Arc::new(Mutex::new(TupleStore::new())),
+            waiters: Arc::new(Mutex::new(Waiters::new())),
+            reaper: OnceLock::new(),
             spacetype: SpaceType::Stack,
         }
     }
@@ -275,8 +494,9 @@ impl LocalSpace {
     */
     pub fn new_pile() -> LocalSpace {
         LocalSpace {
-            v: Mutex::new(Vec::new()),
-            listeners: Mutex::new(Vec::new()),
+            v: Arc::new(Mutex::new(TupleStore::new())),
+            waiters: Arc::new(Mutex::new(Waiters::new())),
+            reaper: OnceLock::new(),
             spacetype: SpaceType::Pile,
         }
     }
@@ -288,214 +508,1264 @@ impl LocalSpace {
     */
     pub fn new_random() -> LocalSpace {
         LocalSpace {
-            v: Mutex::new(Vec::new()),
-            listeners: Mutex::new(Vec::new()),
+            v: Arc::new(Mutex::new(TupleStore::new())),
+            waiters: Arc::new(Mutex::new(Waiters::new())),
+            reaper: OnceLock::new(),
             spacetype: SpaceType::Random,
         }
     }
 
-    fn look(
+    /// Opens a space that keeps its tuples in the directory, so they survive restarts.
+    ///
+    /// Every change to the space is appended to a log in the directory and synced to disk before
+    /// blocked readers or subscribers see it, and the log is compacted into a snapshot as it
+    /// grows. Tuples found in the directory are restored in the order they were put. The changes
+    /// of an operation, such as a `getall` or a transaction, are logged together, and an
+    /// operation cut short by a crash at the end of the log is dropped as a whole.
+    ///
+    /// Operations that change the space fail with `Error::Io` if the log can not be written,
+    /// leaving the space as it was. Tuples that could not be read back from the log, such as
+    /// tuples with an infinite or NaN float, are refused with `Error::Serialization`.
+    ///
+    /// # Errors
+    /// Returns `Error::Io` if the directory can not be opened or a record in it is corrupt
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # let dir = std::env::temp_dir().join(format!("rspaces-doc-{}", std::process::id()));
+    /// let space = LocalSpace::open_persistent(&dir, SpaceType::Queue).unwrap();
+    /// space.put(new_tuple!(String::from("job"), 1)).unwrap();
+    /// drop(space);
+    ///
+    /// let space = LocalSpace::open_persistent(&dir, SpaceType::Queue).unwrap();
+    /// let job = space
+    ///     .get(new_template!(String::from("job").actual(), 0.formal()))
+    ///     .unwrap();
+    /// assert_eq!(1, *job.get_field::<i32>(1));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn open_persistent<P: AsRef<Path>>(dir: P, kind: SpaceType) -> Result<LocalSpace> {
+        let space = LocalSpace {
+            v: Arc::new(Mutex::new(TupleStore::open(dir.as_ref())?)),
+            waiters: Arc::new(Mutex::new(Waiters::new())),
+            reaper: OnceLock::new(),
+            spacetype: kind,
+        };
+        if space.v.lock().unwrap().next_expiry().is_some() {
+            space.reap_in_background();
+        }
+        Ok(space)
+    }
+
+    /// Writes the tuples of a persistent space to a new snapshot and empties its log. Spaces
+    /// only kept in memory are left as they are.
+    pub fn compact(&self) -> Result<()> {
+        self.v.lock().unwrap().compact()
+    }
+
+    /// Wakes the thread removing expired tuples, starting it unless it is running, so it sleeps
+    /// until the next tuple expires. The thread stops once the space is dropped.
+    fn reap_in_background(&self) {
+        let wake = self.reaper.get_or_init(|| {
+            let (wake, woken) = mpsc::sync_channel(1);
+            let store = Arc::downgrade(&self.v);
+            let waiters = Arc::downgrade(&self.waiters);
+            let spacetype = self.spacetype;
+            thread::spawn(move || loop {
+                let pause = match (store.upgrade(), waiters.upgrade()) {
+                    (Some(store), Some(waiters)) => {
+                        let mut store = store.lock().unwrap();
+                        if store.reap() {
+                            let _ = store.commit();
+                            // The tuple after an expired head may be what readers wait for
+                            if matches!(spacetype, SpaceType::Queue | SpaceType::Stack) {
+                                LocalSpace::serve_waiters(spacetype, &waiters, &mut store, None);
+                            }
+                        }
+                        store.next_expiry().map_or(REAP_INTERVAL, |at| {
+                            at.saturating_duration_since(Instant::now())
+                                .min(REAP_INTERVAL)
+                        })
+                    }
+                    _ => return,
+                };
+                if let Err(RecvTimeoutError::Disconnected) = woken.recv_timeout(pause) {
+                    return;
+                }
+            });
+            wake
+        });
+        // A full channel means the thread will wake up anyway
+        let _ = wake.try_send(());
+    }
+
+    fn look(&self, matcher: &Matcher, destroy: bool, store: &mut TupleStore) -> Result<Tuple> {
+        self.look_entry(matcher, destroy, store).map(|(_, t)| t)
+    }
+
+    /// Like `look`, also returning the id the tuple had in the store
+    fn look_entry(
         &self,
-        query: Template,
+        matcher: &Matcher,
         destroy: bool,
-        v: &mut MutexGuard<Vec<Tuple>>,
-    ) -> std::io::Result<Tuple> {
-        let index: usize;
+        store: &mut TupleStore,
+    ) -> Result<(u64, Tuple)> {
+        let id = self.find(matcher, store).ok_or(Error::NoMatch)?;
+        match destroy {
+            true => self.transact(store, |tx| Ok((id, tx.take(id)))),
+            false => Ok((id, store.get(id).clone())),
+        }
+    }
+
+    /// Puts a tuple taken for a reader that went away back in its old place
+    fn put_back(&self, store: &mut TupleStore, id: u64, tuple: Tuple) {
+        store.restore(id, tuple);
+        let _ = store.commit();
+        // It was reported as removed when it was taken
+        self.publish(|| SpaceEvent::Put(store.get(id).clone()));
+        self.serve(store, Some(id));
+    }
+
+    /// The id of the tuple `get` and `query` return, following the ordering of the space
+    fn find(&self, matcher: &Matcher, store: &TupleStore) -> Option<u64> {
         match self.spacetype {
-            SpaceType::Sequential => {
-                if let Some(i) = v.iter().position(|t| query.query(t)) {
-                    index = i;
+            SpaceType::Sequential => store
+                .candidates(matcher.template())
+                .find(|id| matcher.matches(store.get(*id))),
+            SpaceType::Queue => store.first().filter(|id| matcher.matches(store.get(*id))),
+            SpaceType::Pile => store
+                .candidates(matcher.template())
+                .rev()
+                .find(|id| matcher.matches(store.get(*id))),
+            SpaceType::Stack => store.last().filter(|id| matcher.matches(store.get(*id))),
+            SpaceType::Random => {
+                let candidates: Vec<u64> = store
+                    .candidates(matcher.template())
+                    .filter(|id| matcher.matches(store.get(*id)))
+                    .collect();
+                if candidates.is_empty() {
+                    None
                 } else {
-                    return Err(Error::from(std::io::ErrorKind::NotFound));
+                    let mut rng = thread_rng();
+                    Some(candidates[rng.gen_range(0..candidates.len())])
                 }
             }
-            SpaceType::Queue => {
-                if v.len() > 0 && query.query(v.get(0).unwrap()) {
-                    index = 0;
-                } else {
-                    return Err(Error::from(std::io::ErrorKind::NotFound));
+        }
+    }
+
+    /// Returns all matching tuples in the order they were put, removing them if destroy is set
+    fn look_all(&self, matcher: &Matcher, destroy: bool) -> Result<Vec<Tuple>> {
+        let mut store = self.v.lock().unwrap();
+        let ids: Vec<u64> = store
+            .candidates(matcher.template())
+            .filter(|id| matcher.matches(store.get(*id)))
+            .collect();
+        match destroy {
+            true => self.transact(&mut store, |tx| Ok(tx.take_all(ids))),
+            false => Ok(ids.into_iter().map(|id| store.get(id).clone()).collect()),
+        }
+    }
+
+    /// Tells the subscribers about a change to the space
+    fn publish<F: FnOnce() -> SpaceEvent>(&self, event: F) {
+        self.waiters.lock().unwrap().publish(event)
+    }
+
+    /// Hands tuples to blocked readers after the space changed, `new` being the id of a tuple just put.
+    ///
+    /// Waiters are served in the order they started waiting. A template waiter is only woken if the
+    /// tuple it can see matches its template, and receives that tuple directly. A `get` waiter takes
+    /// the tuple, so waiters after it do not see it. In queue and stack spaces that exposes the next
+    /// tuple, which is tested against the waiters from the start again. Closure waiters can not be
+    /// tested here and are woken to look through the space themselves.
+    fn serve(&self, store: &mut TupleStore, new: Option<u64>) {
+        LocalSpace::serve_waiters(self.spacetype, &self.waiters, store, new)
+    }
+
+    /// Serves the waiters of a space of the type, see `serve`
+    fn serve_waiters(
+        spacetype: SpaceType,
+        waiters: &Mutex<Waiters>,
+        store: &mut TupleStore,
+        new: Option<u64>,
+    ) {
+        let mut waiters = waiters.lock().unwrap();
+        let mut i = 0;
+        while i < waiters.queue.len() {
+            let waiter = &waiters.queue[i];
+            let template = match &waiter.template {
+                Some(template) => template,
+                None => {
+                    // A closed channel means the waiter timed out and is no longer listening
+                    if waiter.notify.send(None).is_err() {
+                        waiters.queue.remove(i);
+                    } else {
+                        i += 1;
+                    }
+                    continue;
                 }
-            }
-            SpaceType::Pile => {
-                if let Some(i) = v.iter().rev().position(|t| query.query(t)) {
-                    index = v.len() - i - 1;
-                } else {
-                    return Err(Error::from(std::io::ErrorKind::NotFound));
+            };
+            //Only the tuple that became visible to the waiter can be a new match
+            let visible = match spacetype {
+                SpaceType::Queue => store.first(),
+                SpaceType::Stack => store.last(),
+                _ => new.filter(|id| store.contains(*id)),
+            };
+            let id = match visible {
+                Some(id) if template.query(store.get(id)) => id,
+                _ => {
+                    i += 1;
+                    continue;
                 }
-            }
-            SpaceType::Stack => {
-                if v.len() > 0 && query.query(v.get(v.len() - 1).unwrap()) {
-                    index = v.len() - 1;
-                } else {
-                    return Err(Error::from(std::io::ErrorKind::NotFound));
+            };
+            if waiters.queue[i].destroy {
+                let expiry = store.expiry(id);
+                let tuple = store.remove(id);
+                // The tuple is only handed over once its removal is in the log
+                if store.commit().is_err() {
+                    store.restore(id, tuple);
+                    if let Some(at) = expiry {
+                        store.expire(id, at);
+                    }
+                    store.discard();
+                    i += 1;
+                    continue;
                 }
-            }
-            SpaceType::Random => {
-                let matches = self.queryall(query)?;
-                if matches.len() == 0 {
-                    return Err(Error::from(std::io::ErrorKind::NotFound));
+                let waiter = waiters.queue.remove(i).unwrap();
+                let removed = waiters.subscribed().then(|| tuple.clone());
+                match waiter.notify.send(Some((id, tuple))) {
+                    Err(Some((id, tuple))) => {
+                        store.restore(id, tuple);
+                        let _ = store.commit();
+                    }
+                    _ => {
+                        if let Some(tuple) = removed {
+                            waiters.publish(|| SpaceEvent::Removed(tuple));
+                        }
+                        // A waiter passed over earlier may match the new head
+                        if matches!(spacetype, SpaceType::Queue | SpaceType::Stack) {
+                            i = 0;
+                        }
+                    }
                 }
-                let mut rng = thread_rng();
-                index = rng.gen_range(0..matches.len());
+            } else {
+                let waiter = waiters.queue.remove(i).unwrap();
+                let _ = waiter.notify.send(Some((id, store.get(id).clone())));
             }
         }
-        match destroy {
-            true => Ok(v.remove(index)),
-            false => {
-                let ret = (*v.get(index).unwrap()).clone();
-                Ok(ret)
+    }
+}
+
+/// A reader blocked in `get`, `query` or one of their variants
+struct Waiter {
+    id: u64,
+    /// The template of the reader, or None for closure queries
+    template: Option<Template>,
+    destroy: bool,
+    notify: Notify,
+}
+
+/// A tuple handed to a waiter along with its id in the store, or None when a closure reader should look again
+type Handoff = Option<(u64, Tuple)>;
+
+/// The channel a waiter is woken through
+enum Notify {
+    Blocking(Sender<Handoff>),
+    #[cfg(feature = "tokio")]
+    Async(tokio::sync::mpsc::UnboundedSender<Handoff>),
+}
+
+impl Notify {
+    /// Sends to the waiter, giving the handoff back if the waiter stopped listening
+    fn send(&self, handoff: Handoff) -> std::result::Result<(), Handoff> {
+        match self {
+            Notify::Blocking(tx) => tx.send(handoff).map_err(|e| e.0),
+            #[cfg(feature = "tokio")]
+            Notify::Async(tx) => tx.send(handoff).map_err(|e| e.0),
+        }
+    }
+}
+
+/// Blocked readers in the order they started waiting, and the subscribers to the space
+struct Waiters {
+    queue: VecDeque<Waiter>,
+    next: u64,
+    subscribers: Vec<Subscriber>,
+}
+
+impl Waiters {
+    fn new() -> Waiters {
+        Waiters {
+            queue: VecDeque::new(),
+            next: 0,
+            subscribers: Vec::new(),
+        }
+    }
+
+    fn subscribed(&self) -> bool {
+        !self.subscribers.is_empty()
+    }
+
+    /// Reports the change to every subscriber it concerns, dropping subscribers whose receiver
+    /// is gone. The event is only built if anyone subscribed.
+    fn publish<F: FnOnce() -> SpaceEvent>(&mut self, event: F) {
+        if !self.subscribed() {
+            return;
+        }
+        let event = event();
+        self.subscribers
+            .retain(|s| match event.matching(&s.template) {
+                Some(event) => s.events.send(event),
+                None => true,
+            });
+    }
+
+    fn add(&mut self, template: Option<Template>, destroy: bool, notify: Notify) -> u64 {
+        let id = self.next;
+        self.next += 1;
+        self.queue.push_back(Waiter {
+            id,
+            template,
+            destroy,
+            notify,
+        });
+        id
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.queue.retain(|w| w.id != id);
+    }
+}
+
+/// How a local query selects tuples, templates can use the indexes of the space while closures can not
+enum Matcher<'a> {
+    Template(&'a Template),
+    Where(&'a dyn Fn(&Tuple) -> bool),
+}
+
+impl Matcher<'_> {
+    fn matches(&self, tuple: &Tuple) -> bool {
+        match self {
+            Matcher::Template(template) => template.query(tuple),
+            Matcher::Where(f) => f(tuple),
+        }
+    }
+
+    fn template(&self) -> Option<&Template> {
+        match self {
+            Matcher::Template(template) => Some(template),
+            Matcher::Where(_) => None,
+        }
+    }
+}
+
+/// A waiter registered by `wait_async`, removed again if the future is dropped before a tuple arrives
+#[cfg(feature = "tokio")]
+struct AsyncWait<'a> {
+    space: &'a LocalSpace,
+    id: u64,
+    destroy: bool,
+    rx: tokio::sync::mpsc::UnboundedReceiver<Handoff>,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncWait<'_> {
+    /// Stops waiting, returning a tuple handed over right before
+    fn stop(&mut self) -> Option<(u64, Tuple)> {
+        let _store = self.space.v.lock().unwrap();
+        self.space.waiters.lock().unwrap().remove(self.id);
+        self.rx.try_recv().ok().flatten()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for AsyncWait<'_> {
+    fn drop(&mut self) {
+        let mut store = self.space.v.lock().unwrap();
+        self.space.waiters.lock().unwrap().remove(self.id);
+        //A tuple taken for a future that was dropped goes back in its old place
+        if let Ok(Some((id, tuple))) = self.rx.try_recv() {
+            if self.destroy {
+                self.space.put_back(&mut store, id, tuple);
             }
         }
     }
 }
 
-impl Space for LocalSpace {
-    fn get(&self, template: Template) -> std::io::Result<Tuple> {
+impl LocalSpace {
+    /// Blocks until a matching tuple is found, or until the deadline if one is given
+    fn wait(&self, matcher: &Matcher, destroy: bool, deadline: Option<Instant>) -> Result<Tuple> {
+        self.wait_entry(matcher, destroy, deadline, None)
+            .map(|(_, t)| t)
+    }
+
+    /// Like `wait`, also returning the id the tuple had in the store. Gives up with
+    /// `Error::Closed` once `ended` is set, if it is given.
+    fn wait_entry(
+        &self,
+        matcher: &Matcher,
+        destroy: bool,
+        deadline: Option<Instant>,
+        ended: Option<&AtomicBool>,
+    ) -> Result<(u64, Tuple)> {
+        let (tx, rx) = mpsc::channel();
+        let id = {
+            let mut store = self.v.lock().unwrap();
+            if let Ok(entry) = self.look_entry(matcher, destroy, &mut store) {
+                return Ok(entry);
+            }
+            let mut waiters = self.waiters.lock().unwrap();
+            waiters.add(matcher.template().cloned(), destroy, Notify::Blocking(tx))
+        };
         loop {
-            let (tx, rx) = mpsc::channel();
-            {
-                let mut v = self.v.lock().unwrap();
-                match self.look(template.clone(), true, &mut v) {
-                    Ok(t) => return Ok(t),
-                    Err(_) => {
-                        let mut l = self.listeners.lock().unwrap();
-                        l.push(tx);
+            let received = match (deadline, ended) {
+                (None, None) => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                (Some(deadline), None) => {
+                    rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+                // A reader that can be cancelled wakes up now and then to check
+                (deadline, Some(_)) => rx.recv_timeout(
+                    deadline
+                        .map_or(CANCEL_INTERVAL, |deadline| {
+                            deadline.saturating_duration_since(Instant::now())
+                        })
+                        .min(CANCEL_INTERVAL),
+                ),
+            };
+            let error = match received {
+                Ok(Some(entry)) => return Ok(entry),
+                Ok(None) => {
+                    let mut store = self.v.lock().unwrap();
+                    if let Ok(entry) = self.look_entry(matcher, destroy, &mut store) {
+                        self.waiters.lock().unwrap().remove(id);
+                        return Ok(entry);
                     }
-                };
-            }
-            let _ = rx.recv();
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout)
+                    if deadline.is_none_or(|deadline| Instant::now() < deadline) =>
+                {
+                    match ended {
+                        Some(ended) if ended.load(Ordering::Relaxed) => Error::Closed,
+                        _ => continue,
+                    }
+                }
+                Err(_) => Error::TimedOut,
+            };
+            //Stop waiting, but a tuple may have been handed over right before
+            let _store = self.v.lock().unwrap();
+            self.waiters.lock().unwrap().remove(id);
+            return match rx.try_recv() {
+                Ok(Some(entry)) => Ok(entry),
+                _ => Err(error),
+            };
         }
     }
 
-    fn getp(&self, template: Template) -> std::io::Result<Tuple> {
-        let mut v = self.v.lock().unwrap();
-        self.look(template, true, &mut v)
+    /// Waits for a tuple matching the template on behalf of a connection of a gate, giving up
+    /// with `Error::Closed` once `ended` is set. Returns the id the tuple had in the store, for
+    /// `restore` to put it back if the reply does not reach the client.
+    pub(crate) fn wait_for_connection(
+        &self,
+        template: &Template,
+        destroy: bool,
+        deadline: Option<Instant>,
+        ended: &AtomicBool,
+    ) -> Result<(u64, Tuple)> {
+        self.wait_entry(&Matcher::Template(template), destroy, deadline, Some(ended))
+    }
+
+    /// Puts a tuple taken by `wait_for_connection` or `wait_async_entry` back in its old place
+    pub(crate) fn restore(&self, id: u64, tuple: Tuple) {
+        let mut store = self.v.lock().unwrap();
+        self.put_back(&mut store, id, tuple);
     }
 
-    fn put(&self, tuple: Tuple) -> Result<(), std::io::Error> {
-        let mut v = self.v.lock().unwrap();
-        v.push(tuple);
-        let mut l = self.listeners.lock().unwrap();
-        let mut remove = Vec::new();
-        for i in 0..l.len() {
-            let tx = l.get(i).unwrap();
-            match tx.send(()) {
-                Err(e) => panic!("panic: {:?}", e),
-                Ok(_) => remove.push(i),
+    /// Waits without blocking the thread until a tuple matching the template is found, or until
+    /// the deadline if one is given
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn wait_async(
+        &self,
+        template: Template,
+        destroy: bool,
+        deadline: Option<Instant>,
+    ) -> Result<Tuple> {
+        self.wait_async_entry(template, destroy, deadline)
+            .await
+            .map(|(_, t)| t)
+    }
+
+    /// Like `wait_async`, also returning the id the tuple had in the store, for `restore` to
+    /// put it back if the reply does not reach the client of a gate
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn wait_async_entry(
+        &self,
+        template: Template,
+        destroy: bool,
+        deadline: Option<Instant>,
+    ) -> Result<(u64, Tuple)> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let id = {
+            let mut store = self.v.lock().unwrap();
+            if let Ok(entry) = self.look_entry(&Matcher::Template(&template), destroy, &mut store) {
+                return Ok(entry);
+            }
+            let mut waiters = self.waiters.lock().unwrap();
+            waiters.add(Some(template), destroy, Notify::Async(tx))
+        };
+        let mut waiting = AsyncWait {
+            space: self,
+            id,
+            destroy,
+            rx,
+        };
+        let received = match deadline {
+            Some(deadline) => {
+                let deadline = tokio::time::Instant::from_std(deadline);
+                tokio::time::timeout_at(deadline, waiting.rx.recv())
+                    .await
+                    .ok()
+                    .flatten()
             }
+            None => waiting.rx.recv().await,
+        };
+        match received {
+            Some(Some(entry)) => Ok(entry),
+            _ => waiting.stop().ok_or(Error::TimedOut),
+        }
+    }
+
+    /// Copies every tuple in the space, in the order they were put. The copy is taken at once,
+    /// so it never holds half of a change made meanwhile.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// let space = LocalSpace::new_sequential();
+    /// space.put(new_tuple!(1)).unwrap();
+    /// space.put(new_tuple!(2, 'b')).unwrap();
+    ///
+    /// let tuples = space.snapshot();
+    /// assert_eq!(2, tuples.len());
+    /// assert_eq!(2, *tuples[1].get_field::<i32>(0));
+    /// ```
+    pub fn snapshot(&self) -> Vec<Tuple> {
+        self.v.lock().unwrap().tuples().cloned().collect()
+    }
+
+    /// Writes a snapshot of the space as json, one tuple per line
+    ///
+    /// # Errors
+    /// Returns `Error::Io` if the writer fails and `Error::Serialization` if a tuple can not be
+    /// encoded
+    pub fn export_json<W: Write>(&self, mut writer: W) -> Result<()> {
+        for tuple in self.snapshot() {
+            serde_json::to_writer(&mut writer, &tuple)?;
+            writer.write_all(b"\n")?;
         }
-        for i in remove.iter() {
-            l.remove(*i);
+        Ok(writer.flush()?)
+    }
+
+    /// Puts the tuples written by `export_json` into the space, in the order they were written,
+    /// and returns how many there were. Empty lines are skipped.
+    ///
+    /// Every line is read before the first tuple is put, and the tuples are put at once, so the
+    /// space either gets all of them or none.
+    ///
+    /// # Errors
+    /// Returns `Error::Serialization` if a line is not a tuple
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// let space = LocalSpace::new_queue();
+    /// space.put(new_tuple!(1, 'a')).unwrap();
+    /// let mut exported = Vec::new();
+    /// space.export_json(&mut exported).unwrap();
+    ///
+    /// let copy = LocalSpace::new_queue();
+    /// assert_eq!(1, copy.import_json(&exported[..]).unwrap());
+    /// assert!(copy.getp(new_template!(1.actual(), 'a'.actual())).is_ok());
+    /// ```
+    pub fn import_json<R: BufRead>(&self, reader: R) -> Result<usize> {
+        let mut tuples = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                tuples.push(serde_json::from_str::<Tuple>(&line)?);
+            }
         }
+        let n = tuples.len();
+        self.transaction(|tx| tuples.into_iter().try_for_each(|tuple| tx.put(tuple)))?;
+        Ok(n)
+    }
+
+    /// Subscribes to the changes to the space concerning tuples matching the template: every
+    /// tuple put, every tuple removed by `get` and its variants, and the matching tuples removed
+    /// by each `getall`. Tuples that expire are not reported.
+    ///
+    /// Events arrive in the order the changes were made. The subscription ends once the receiver
+    /// is dropped.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// let space = LocalSpace::new_sequential();
+    /// let events = space.subscribe(new_template!(0.formal(), 'a'.actual()));
+    ///
+    /// space.put(new_tuple!(1, 'a')).unwrap();
+    /// space.put(new_tuple!(2, 'b')).unwrap();
+    /// space.getp(new_template!(1.actual(), 'a'.actual())).unwrap();
+    ///
+    /// assert!(matches!(events.try_recv(), Ok(SpaceEvent::Put(_))));
+    /// match events.try_recv() {
+    ///     Ok(SpaceEvent::Removed(tuple)) => assert_eq!(1, *tuple.get_field::<i32>(0)),
+    ///     event => panic!("unexpected {:?}", event),
+    /// }
+    /// assert!(events.try_recv().is_err());
+    /// ```
+    pub fn subscribe(&self, template: Template) -> Receiver<SpaceEvent> {
+        let (events, rx) = mpsc::channel();
+        self.add_subscriber(Subscriber {
+            template,
+            events: Events::Unbounded(events),
+        });
+        rx
+    }
+
+    /// Subscribes like `subscribe`, but the subscription ends once as many events as the
+    /// capacity are waiting to be received
+    pub(crate) fn subscribe_bounded(
+        &self,
+        template: Template,
+        capacity: usize,
+    ) -> Receiver<SpaceEvent> {
+        let (events, rx) = mpsc::sync_channel(capacity);
+        self.add_subscriber(Subscriber {
+            template,
+            events: Events::Bounded(events),
+        });
+        rx
+    }
+
+    fn add_subscriber(&self, subscriber: Subscriber) {
+        // Taken so the subscription starts between two changes
+        let _store = self.v.lock().unwrap();
+        self.waiters.lock().unwrap().subscribers.push(subscriber);
+    }
+
+    /// Returns the number of tuples in the space
+    pub fn len(&self) -> usize {
+        self.v.lock().unwrap().len()
+    }
+
+    /// Returns true if the space holds no tuples
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of readers blocked until a matching tuple is put
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # use std::{sync::Arc, thread, time::Duration};
+    /// let space = Arc::new(LocalSpace::new_sequential());
+    /// let reader = Arc::clone(&space);
+    /// let handle = thread::spawn(move || reader.get(new_template!(1.actual())).unwrap());
+    /// while space.waiting() == 0 {
+    ///     thread::sleep(Duration::from_millis(1));
+    /// }
+    /// space.put(new_tuple!(1)).unwrap();
+    /// handle.join().unwrap();
+    /// assert_eq!(0, space.waiting());
+    /// ```
+    pub fn waiting(&self) -> usize {
+        self.waiters.lock().unwrap().queue.len()
+    }
+}
+
+//Closure based queries
+impl LocalSpace {
+    /// Finds a tuple for which the closure returns true, removes it from the space and returns it.
+    ///
+    /// Unlike templates the closure can hold any matching logic, but it can not be sent to a remote space.
+    /// Will block the current thread until a tuple is found, and follows the ordering of the space like `get`
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # let space = LocalSpace::new_sequential();
+    /// space.put(new_tuple!(5, 'a'));
+    /// space.put(new_tuple!(6, 'b'));
+    ///
+    /// let tuple = space
+    ///     .get_where(|t| t.try_get_field::<i32>(0).is_ok_and(|x| x % 2 == 0))
+    ///     .unwrap();
+    /// assert_eq!(6, *tuple.get_field::<i32>(0));
+    /// ```
+    pub fn get_where<F: Fn(&Tuple) -> bool>(&self, f: F) -> Result<Tuple> {
+        self.wait(&Matcher::Where(&f), true, None)
+    }
+
+    /// Tries to remove and return a tuple for which the closure returns true without blocking
+    ///
+    /// # Errors
+    /// This will return `Error::NoMatch` if no tuple is found
+    pub fn getp_where<F: Fn(&Tuple) -> bool>(&self, f: F) -> Result<Tuple> {
+        let mut store = self.v.lock().unwrap();
+        self.look(&Matcher::Where(&f), true, &mut store)
+    }
+
+    /// Finds a tuple for which the closure returns true and returns it without removing it.
+    ///
+    /// Will block the current thread until a tuple is found, and follows the ordering of the space like `query`
+    pub fn query_where<F: Fn(&Tuple) -> bool>(&self, f: F) -> Result<Tuple> {
+        self.wait(&Matcher::Where(&f), false, None)
+    }
+
+    /// Tries to return a tuple for which the closure returns true without removing it or blocking
+    ///
+    /// # Errors
+    /// This will return `Error::NoMatch` if no tuple is found
+    pub fn queryp_where<F: Fn(&Tuple) -> bool>(&self, f: F) -> Result<Tuple> {
+        let mut store = self.v.lock().unwrap();
+        self.look(&Matcher::Where(&f), false, &mut store)
+    }
+
+    /// Removes and returns all tuples for which the closure returns true
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # let space = LocalSpace::new_sequential();
+    /// space.put(new_tuple!(5, 'a'));
+    /// space.put(new_tuple!(6, 'b'));
+    /// space.put(new_tuple!(String::from("seven")));
+    ///
+    /// let tuples = space.getall_where(|t| t.len() == 2).unwrap();
+    /// assert_eq!(2, tuples.len());
+    /// assert_eq!(1, space.queryall_where(|_| true).unwrap().len());
+    /// ```
+    pub fn getall_where<F: Fn(&Tuple) -> bool>(&self, f: F) -> Result<Vec<Tuple>> {
+        self.look_all(&Matcher::Where(&f), true)
+    }
+
+    /// Returns all tuples for which the closure returns true without removing them
+    pub fn queryall_where<F: Fn(&Tuple) -> bool>(&self, f: F) -> Result<Vec<Tuple>> {
+        self.look_all(&Matcher::Where(&f), false)
+    }
+}
+
+//Transactions
+impl LocalSpace {
+    /// Runs the closure with the space to itself, so the operations it makes take effect at once.
+    ///
+    /// If the closure returns an error or panics, every change it made is undone and the error
+    /// is returned or the panic carried on. Otherwise the changes are committed and blocked
+    /// readers and subscribers see them. The changes of a transaction on a persistent space are
+    /// logged together, so a crash never keeps part of them. Only operations that do not block
+    /// can be made, and the closure must not use the space other than through the transaction,
+    /// or it deadlocks.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// # let space = LocalSpace::new_sequential();
+    /// space.put(new_tuple!(String::from("token"))).unwrap();
+    ///
+    /// let holder = space
+    ///     .transaction(|tx| {
+    ///         tx.getp(new_template!(String::from("token").actual()))?;
+    ///         tx.put(new_tuple!(String::from("lock"), 1))?;
+    ///         Ok(1)
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(1, holder);
+    ///
+    /// // Without a token the lock taken by this transaction is given back
+    /// let result = space.transaction(|tx| {
+    ///     tx.getp(new_template!(String::from("lock").actual(), 0.formal()))?;
+    ///     tx.getp(new_template!(String::from("token").actual()))
+    /// });
+    /// assert!(matches!(result, Err(Error::NoMatch)));
+    /// assert_eq!(1, space.len());
+    /// ```
+    pub fn transaction<R, F>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Transaction) -> Result<R>,
+    {
+        let mut store = self.v.lock().unwrap();
+        // A panic is only let through once the lock is released, so it does not poison the space
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.transact(&mut store, f)));
+        drop(store);
+        result.unwrap_or_else(|panic| panic::resume_unwind(panic))
+    }
+
+    /// Runs the closure as a transaction on the store, which the caller has locked
+    fn transact<R, F>(&self, store: &mut TupleStore, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Transaction) -> Result<R>,
+    {
+        let events = self.waiters.lock().unwrap().subscribed().then(Vec::new);
+        let mut tx = Transaction {
+            space: self,
+            store,
+            undo: Vec::new(),
+            events,
+        };
+        // Dropping the transaction on an error, or a panic, undoes its changes
+        let result = f(&mut tx)?;
+        tx.commit()?;
+        Ok(result)
+    }
+}
+
+/// Operations on a local space that take effect at once, see `LocalSpace::transaction`
+pub struct Transaction<'a> {
+    space: &'a LocalSpace,
+    store: &'a mut TupleStore,
+    /// How to undo the changes made so far, oldest first
+    undo: Vec<Undo>,
+    /// The changes reported to the subscribers on commit, kept only if the space has any
+    events: Option<Vec<SpaceEvent>>,
+}
+
+/// A change made by a transaction
+enum Undo {
+    Put(u64),
+    Removed {
+        id: u64,
+        tuple: Tuple,
+        expiry: Option<Instant>,
+    },
+    /// The expiry of the tuple was changed from the one given
+    Expired {
+        id: u64,
+        expiry: Option<Instant>,
+    },
+}
+
+impl Transaction<'_> {
+    /// Puts the tuple into the space
+    pub fn put(&mut self, tuple: impl IntoTuple) -> Result<()> {
+        self.push(tuple.into_tuple());
         Ok(())
     }
 
-    fn queryp(&self, template: Template) -> std::io::Result<Tuple> {
-        let mut v = self.v.lock().unwrap();
-        self.look(template, false, &mut v)
+    /// Removes and returns a tuple matching the template, following the ordering of the space
+    ///
+    /// # Errors
+    /// This will return `Error::NoMatch` if no tuple is found
+    pub fn getp(&mut self, template: Template) -> Result<Tuple> {
+        let id = self
+            .space
+            .find(&Matcher::Template(&template), self.store)
+            .ok_or(Error::NoMatch)?;
+        Ok(self.take(id))
     }
 
-    fn query(&self, template: Template) -> std::io::Result<Tuple> {
-        loop {
-            let (tx, rx) = mpsc::channel();
-            {
-                let mut v = self.v.lock().unwrap();
-                match self.look(template.clone(), false, &mut v) {
-                    Ok(t) => return Ok(t),
-                    Err(_) => {
-                        let mut l = self.listeners.lock().unwrap();
-                        l.push(tx);
-                    }
-                };
+    /// Returns a tuple matching the template without removing it
+    ///
+    /// # Errors
+    /// This will return `Error::NoMatch` if no tuple is found
+    pub fn queryp(&self, template: Template) -> Result<Tuple> {
+        let id = self
+            .space
+            .find(&Matcher::Template(&template), self.store)
+            .ok_or(Error::NoMatch)?;
+        Ok(self.store.get(id).clone())
+    }
+
+    /// Removes and returns all tuples matching the template
+    pub fn getall(&mut self, template: Template) -> Result<Vec<Tuple>> {
+        let ids = self.matching(&template);
+        Ok(self.take_all(ids))
+    }
+
+    /// Returns all tuples matching the template without removing them
+    pub fn queryall(&self, template: Template) -> Result<Vec<Tuple>> {
+        let ids = self.matching(&template);
+        Ok(ids
+            .into_iter()
+            .map(|id| self.store.get(id).clone())
+            .collect())
+    }
+
+    /// Makes one operation of a batch
+    fn apply(&mut self, operation: Operation) -> Result<Vec<Tuple>> {
+        match operation {
+            Operation::Put(tuple) => self.put(tuple).map(|_| Vec::new()),
+            Operation::Getp(template) => self.getp(template).map(|t| vec![t]),
+            Operation::Queryp(template) => self.queryp(template).map(|t| vec![t]),
+            Operation::Getall(template) => self.getall(template),
+            Operation::Queryall(template) => self.queryall(template),
+        }
+    }
+
+    /// The ids of the tuples matching the template, oldest first
+    fn matching(&self, template: &Template) -> Vec<u64> {
+        self.store
+            .candidates(Some(template))
+            .filter(|id| template.query(self.store.get(*id)))
+            .collect()
+    }
+
+    /// Puts the tuple into the space, returning its id
+    fn push(&mut self, tuple: Tuple) -> u64 {
+        let id = self.store.push(tuple);
+        self.undo.push(Undo::Put(id));
+        if let Some(events) = &mut self.events {
+            events.push(SpaceEvent::Put(self.store.get(id).clone()));
+        }
+        id
+    }
+
+    /// Removes the tuple as `get` does
+    fn take(&mut self, id: u64) -> Tuple {
+        let tuple = self.remove(id);
+        if let Some(events) = &mut self.events {
+            events.push(SpaceEvent::Removed(tuple.clone()));
+        }
+        tuple
+    }
+
+    /// Removes the tuples as `getall` does
+    fn take_all(&mut self, ids: Vec<u64>) -> Vec<Tuple> {
+        let tuples: Vec<Tuple> = ids.into_iter().map(|id| self.remove(id)).collect();
+        if let Some(events) = &mut self.events {
+            if !tuples.is_empty() {
+                events.push(SpaceEvent::RemovedAll(tuples.clone()));
             }
-            let _ = rx.recv();
         }
+        tuples
     }
 
-    fn getall(&self, template: Template) -> std::io::Result<Vec<Tuple>> {
-        let mut v = self.v.lock().unwrap();
-        Ok(drain_filter(&mut v, |t| template.query(t)).collect::<Vec<_>>())
+    /// Makes the tuple expire at the time, or never
+    fn expire(&mut self, id: u64, at: Option<Instant>) {
+        self.undo.push(Undo::Expired {
+            id,
+            expiry: self.store.expiry(id),
+        });
+        self.store.set_expiry(id, at);
     }
 
-    fn queryall(&self, template: Template) -> std::io::Result<Vec<Tuple>> {
-        let v = self.v.lock().unwrap();
-        let viter = v.iter().filter(|t| template.query(t));
-        let mut res = Vec::new();
-        for t in viter {
-            res.push(t.clone());
+    fn remove(&mut self, id: u64) -> Tuple {
+        let expiry = self.store.expiry(id);
+        let tuple = self.store.remove(id);
+        self.undo.push(Undo::Removed {
+            id,
+            tuple: tuple.clone(),
+            expiry,
+        });
+        tuple
+    }
+
+    /// Logs the changes of a persistent space, then reports them to the subscribers and hands
+    /// the tuples put to blocked readers. Nothing is reported if the changes can not be logged,
+    /// they are undone instead.
+    fn commit(mut self) -> Result<()> {
+        self.store.commit()?;
+        // Nothing is left to undo once the transaction is dropped
+        let undo = std::mem::take(&mut self.undo);
+        for event in self.events.take().into_iter().flatten() {
+            self.space.publish(|| event);
+        }
+        let mut put = false;
+        let mut removed = false;
+        for change in &undo {
+            match change {
+                Undo::Put(id) => {
+                    put = true;
+                    self.space.serve(self.store, Some(*id));
+                }
+                Undo::Removed { .. } => removed = true,
+                Undo::Expired { .. } => {}
+            }
         }
-        Ok(res)
+        if !put && removed {
+            self.space.serve(self.store, None);
+        }
+        Ok(())
+    }
+
+    /// Undoes the changes, newest first, and keeps them out of the log of a persistent space
+    fn rollback(&mut self) {
+        for change in self.undo.drain(..).rev() {
+            match change {
+                Undo::Put(id) => {
+                    self.store.remove(id);
+                }
+                Undo::Removed { id, tuple, expiry } => {
+                    self.store.restore(id, tuple);
+                    if let Some(at) = expiry {
+                        self.store.expire(id, at);
+                    }
+                }
+                Undo::Expired { id, expiry } => self.store.set_expiry(id, expiry),
+            }
+        }
+        self.store.discard();
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        self.rollback();
+    }
+}
+
+impl Space for LocalSpace {
+    fn get(&self, template: Template) -> Result<Tuple> {
+        self.wait(&Matcher::Template(&template), true, None)
+    }
+
+    fn getp(&self, template: Template) -> Result<Tuple> {
+        let mut store = self.v.lock().unwrap();
+        self.look(&Matcher::Template(&template), true, &mut store)
+    }
+
+    fn put_tuple(&self, tuple: Tuple) -> Result<()> {
+        self.transaction(|tx| tx.put(tuple))
+    }
+
+    fn put_with_ttl(&self, tuple: Tuple, ttl: Duration) -> Result<()> {
+        let at = Instant::now().checked_add(ttl);
+        self.transaction(|tx| {
+            let id = tx.push(tuple);
+            tx.expire(id, at);
+            Ok(())
+        })?;
+        self.reap_in_background();
+        Ok(())
+    }
+
+    fn renew(&self, template: Template, ttl: Duration) -> Result<Vec<Tuple>> {
+        let at = Instant::now().checked_add(ttl);
+        let renewed = self.transaction(|tx| {
+            let ids: Vec<u64> = tx
+                .store
+                .candidates(Some(&template))
+                .filter(|id| tx.store.expires(*id) && template.query(tx.store.get(*id)))
+                .collect();
+            for id in &ids {
+                tx.expire(*id, at);
+            }
+            Ok(ids.into_iter().map(|id| tx.store.get(id).clone()).collect())
+        });
+        self.reap_in_background();
+        renewed
+    }
+
+    fn queryp(&self, template: Template) -> Result<Tuple> {
+        let mut store = self.v.lock().unwrap();
+        self.look(&Matcher::Template(&template), false, &mut store)
+    }
+
+    fn query(&self, template: Template) -> Result<Tuple> {
+        self.wait(&Matcher::Template(&template), false, None)
+    }
+
+    fn get_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple> {
+        // A timeout too long to be represented never runs out
+        let deadline = Instant::now().checked_add(timeout);
+        self.wait(&Matcher::Template(&template), true, deadline)
+    }
+
+    fn query_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple> {
+        let deadline = Instant::now().checked_add(timeout);
+        self.wait(&Matcher::Template(&template), false, deadline)
+    }
+
+    fn getall(&self, template: Template) -> Result<Vec<Tuple>> {
+        self.look_all(&Matcher::Template(&template), true)
+    }
+
+    fn queryall(&self, template: Template) -> Result<Vec<Tuple>> {
+        self.look_all(&Matcher::Template(&template), false)
+    }
+
+    fn batch(&self, operations: Vec<Operation>) -> Result<Vec<Vec<Tuple>>> {
+        self.transaction(|tx| operations.into_iter().map(|op| tx.apply(op)).collect())
+    }
+
+    fn as_local(&self) -> Option<&LocalSpace> {
+        Some(self)
     }
 }
 
 pub struct RemoteSpace {
-    stream: Mutex<TcpStream>,
+    client: Client,
+    client_id: String,
 }
 
 impl RemoteSpace {
-    pub fn new(mut conn: String) -> std::io::Result<RemoteSpace> {
-        let ip_offset = match conn.find('/') {
-            Some(c) => c,
-            None => return Err(Error::from(std::io::ErrorKind::InvalidInput)),
+    /// Connects to a space on a gate, given a connection string like `127.0.0.1:3800/space` or
+    /// any other `SpaceUri`
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidAddress` if the connection string is not a valid uri and
+    /// `Error::SpaceNotFound` if the gate has no space of that name
+    pub fn new(conn: String) -> Result<RemoteSpace> {
+        RemoteSpace::with_options(conn, ConnectOptions::default())
+    }
+
+    /// Connects like `new`, but encodes messages with the given codec instead of json
+    ///
+    /// # Errors
+    /// Returns `Error::Rejected` if the gate does not support the codec
+    pub fn with_codec(conn: String, codec: Codec) -> Result<RemoteSpace> {
+        let options = ConnectOptions {
+            codec,
+            ..Default::default()
         };
-        let ip_string: String = conn.drain(0..ip_offset).collect();
-        let mut stream = TcpStream::connect(ip_string)?;
-        stream.set_nonblocking(false).unwrap();
-        conn.remove(0);
-        stream.write_all(conn.as_bytes())?;
-        let mut buf = [0; 2];
+        RemoteSpace::with_options(conn, options)
+    }
 
-        let n = stream.read(&mut buf[..])?;
-        let inc_string = String::from_utf8_lossy(&buf[..n]);
-        match inc_string.as_ref() {
-            "t" => {}
-            _ => return Err(Error::from(std::io::ErrorKind::NotFound)),
-        }
+    /// Connects like `new` to a gate added with `Repository::add_gate_tls`. The host in the
+    /// connection string must match the certificate of the gate.
+    ///
+    /// # Errors
+    /// Returns `Error::Tls` if the gate can not prove its identity
+    #[cfg(feature = "tls")]
+    pub fn new_tls(conn: String, tls: crate::TlsConnector) -> Result<RemoteSpace> {
+        let options = ConnectOptions {
+            tls: Some(tls),
+            ..Default::default()
+        };
+        RemoteSpace::with_options(conn, options)
+    }
+
+    /// Connects to a space on a gate, introducing the client with the given options
+    ///
+    /// # Errors
+    /// Returns `Error::SpaceNotFound` if the gate has no space of that name, and
+    /// `Error::Rejected` if the gate refuses the connection for any other reason
+    pub fn with_options(conn: String, options: ConnectOptions) -> Result<RemoteSpace> {
+        RemoteSpace::connect(&conn.parse()?, options)
+    }
+
+    /// Connects to the space at the uri. Options given in the uri take precedence over the
+    /// connect options.
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidAddress` if the uri can not be used, like a `tls://` uri without a
+    /// TLS connector
+    pub fn connect(uri: &SpaceUri, options: ConnectOptions) -> Result<RemoteSpace> {
+        let options = uri.apply(options);
+        let stream = transport::connect(&uri.endpoint, &options)?;
+        RemoteSpace::open(stream, uri.space.clone(), options)
+    }
+
+    /// Connects to a space through the in-memory gate of the repository with the name, added
+    /// with `Repository::add_gate_in_memory`
+    ///
+    /// # Errors
+    /// Returns `Error::Io` if the repository has no in-memory gate of that name, and
+    /// `Error::SpaceNotFound` if it has no space of that name
+    pub fn connect_in_memory(
+        repo: &Repository,
+        gate: String,
+        space: String,
+    ) -> Result<RemoteSpace> {
+        RemoteSpace::open(
+            repo.connect_in_memory(&gate)?,
+            space,
+            ConnectOptions::default(),
+        )
+    }
+
+    /// Greets the gate at the other end of the stream and asks for the space
+    fn open(
+        mut stream: Box<dyn Stream>,
+        space: String,
+        options: ConnectOptions,
+    ) -> Result<RemoteSpace> {
+        stream.write_all(&options.hello(space)?)?;
+        let client_id = handshake::check_welcome(&handshake::read_frame(&mut stream)?)?;
+        stream.set_read_timeout(None)?;
 
         Ok(RemoteSpace {
-            stream: Mutex::new(stream),
+            client: Client::new(stream, options.codec)?,
+            client_id,
         })
     }
 
-    fn send(&self, m: Message) -> Result<(), std::io::Error> {
-        let mut stream = self.stream.lock().unwrap();
-        let m_json = serde_json::to_string(&m)?;
-        stream.write_all(m_json.as_bytes())?;
-        stream.flush()?;
-        Ok(())
+    /// The id the gate knows this client by
+    pub fn client_id(&self) -> &str {
+        &self.client_id
     }
 
-    fn recv(&self) -> Result<Tuple, std::io::Error> {
-        let mut stream = self.stream.lock().unwrap();
-        let mut buffer = [0; 1024];
-        let n = stream.read(&mut buffer[..])?;
-        let inc_string = String::from_utf8_lossy(&buffer[..n]);
-        let mut message = serde_json::from_str::<Message>(&inc_string)?;
-        if message.tuple.len() == 1 {
-            Ok(message.tuple.remove(0))
-        } else {
-            Err(Error::from(std::io::ErrorKind::NotFound))
+    /// Subscribes to the changes to the space on the gate, like `LocalSpace::subscribe`. The gate
+    /// pushes the events over the connection of the remote space until the receiver is dropped,
+    /// which it is told of with the next event. The receiver also ends if the connection is lost.
+    ///
+    /// # Errors
+    /// Returns `Error::Remote` if the space on the gate is not a local space or the client may
+    /// not read from it
+    pub fn subscribe(&self, template: Template) -> Result<Receiver<SpaceEvent>> {
+        let (tx, rx) = mpsc::channel();
+        let m = Message {
+            id: 0,
+            action: MessageType::Subscribe,
+            tuple: Vec::new(),
+            template,
+        };
+        match self.client.subscribe(m, tx)?.action {
+            MessageType::Ok => Ok(rx),
+            action => Err(RemoteSpace::reply_error(action)),
         }
     }
 
-    fn recv_multiple(&self) -> Result<Vec<Tuple>, std::io::Error> {
-        let mut stream = self.stream.lock().unwrap();
-        let mut buffer = [0; 1024];
-        let n = stream.read(&mut buffer[..])?;
-        let inc_string = String::from_utf8_lossy(&buffer[..n]);
-        let message = serde_json::from_str::<Message>(&inc_string)?;
-        Ok(message.tuple)
+    /// Sends the message and waits for its reply, other threads can make requests meanwhile
+    fn request(&self, m: Message) -> Result<Message> {
+        self.client.request(m)
     }
 
-    fn send_recv(&self, m: Message) -> Result<Tuple, std::io::Error> {
-        self.send(m)?;
-        self.recv()
+    fn send_recv(&self, m: Message) -> Result<Tuple> {
+        RemoteSpace::single_tuple(self.request(m)?)
+    }
+
+    fn send_recv_multiple(&self, m: Message) -> Result<Vec<Tuple>> {
+        RemoteSpace::tuples(self.request(m)?)
+    }
+
+    /// Extracts the tuple from a reply to a request for a single tuple
+    pub(crate) fn single_tuple(mut message: Message) -> Result<Tuple> {
+        match message.action {
+            MessageType::Ok if message.tuple.len() == 1 => Ok(message.tuple.remove(0)),
+            MessageType::Ok => Err(Error::Protocol(format!(
+                "expected a single tuple but got {}",
+                message.tuple.len()
+            ))),
+            action => Err(RemoteSpace::reply_error(action)),
+        }
+    }
+
+    /// Extracts the tuples from a reply to a request for any number of tuples
+    pub(crate) fn tuples(message: Message) -> Result<Vec<Tuple>> {
+        match message.action {
+            MessageType::Ok => Ok(message.tuple),
+            action => Err(RemoteSpace::reply_error(action)),
+        }
+    }
+
+    /// Maps a reply from the gate that did not succeed to the matching error
+    pub(crate) fn reply_error(action: MessageType) -> Error {
+        match action {
+            MessageType::NoMatch => Error::NoMatch,
+            MessageType::TimedOut => Error::TimedOut,
+            MessageType::Error { code, reason } => Error::Remote(code, reason),
+            action => Error::Protocol(format!("unexpected reply {:?}", action)),
+        }
     }
 }
 
 impl Space for RemoteSpace {
-    fn get(&self, template: Template) -> Result<Tuple, std::io::Error> {
+    fn get(&self, template: Template) -> Result<Tuple> {
         let m = Message {
+            id: 0,
             action: MessageType::Get,
             tuple: Vec::new(),
             template,
@@ -503,8 +1773,9 @@ impl Space for RemoteSpace {
         self.send_recv(m)
     }
 
-    fn getp(&self, template: Template) -> Result<Tuple, std::io::Error> {
+    fn getp(&self, template: Template) -> Result<Tuple> {
         let m = Message {
+            id: 0,
             action: MessageType::Getp,
             tuple: Vec::new(),
             template,
@@ -512,27 +1783,47 @@ impl Space for RemoteSpace {
         self.send_recv(m)
     }
 
-    fn put(&self, tuple: Tuple) -> Result<(), std::io::Error> {
+    fn put_tuple(&self, tuple: Tuple) -> Result<()> {
         let m = Message {
+            id: 0,
             action: MessageType::Put,
             tuple: Vec::from([tuple]),
             template: new_template!(),
         };
-        self.send(m)?;
-        let mut stream = self.stream.lock().unwrap();
-        let mut buffer = [0; 1024];
-        let n = stream.read(&mut buffer[..])?;
-        let inc_string = String::from_utf8_lossy(&buffer[..n]);
-        let message = serde_json::from_str::<Message>(&inc_string)?;
-        if message.action == MessageType::Ok {
-            Ok(())
-        } else {
-            Err(Error::from(std::io::ErrorKind::Other))
+        let message = self.request(m)?;
+        match message.action {
+            MessageType::Ok => Ok(()),
+            action => Err(RemoteSpace::reply_error(action)),
+        }
+    }
+
+    fn put_with_ttl(&self, tuple: Tuple, ttl: Duration) -> Result<()> {
+        let m = Message {
+            id: 0,
+            action: MessageType::PutWithTtl(ttl),
+            tuple: Vec::from([tuple]),
+            template: new_template!(),
+        };
+        let message = self.request(m)?;
+        match message.action {
+            MessageType::Ok => Ok(()),
+            action => Err(RemoteSpace::reply_error(action)),
         }
     }
 
-    fn queryp(&self, template: Template) -> Result<Tuple, std::io::Error> {
+    fn renew(&self, template: Template, ttl: Duration) -> Result<Vec<Tuple>> {
+        let m = Message {
+            id: 0,
+            action: MessageType::Renew(ttl),
+            tuple: Vec::new(),
+            template,
+        };
+        self.send_recv_multiple(m)
+    }
+
+    fn queryp(&self, template: Template) -> Result<Tuple> {
         let m = Message {
+            id: 0,
             action: MessageType::Queryp,
             tuple: Vec::new(),
             template,
@@ -540,8 +1831,9 @@ impl Space for RemoteSpace {
         self.send_recv(m)
     }
 
-    fn query(&self, template: Template) -> Result<Tuple, std::io::Error> {
+    fn query(&self, template: Template) -> Result<Tuple> {
         let m = Message {
+            id: 0,
             action: MessageType::Query,
             tuple: Vec::new(),
             template,
@@ -549,23 +1841,58 @@ impl Space for RemoteSpace {
         self.send_recv(m)
     }
 
-    fn getall(&self, template: Template) -> Result<Vec<Tuple>, std::io::Error> {
+    fn get_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple> {
+        let m = Message {
+            id: 0,
+            action: MessageType::GetTimeout(timeout),
+            tuple: Vec::new(),
+            template,
+        };
+        self.send_recv(m)
+    }
+
+    fn query_timeout(&self, template: Template, timeout: Duration) -> Result<Tuple> {
         let m = Message {
+            id: 0,
+            action: MessageType::QueryTimeout(timeout),
+            tuple: Vec::new(),
+            template,
+        };
+        self.send_recv(m)
+    }
+
+    fn getall(&self, template: Template) -> Result<Vec<Tuple>> {
+        let m = Message {
+            id: 0,
             action: MessageType::Getall,
             tuple: Vec::new(),
             template,
         };
-        self.send(m)?;
-        self.recv_multiple()
+        self.send_recv_multiple(m)
     }
 
-    fn queryall(&self, template: Template) -> Result<Vec<Tuple>, std::io::Error> {
+    fn queryall(&self, template: Template) -> Result<Vec<Tuple>> {
         let m = Message {
-            action: MessageType::Getp,
+            id: 0,
+            action: MessageType::Queryall,
             tuple: Vec::new(),
             template,
         };
-        self.send(m)?;
-        self.recv_multiple()
+        self.send_recv_multiple(m)
+    }
+
+    fn batch(&self, operations: Vec<Operation>) -> Result<Vec<Vec<Tuple>>> {
+        let (steps, tuple, template) = batch::encode(operations);
+        let m = Message {
+            id: 0,
+            action: MessageType::Transaction(steps),
+            tuple,
+            template,
+        };
+        let reply = self.request(m)?;
+        match reply.action {
+            MessageType::Committed(counts) => batch::split(counts, reply.tuple),
+            action => Err(RemoteSpace::reply_error(action)),
+        }
     }
 }
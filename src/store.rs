@@ -0,0 +1,304 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::Path,
+    time::Instant,
+};
+
+use crate::{wal::Wal, Arity, Result, Template, TemplateType, Tuple, TupleField};
+
+/// Number of leading fields whose actual values are indexed
+const INDEXED_FIELDS: usize = 2;
+
+/// The keys a tuple is indexed under
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
+enum Key {
+    /// Number of fields in the tuple
+    Arity(usize),
+    /// Type of the first field
+    Type(&'static str),
+    /// Value of one of the leading fields
+    Value {
+        position: usize,
+        kind: &'static str,
+        hash: u64,
+    },
+}
+
+/// Tuple storage for local spaces.
+///
+/// Tuples are kept in insertion order under an increasing id, so the ordering guarantees of the
+/// space types are kept, while the indexes narrow down which tuples a template has to be tested against.
+///
+/// Tuples put with a time to live stay in the store until they are reaped, but are left out of
+/// everything else once they have expired.
+pub(crate) struct TupleStore {
+    tuples: BTreeMap<u64, Tuple>,
+    index: HashMap<Key, BTreeSet<u64>>,
+    next: u64,
+    /// When the tuples put with a time to live expire
+    expiries: HashMap<u64, Instant>,
+    /// Where the changes to a persistent store are recorded
+    log: Option<Wal>,
+}
+
+impl TupleStore {
+    pub fn new() -> TupleStore {
+        TupleStore {
+            tuples: BTreeMap::new(),
+            index: HashMap::new(),
+            next: 0,
+            expiries: HashMap::new(),
+            log: None,
+        }
+    }
+
+    /// Opens a store that keeps its tuples in the directory, with the tuples found there
+    pub fn open(dir: &Path) -> Result<TupleStore> {
+        let (log, recovered) = Wal::open(dir)?;
+        let mut store = TupleStore::new();
+        for (id, tuple) in recovered.tuples {
+            store.restore(id, tuple);
+        }
+        store.next = recovered.next;
+        store.expiries = recovered.expiries;
+        store.log = Some(log);
+        Ok(store)
+    }
+
+    /// Writes the changes made since the last commit to the log of a persistent store
+    pub fn commit(&mut self) -> Result<()> {
+        match &mut self.log {
+            Some(log) => log.commit(&self.tuples, &self.expiries),
+            None => Ok(()),
+        }
+    }
+
+    /// Forgets the changes made since the last commit, once the caller has undone them
+    pub fn discard(&mut self) {
+        if let Some(log) = &mut self.log {
+            log.discard();
+        }
+    }
+
+    /// Rewrites the snapshot of a persistent store and empties its log
+    pub fn compact(&mut self) -> Result<()> {
+        match &mut self.log {
+            Some(log) => log.compact(&self.tuples, &self.expiries),
+            None => Ok(()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self.expiries.is_empty() {
+            true => self.tuples.len(),
+            false => self.tuples().count(),
+        }
+    }
+
+    pub fn push(&mut self, tuple: Tuple) -> u64 {
+        let id = self.next;
+        self.next += 1;
+        self.restore(id, tuple);
+        id
+    }
+
+    /// Puts a removed tuple back under its old id, keeping its place in the order of the space
+    pub fn restore(&mut self, id: u64, tuple: Tuple) {
+        if let Some(log) = &mut self.log {
+            log.put(id, &tuple);
+        }
+        for key in tuple_keys(&tuple) {
+            self.index.entry(key).or_default().insert(id);
+        }
+        self.tuples.insert(id, tuple);
+    }
+
+    pub fn get(&self, id: u64) -> &Tuple {
+        &self.tuples[&id]
+    }
+
+    /// Whether the tuple is in the store and has not expired
+    pub fn contains(&self, id: u64) -> bool {
+        self.tuples.contains_key(&id) && self.live(id, Instant::now())
+    }
+
+    fn live(&self, id: u64, now: Instant) -> bool {
+        self.expiries.get(&id).is_none_or(|at| *at > now)
+    }
+
+    /// Makes the tuple expire at the given time, replacing any earlier expiry
+    pub fn expire(&mut self, id: u64, at: Instant) {
+        if let Some(log) = &mut self.log {
+            log.expire(id, at);
+        }
+        self.expiries.insert(id, at);
+    }
+
+    /// Makes the tuple expire at the time, or never
+    pub fn set_expiry(&mut self, id: u64, expiry: Option<Instant>) {
+        match expiry {
+            Some(at) => self.expire(id, at),
+            None => {
+                if let Some(log) = &mut self.log {
+                    log.keep(id);
+                }
+                self.expiries.remove(&id);
+            }
+        }
+    }
+
+    /// Whether the tuple was put with a time to live
+    pub fn expires(&self, id: u64) -> bool {
+        self.expiries.contains_key(&id)
+    }
+
+    /// When the tuple expires, if it was put with a time to live
+    pub fn expiry(&self, id: u64) -> Option<Instant> {
+        self.expiries.get(&id).copied()
+    }
+
+    /// When the next tuple expires
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.expiries.values().min().copied()
+    }
+
+    /// Removes the tuples that have expired, returning whether there were any
+    pub fn reap(&mut self) -> bool {
+        let now = Instant::now();
+        let expired: Vec<u64> = self
+            .expiries
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            self.remove(*id);
+        }
+        !expired.is_empty()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Tuple {
+        let tuple = self.tuples.remove(&id).expect("tuple id not in store");
+        self.expiries.remove(&id);
+        if let Some(log) = &mut self.log {
+            log.remove(id);
+        }
+        for key in tuple_keys(&tuple) {
+            if let Some(ids) = self.index.get_mut(&key) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.index.remove(&key);
+                }
+            }
+        }
+        tuple
+    }
+
+    /// Every tuple, oldest first
+    pub fn tuples(&self) -> impl Iterator<Item = &Tuple> {
+        let now = Instant::now();
+        self.tuples
+            .iter()
+            .filter(move |(id, _)| self.live(**id, now))
+            .map(|(_, tuple)| tuple)
+    }
+
+    /// The id of the oldest tuple
+    pub fn first(&self) -> Option<u64> {
+        let now = Instant::now();
+        self.tuples.keys().copied().find(|id| self.live(*id, now))
+    }
+
+    /// The id of the newest tuple
+    pub fn last(&self) -> Option<u64> {
+        let now = Instant::now();
+        self.tuples.keys().copied().rfind(|id| self.live(*id, now))
+    }
+
+    /// Returns the ids of the tuples that can match the template, oldest first.
+    ///
+    /// Without a template every tuple is a candidate. The candidates still have to be tested
+    /// against the template, as the indexes only rule out tuples that can not match.
+    pub fn candidates<'a>(
+        &'a self,
+        template: Option<&Template>,
+    ) -> Box<dyn DoubleEndedIterator<Item = u64> + 'a> {
+        let keys = match template {
+            Some(template) => template_keys(template),
+            None => Vec::new(),
+        };
+        let mut smallest: Option<&BTreeSet<u64>> = None;
+        for key in keys {
+            match self.index.get(&key) {
+                Some(ids) => {
+                    if smallest.is_none_or(|s| ids.len() < s.len()) {
+                        smallest = Some(ids);
+                    }
+                }
+                None => return Box::new(std::iter::empty()),
+            }
+        }
+        let ids: Box<dyn DoubleEndedIterator<Item = u64> + 'a> = match smallest {
+            Some(ids) => Box::new(ids.iter().copied()),
+            None => Box::new(self.tuples.keys().copied()),
+        };
+        if self.expiries.is_empty() {
+            return ids;
+        }
+        let now = Instant::now();
+        Box::new(ids.filter(move |id| self.live(*id, now)))
+    }
+}
+
+fn tuple_keys(tuple: &Tuple) -> Vec<Key> {
+    let mut keys = vec![Key::Arity(tuple.fields.len())];
+    if let Some(first) = tuple.fields.first() {
+        keys.push(Key::Type(first.typetag_name()));
+    }
+    for (position, field) in tuple.fields.iter().take(INDEXED_FIELDS).enumerate() {
+        if let Some(key) = value_key(position, &**field) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+/// The keys a tuple must be indexed under to match the template
+fn template_keys(template: &Template) -> Vec<Key> {
+    let mut keys = Vec::new();
+    if template.arity == Arity::Exact {
+        keys.push(Key::Arity(template.fields.len()));
+    }
+    if let Some((value, matching)) = template.fields.first() {
+        let same_type = matches!(
+            matching,
+            TemplateType::Actual
+                | TemplateType::Formal
+                | TemplateType::Greater
+                | TemplateType::GreaterEqual
+                | TemplateType::Less
+                | TemplateType::LessEqual
+                | TemplateType::Range { .. }
+                | TemplateType::StartsWith
+        );
+        if same_type {
+            keys.push(Key::Type(value.typetag_name()));
+        }
+    }
+    for (position, (value, matching)) in template.fields.iter().take(INDEXED_FIELDS).enumerate() {
+        if let TemplateType::Actual = matching {
+            if let Some(key) = value_key(position, &**value) {
+                keys.push(key);
+            }
+        }
+    }
+    keys
+}
+
+fn value_key(position: usize, field: &dyn TupleField) -> Option<Key> {
+    field.index_key().map(|hash| Key::Value {
+        position,
+        kind: field.typetag_name(),
+        hash,
+    })
+}
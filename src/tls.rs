@@ -0,0 +1,286 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rustls::{
+    client::WebPkiServerVerifier,
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+    server::WebPkiClientVerifier,
+    ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig, ServerConnection,
+};
+
+use crate::{transport::Stream, Error, Result};
+
+/// The certificate a gate proves its identity with, for `Repository::add_gate_tls`
+///
+/// # Example
+/// ```no_run
+/// # use rspaces::*;
+/// # use std::sync::Arc;
+/// let repo = Arc::new(Repository::new());
+/// let tls = TlsAcceptor::new(
+///     &std::fs::read("gate.pem").unwrap(),
+///     &std::fs::read("gate.key").unwrap(),
+/// )
+/// .unwrap();
+/// Repository::add_gate_tls(Arc::clone(&repo), String::from("gate"), "0.0.0.0:3800".to_string(), tls)
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsAcceptor {
+    /// Takes a PEM encoded certificate chain, starting with the certificate of the gate, and the
+    /// PEM encoded private key of that certificate
+    ///
+    /// # Errors
+    /// Returns `Error::Io` if the PEM can not be read and `Error::Tls` if the certificate or key
+    /// can not be used
+    pub fn new(cert_chain: &[u8], key: &[u8]) -> Result<TlsAcceptor> {
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?
+            .with_no_client_auth()
+            .with_single_cert(certificates(cert_chain)?, private_key(key)?)
+            .map_err(tls_error)?;
+        Ok(TlsAcceptor {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Like `new`, but clients must also present a certificate issued by one of the CAs in the
+    /// PEM bundle. Use `Authenticator::certificates` to map client certificates to principals.
+    ///
+    /// # Errors
+    /// Returns `Error::Tls` if the bundle holds no usable CA certificate
+    pub fn with_client_auth(
+        cert_chain: &[u8],
+        key: &[u8],
+        client_cas: &[u8],
+    ) -> Result<TlsAcceptor> {
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots(client_cas)?), provider())
+                .build()
+                .map_err(tls_error)?;
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certificates(cert_chain)?, private_key(key)?)
+            .map_err(tls_error)?;
+        Ok(TlsAcceptor {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Runs the server side of the TLS handshake over an accepted connection
+    pub(crate) fn accept(&self, stream: Box<dyn Stream>) -> Result<Box<dyn Stream>> {
+        let session = ServerConnection::new(Arc::clone(&self.config)).map_err(tls_error)?;
+        Ok(Box::new(TlsStream::handshake(stream, session.into())?))
+    }
+}
+
+/// The CAs a remote space trusts to identify gates, and optionally the certificate it
+/// identifies itself with
+///
+/// # Example
+/// ```no_run
+/// # use rspaces::*;
+/// let tls = TlsConnector::new(&std::fs::read("ca.pem").unwrap()).unwrap();
+/// let space = RemoteSpace::new_tls(String::from("localhost:3800/space"), tls).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+}
+
+impl TlsConnector {
+    /// Trusts gates with a certificate issued by one of the CAs in the PEM bundle
+    ///
+    /// # Errors
+    /// Returns `Error::Tls` if the bundle holds no usable CA certificate
+    pub fn new(ca_bundle: &[u8]) -> Result<TlsConnector> {
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?
+            .with_webpki_verifier(server_verifier(ca_bundle)?)
+            .with_no_client_auth();
+        Ok(TlsConnector {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Like `new`, but also presents the certificate to gates that authenticate their clients
+    pub fn with_certificate(
+        ca_bundle: &[u8],
+        cert_chain: &[u8],
+        key: &[u8],
+    ) -> Result<TlsConnector> {
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?
+            .with_webpki_verifier(server_verifier(ca_bundle)?)
+            .with_client_auth_cert(certificates(cert_chain)?, private_key(key)?)
+            .map_err(tls_error)?;
+        Ok(TlsConnector {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Runs the client side of the TLS handshake, checking the gate has a certificate for the host
+    pub(crate) fn connect(&self, stream: Box<dyn Stream>, host: &str) -> Result<Box<dyn Stream>> {
+        let name = ServerName::try_from(host.to_string()).map_err(tls_error)?;
+        let session = ClientConnection::new(Arc::clone(&self.config), name).map_err(tls_error)?;
+        Ok(Box::new(TlsStream::handshake(stream, session.into())?))
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn tls_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> Error {
+    Error::Tls(Box::new(e))
+}
+
+fn certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>> {
+    Ok(rustls_pemfile::certs(&mut &pem[..]).collect::<io::Result<_>>()?)
+}
+
+fn private_key(pem: &[u8]) -> Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut &pem[..])?
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "no private key found").into())
+}
+
+/// Reads the first certificate of a PEM bundle as DER
+pub(crate) fn certificate(pem: &[u8]) -> Result<Vec<u8>> {
+    match certificates(pem)?.into_iter().next() {
+        Some(cert) => Ok(cert.to_vec()),
+        None => Err(io::Error::new(ErrorKind::InvalidData, "no certificate found").into()),
+    }
+}
+
+fn roots(ca_bundle: &[u8]) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in certificates(ca_bundle)? {
+        roots.add(cert).map_err(tls_error)?;
+    }
+    Ok(roots)
+}
+
+fn server_verifier(ca_bundle: &[u8]) -> Result<Arc<WebPkiServerVerifier>> {
+    WebPkiServerVerifier::builder_with_provider(Arc::new(roots(ca_bundle)?), provider())
+        .build()
+        .map_err(tls_error)
+}
+
+/// A TLS session over another stream.
+///
+/// Every handle to the stream shares the session, which is only locked while encrypting or
+/// decrypting. Waiting for the peer happens without the lock, so one thread can block reading
+/// while others write.
+struct TlsStream {
+    socket: Box<dyn Stream>,
+    session: Arc<Mutex<Connection>>,
+}
+
+impl TlsStream {
+    fn handshake(mut socket: Box<dyn Stream>, mut session: Connection) -> Result<TlsStream> {
+        while session.is_handshaking() {
+            if let Err(e) = session.complete_io(&mut socket) {
+                // Rustls reports failed handshakes as io errors wrapping the reason
+                return match e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()) {
+                    Some(reason) => Err(Error::Tls(Box::new(reason.clone()))),
+                    None => Err(e.into()),
+                };
+            }
+        }
+        Ok(TlsStream {
+            socket,
+            session: Arc::new(Mutex::new(session)),
+        })
+    }
+
+    /// Sends the records the session has queued for the peer
+    fn send_records(session: &mut Connection, mut socket: &mut dyn Stream) -> io::Result<()> {
+        while session.wants_write() {
+            session.write_tls(&mut socket)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.session.lock().unwrap().reader().read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            let mut chunk = [0; 4096];
+            let n = self.socket.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let mut session = self.session.lock().unwrap();
+            let mut records = &chunk[..n];
+            while !records.is_empty() {
+                session.read_tls(&mut records)?;
+                session
+                    .process_new_packets()
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            }
+            // Records like key updates are answered right away
+            TlsStream::send_records(&mut session, &mut *self.socket)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut session = self.session.lock().unwrap();
+        let n = session.writer().write(buf)?;
+        TlsStream::send_records(&mut session, &mut *self.socket)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut session = self.session.lock().unwrap();
+        session.writer().flush()?;
+        TlsStream::send_records(&mut session, &mut *self.socket)?;
+        self.socket.flush()
+    }
+}
+
+impl Stream for TlsStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(TlsStream {
+            socket: self.socket.try_clone()?,
+            session: Arc::clone(&self.session),
+        }))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        {
+            let mut session = self.session.lock().unwrap();
+            session.send_close_notify();
+            let _ = TlsStream::send_records(&mut session, &mut *self.socket);
+        }
+        self.socket.shutdown()
+    }
+
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        let session = self.session.lock().unwrap();
+        let certs = session.peer_certificates()?;
+        certs.first().map(|cert| cert.to_vec())
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+use crate::{ConnectOptions, Endpoint, Error, Result, UriError};
+
+/// A connection between a remote space and a gate.
+///
+/// Both ends read from one handle in a thread of their own while writing through another, so
+/// every transport must be able to hand out a second handle to the same connection.
+pub(crate) trait Stream: Read + Write + Send {
+    /// Another handle to the same connection
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>>;
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Closes the connection, waking up any thread blocked reading from it
+    fn shutdown(&mut self) -> io::Result<()>;
+
+    /// The certificate the peer authenticated with, as DER
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+impl Stream for TcpStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(UnixStream::try_clone(self)?))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+/// Where a gate waits for clients
+pub(crate) trait Listener: Send {
+    /// Takes the next waiting client, or fails with `WouldBlock` if none is waiting
+    fn accept(&self) -> io::Result<Box<dyn Stream>>;
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> io::Result<Box<dyn Stream>> {
+        let (stream, _) = TcpListener::accept(self)?;
+        stream.set_nonblocking(false)?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// A listening unix socket, which removes its socket file when the gate closes
+#[cfg(unix)]
+struct UnixSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    fn bind(path: &Path) -> io::Result<UnixSocket> {
+        let listener = match UnixListener::bind(path) {
+            // The socket may be left over from a gate that did not close, which nobody answers
+            // on. Other files are never removed.
+            Err(e)
+                if e.kind() == ErrorKind::AddrInUse
+                    && std::fs::symlink_metadata(path)?.file_type().is_socket()
+                    && UnixStream::connect(path).is_err() =>
+            {
+                std::fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            listener => listener?,
+        };
+        listener.set_nonblocking(true)?;
+        Ok(UnixSocket {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixSocket {
+    fn accept(&self) -> io::Result<Box<dyn Stream>> {
+        let (stream, _) = self.listener.accept()?;
+        stream.set_nonblocking(false)?;
+        Ok(Box::new(stream))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Starts listening on the endpoint. For `tls://` endpoints this only binds the port, TLS is
+/// set up by the gate after accepting a client.
+pub(crate) fn listen(endpoint: &Endpoint) -> Result<Box<dyn Listener>> {
+    match endpoint {
+        Endpoint::Tcp(addr) | Endpoint::Tls(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+            Ok(Box::new(listener))
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => Ok(Box::new(UnixSocket::bind(path)?)),
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => Err(unsupported("unix sockets are only supported on unix")),
+    }
+}
+
+/// Connects to the gate at the endpoint, over TLS if the options carry a connector
+pub(crate) fn connect(endpoint: &Endpoint, options: &ConnectOptions) -> Result<Box<dyn Stream>> {
+    let addr = match endpoint {
+        Endpoint::Tcp(addr) | Endpoint::Tls(addr) => addr,
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            #[cfg(feature = "tls")]
+            if options.tls.is_some() {
+                return Err(unsupported("TLS is not supported over unix sockets"));
+            }
+            let stream = UnixStream::connect(path)?;
+            stream.set_read_timeout(options.timeout)?;
+            return Ok(Box::new(stream));
+        }
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => return Err(unsupported("unix sockets are only supported on unix")),
+    };
+    let stream = match options.timeout {
+        Some(timeout) => connect_timeout(addr, timeout)?,
+        None => TcpStream::connect(addr)?,
+    };
+    stream.set_nonblocking(false)?;
+    // Covers the TLS handshake and the welcome, the caller lifts it once connected
+    stream.set_read_timeout(options.timeout)?;
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &options.tls {
+        return tls.connect(Box::new(stream), host(addr));
+    }
+    match endpoint {
+        Endpoint::Tls(_) => Err(unsupported(
+            "tls:// addresses need a TlsConnector in the connect options",
+        )),
+        _ => Ok(Box::new(stream)),
+    }
+}
+
+/// Tries every address the host resolves to in turn
+fn connect_timeout(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut error = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error.unwrap_or_else(|| io::Error::new(ErrorKind::NotFound, "host has no addresses")))
+}
+
+/// The host of an address like `localhost:3800` or `[::1]:3800`, as named in certificates
+#[cfg(feature = "tls")]
+fn host(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+pub(crate) fn unsupported(reason: &str) -> Error {
+    Error::InvalidAddress(UriError::UnsupportedScheme(reason.to_string()))
+}
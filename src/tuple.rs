@@ -1,8 +1,8 @@
-use std::{any::Any, fmt::Debug};
+use std::{any::Any, cmp::Ordering, fmt::Debug};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{implement_tuplefield_for, TemplateType};
+use crate::{implement_tuplefield_for, FieldError, TemplateType};
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Tuple {
@@ -31,10 +31,10 @@ impl Tuple {
         Tuple { fields }
     }
     /// Get the value from a field of a tuple. Need to be passed the expected datatype in order for a cast.
-    /// Will return None if the expected type is not equal to the actual. Othwerwise it will return Some(value)
     ///
     /// # Panic
     /// Function panics if index is not valid in the tuple or if the type supplied is not equal of that of the field.
+    /// Use `try_get_field` to handle these cases instead.
     ///
     /// # Example
     /// ```
@@ -49,35 +49,190 @@ impl Tuple {
     ///
     /// ```
     pub fn get_field<T: 'static>(&self, index: usize) -> &T {
-        let b = (*(*self.fields.get(index).expect("Wrong index")))
+        match self.try_get_field::<T>(index) {
+            Ok(field) => field,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Get the value from a field of a tuple without panicking.
+    ///
+    /// # Errors
+    /// Returns `FieldError::OutOfBounds` if the index is not valid in the tuple and
+    /// `FieldError::TypeMismatch` if the field holds another type than `T`.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// let tuple = new_tuple!(5, 'a');
+    ///
+    /// assert_eq!(Ok(&5), tuple.try_get_field::<i32>(0));
+    /// assert!(tuple.try_get_field::<u64>(0).is_err());
+    /// assert!(tuple.try_get_field::<char>(2).is_err());
+    /// ```
+    pub fn try_get_field<T: 'static>(&self, index: usize) -> Result<&T, FieldError> {
+        let field = self.fields.get(index).ok_or(FieldError::OutOfBounds {
+            index,
+            len: self.fields.len(),
+        })?;
+        field
             .as_any()
-            .downcast_ref::<T>();
-        return b.unwrap();
+            .downcast_ref::<T>()
+            .ok_or_else(|| FieldError::TypeMismatch {
+                index,
+                expected: std::any::type_name::<T>(),
+                found: field.typetag_name(),
+            })
+    }
+
+    /// Converts the tuple into a rust tuple of the given types.
+    ///
+    /// # Errors
+    /// Returns `FieldError::ArityMismatch` if the number of fields differ and
+    /// `FieldError::TypeMismatch` if any of the fields holds another type.
+    ///
+    /// # Example
+    /// ```
+    /// # use rspaces::*;
+    /// let tuple = new_tuple!(5, String::from("name"));
+    ///
+    /// let (id, name): (i32, String) = tuple.into_typed().unwrap();
+    /// assert_eq!(5, id);
+    /// assert_eq!("name", name);
+    /// ```
+    pub fn into_typed<T: FromTuple>(self) -> Result<T, FieldError> {
+        T::from_tuple(self)
+    }
+
+    /// Returns the number of fields in the tuple
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Returns true if the tuple has no fields
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+/// Conversion from a tuple into a typed rust value, implemented for rust tuples of up to 12 fields
+pub trait FromTuple: Sized {
+    fn from_tuple(tuple: Tuple) -> Result<Self, FieldError>;
+}
+
+/// Conversion from a typed rust value into a tuple, implemented for rust tuples of up to 12 fields
+///
+/// Rust tuples can be put directly in a space, and also convert with `Into<Tuple>`
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// # let space = LocalSpace::new_sequential();
+/// space.put((5, 'a')).unwrap();
+///
+/// let tuple = space.getp(new_template!(5.actual(), 'a'.formal())).unwrap();
+/// assert_eq!((5, 'a'), tuple.into_typed().unwrap());
+/// ```
+pub trait IntoTuple {
+    fn into_tuple(self) -> Tuple;
+}
+
+impl IntoTuple for Tuple {
+    fn into_tuple(self) -> Tuple {
+        self
     }
 }
 
+/// Moves the field out of a tuple being converted into a rust tuple
+fn take_field<T: 'static>(index: usize, field: Box<dyn TupleField>) -> Result<T, FieldError> {
+    let found = field.typetag_name();
+    let field: Box<dyn Any> = field;
+    field
+        .downcast::<T>()
+        .map(|field| *field)
+        .map_err(|_| FieldError::TypeMismatch {
+            index,
+            expected: std::any::type_name::<T>(),
+            found,
+        })
+}
+
+macro_rules! implement_typed_tuple {
+    ( $len:expr; $( $name:ident $field:ident $index:tt ),+ ) => {
+        impl<$( $name: TupleField + 'static ),+> FromTuple for ($( $name, )+) {
+            fn from_tuple(tuple: Tuple) -> Result<Self, FieldError> {
+                let found = tuple.len();
+                let [$( $field ),+]: [Box<dyn TupleField>; $len] = tuple
+                    .fields
+                    .try_into()
+                    .map_err(|_| FieldError::ArityMismatch {
+                        expected: $len,
+                        found,
+                    })?;
+                Ok(($( take_field::<$name>($index, $field)?, )+))
+            }
+        }
+
+        impl<$( $name: TupleField + 'static ),+> IntoTuple for ($( $name, )+) {
+            fn into_tuple(self) -> Tuple {
+                Tuple::new(vec![$( Box::new(self.$index) ),+])
+            }
+        }
+
+        impl<$( $name: TupleField + 'static ),+> From<($( $name, )+)> for Tuple {
+            fn from(t: ($( $name, )+)) -> Tuple {
+                t.into_tuple()
+            }
+        }
+    };
+}
+
+implement_typed_tuple!(1; A a 0);
+implement_typed_tuple!(2; A a 0, B b 1);
+implement_typed_tuple!(3; A a 0, B b 1, C c 2);
+implement_typed_tuple!(4; A a 0, B b 1, C c 2, D d 3);
+implement_typed_tuple!(5; A a 0, B b 1, C c 2, D d 3, E e 4);
+implement_typed_tuple!(6; A a 0, B b 1, C c 2, D d 3, E e 4, F f 5);
+implement_typed_tuple!(7; A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6);
+implement_typed_tuple!(8; A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6, H h 7);
+implement_typed_tuple!(9; A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6, H h 7, I i 8);
+implement_typed_tuple!(10; A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6, H h 7, I i 8, J j 9);
+implement_typed_tuple!(11; A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6, H h 7, I i 8, J j 9, K k 10);
+implement_typed_tuple!(12; A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6, H h 7, I i 8, J j 9, K k 10, L l 11);
+
 #[typetag::serde(tag = "field")]
-pub trait TupleField: Send + Sync + std::fmt::Debug {
+pub trait TupleField: Any + Send + Sync + std::fmt::Debug {
     fn as_any(&self) -> &dyn Any;
     fn box_clone(&self) -> Box<dyn TupleField>;
+    #[allow(clippy::borrowed_box)]
     fn query(&self, element: &Box<dyn TupleField>, matching: &TemplateType) -> bool;
+    /// Compares the element to this field, returning how the element orders relative to it.
+    /// Returns None for types without an ordering or if the element is of another type.
+    fn compare(&self, _element: &dyn TupleField) -> Option<Ordering> {
+        None
+    }
+    /// Returns a hash of the value used by local spaces to index tuples on their leading fields.
+    /// Equal values must return the same key. Returns None for types that can not be indexed.
+    fn index_key(&self) -> Option<u64> {
+        None
+    }
 }
 
 //Impl blocks as serde typetag wont allow for generic
-implement_tuplefield_for!(i8);
-implement_tuplefield_for!(i16);
-implement_tuplefield_for!(i32);
-implement_tuplefield_for!(i64);
-implement_tuplefield_for!(i128);
-implement_tuplefield_for!(u8);
-implement_tuplefield_for!(u16);
-implement_tuplefield_for!(u32);
-implement_tuplefield_for!(u64);
-implement_tuplefield_for!(u128);
-implement_tuplefield_for!(usize);
-implement_tuplefield_for!(isize);
-implement_tuplefield_for!(f32);
-implement_tuplefield_for!(f64);
-implement_tuplefield_for!(char);
-implement_tuplefield_for!(String);
-implement_tuplefield_for!(bool);
+implement_tuplefield_for!(i8, ordered, hashed);
+implement_tuplefield_for!(i16, ordered, hashed);
+implement_tuplefield_for!(i32, ordered, hashed);
+implement_tuplefield_for!(i64, ordered, hashed);
+implement_tuplefield_for!(i128, ordered, hashed);
+implement_tuplefield_for!(u8, ordered, hashed);
+implement_tuplefield_for!(u16, ordered, hashed);
+implement_tuplefield_for!(u32, ordered, hashed);
+implement_tuplefield_for!(u64, ordered, hashed);
+implement_tuplefield_for!(u128, ordered, hashed);
+implement_tuplefield_for!(usize, ordered, hashed);
+implement_tuplefield_for!(isize, ordered, hashed);
+implement_tuplefield_for!(f32, ordered);
+implement_tuplefield_for!(f64, ordered);
+implement_tuplefield_for!(char, ordered, hashed);
+implement_tuplefield_for!(String, ordered, hashed);
+implement_tuplefield_for!(bool, ordered, hashed);
@@ -0,0 +1,264 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr, time::Duration};
+
+use crate::{Codec, ConnectOptions};
+
+/// Where a gate listens, and where remote spaces connect to it
+///
+/// Written as `tcp://host:port`, `tls://host:port` or `unix:///path/to/socket`. An address
+/// without a scheme, like `127.0.0.1:3800`, is a tcp address. IPv6 hosts are written in
+/// brackets, as in `tcp://[::1]:3800`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(String),
+    Tls(String),
+    Unix(PathBuf),
+}
+
+/// The address of a space on a gate, with options for connecting to it
+///
+/// The endpoint of the gate is followed by `/` and the name of the space, and optionally by
+/// `?` and `&` separated options: `codec` (`json` or `msgpack`), `timeout` for connecting and
+/// greeting the gate (like `500ms`, `5s` or `1m`) and `client_id`.
+///
+/// # Example
+/// ```
+/// # use rspaces::*;
+/// # use std::time::Duration;
+/// let uri: SpaceUri = "tcp://[::1]:3800/jobs?codec=msgpack&timeout=5s".parse().unwrap();
+/// assert_eq!(Endpoint::Tcp(String::from("[::1]:3800")), uri.endpoint);
+/// assert_eq!("jobs", uri.space);
+/// assert_eq!(Some(Codec::MessagePack), uri.codec);
+/// assert_eq!(Some(Duration::from_secs(5)), uri.timeout);
+///
+/// let uri: SpaceUri = "unix:///tmp/rspaces.sock/jobs".parse().unwrap();
+/// assert_eq!(Endpoint::Unix("/tmp/rspaces.sock".into()), uri.endpoint);
+///
+/// assert!(matches!(
+///     "127.0.0.1:3800".parse::<SpaceUri>(),
+///     Err(UriError::MissingSpace)
+/// ));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpaceUri {
+    pub endpoint: Endpoint,
+    pub space: String,
+    pub codec: Option<Codec>,
+    pub timeout: Option<Duration>,
+    pub client_id: Option<String>,
+}
+
+/// Why a connection string was refused
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UriError {
+    /// The scheme is not `tcp`, `tls` or `unix`
+    UnknownScheme(String),
+    /// The scheme is known, but can not be used here
+    UnsupportedScheme(String),
+    /// The host is empty, or an IPv6 address without brackets
+    InvalidHost(String),
+    /// The port is missing or not a number below 65536
+    InvalidPort(String),
+    /// A unix address has no socket path
+    MissingPath,
+    /// The address of a remote space does not end in the name of a space
+    MissingSpace,
+    /// The address of a gate names a space
+    UnexpectedSpace(String),
+    UnknownOption(String),
+    InvalidOption {
+        name: String,
+        value: String,
+    },
+}
+
+impl Display for UriError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UriError::UnknownScheme(s) => write!(f, "unknown scheme {}", s),
+            UriError::UnsupportedScheme(s) => write!(f, "{}", s),
+            UriError::InvalidHost(s) => write!(f, "invalid host {:?}", s),
+            UriError::InvalidPort(s) => write!(f, "invalid port {:?}", s),
+            UriError::MissingPath => write!(f, "unix address without a socket path"),
+            UriError::MissingSpace => write!(f, "no space name after the address"),
+            UriError::UnexpectedSpace(s) => write!(f, "gate address names space {}", s),
+            UriError::UnknownOption(s) => write!(f, "unknown option {}", s),
+            UriError::InvalidOption { name, value } => {
+                write!(f, "invalid value {:?} for option {}", value, name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UriError {}
+
+impl Endpoint {
+    /// Splits off the scheme and parses the rest up to the end of the endpoint, returning what
+    /// follows it. For unix addresses the caller decides where the path ends.
+    fn split(s: &str) -> Result<(Endpoint, &str), UriError> {
+        let (scheme, rest) = match s.split_once("://") {
+            Some((scheme, rest)) => (scheme, rest),
+            None => ("tcp", s),
+        };
+        match scheme {
+            "tcp" | "tls" => {
+                let (authority, rest) = match rest.find('/') {
+                    Some(i) => rest.split_at(i),
+                    None => (rest, ""),
+                };
+                let addr = Endpoint::authority(authority)?;
+                match scheme {
+                    "tcp" => Ok((Endpoint::Tcp(addr), rest)),
+                    _ => Ok((Endpoint::Tls(addr), rest)),
+                }
+            }
+            "unix" => Ok((Endpoint::Unix(PathBuf::new()), rest)),
+            scheme => Err(UriError::UnknownScheme(scheme.to_string())),
+        }
+    }
+
+    /// Checks a `host:port` pair
+    fn authority(authority: &str) -> Result<String, UriError> {
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(rest) => match rest.split_once("]:") {
+                Some((host, port)) => (host, port),
+                None => return Err(UriError::InvalidPort(authority.to_string())),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, _)) if host.contains(':') => {
+                    return Err(UriError::InvalidHost(host.to_string()))
+                }
+                Some((host, port)) => (host, port),
+                None => return Err(UriError::InvalidPort(authority.to_string())),
+            },
+        };
+        if host.is_empty() {
+            return Err(UriError::InvalidHost(host.to_string()));
+        }
+        if port.parse::<u16>().is_err() {
+            return Err(UriError::InvalidPort(port.to_string()));
+        }
+        Ok(authority.to_string())
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = UriError;
+
+    fn from_str(s: &str) -> Result<Endpoint, UriError> {
+        match Endpoint::split(s)? {
+            (Endpoint::Unix(_), "") => Err(UriError::MissingPath),
+            (Endpoint::Unix(_), path) => Ok(Endpoint::Unix(PathBuf::from(path))),
+            (endpoint, "") => Ok(endpoint),
+            (_, rest) => Err(UriError::UnexpectedSpace(rest[1..].to_string())),
+        }
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Endpoint::Tls(addr) => write!(f, "tls://{}", addr),
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+impl SpaceUri {
+    /// Overrides the options with the ones given in the uri
+    pub(crate) fn apply(&self, mut options: ConnectOptions) -> ConnectOptions {
+        if let Some(codec) = self.codec {
+            options.codec = codec;
+        }
+        if let Some(timeout) = self.timeout {
+            options.timeout = Some(timeout);
+        }
+        if let Some(client_id) = &self.client_id {
+            options.client_id = Some(client_id.clone());
+        }
+        options
+    }
+
+    fn option(&mut self, name: &str, value: &str) -> Result<(), UriError> {
+        let invalid = || UriError::InvalidOption {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        match name {
+            "codec" => self.codec = Some(Codec::from_name(value).ok_or_else(invalid)?),
+            "timeout" => self.timeout = Some(parse_duration(value).ok_or_else(invalid)?),
+            "client_id" if !value.is_empty() => self.client_id = Some(value.to_string()),
+            "client_id" => return Err(invalid()),
+            name => return Err(UriError::UnknownOption(name.to_string())),
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SpaceUri {
+    type Err = UriError;
+
+    fn from_str(s: &str) -> Result<SpaceUri, UriError> {
+        let (address, query) = match s.split_once('?') {
+            Some((address, query)) => (address, Some(query)),
+            None => (s, None),
+        };
+        let (endpoint, space) = match Endpoint::split(address)? {
+            // The socket path is everything before the last segment
+            (Endpoint::Unix(_), rest) => match rest.rsplit_once('/') {
+                Some(("", _)) | None => return Err(UriError::MissingPath),
+                Some((path, space)) => (Endpoint::Unix(PathBuf::from(path)), space),
+            },
+            (endpoint, rest) => (endpoint, rest.strip_prefix('/').unwrap_or_default()),
+        };
+        if space.is_empty() {
+            return Err(UriError::MissingSpace);
+        }
+        let mut uri = SpaceUri {
+            endpoint,
+            space: space.to_string(),
+            codec: None,
+            timeout: None,
+            client_id: None,
+        };
+        let options = query.into_iter().flat_map(|q| q.split('&'));
+        for option in options.filter(|o| !o.is_empty()) {
+            let (name, value) = option.split_once('=').unwrap_or((option, ""));
+            uri.option(name, value)?;
+        }
+        Ok(uri)
+    }
+}
+
+impl Display for SpaceUri {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.endpoint, self.space)?;
+        let mut options = Vec::new();
+        if let Some(codec) = self.codec {
+            options.push(format!("codec={}", codec.name()));
+        }
+        if let Some(timeout) = self.timeout {
+            options.push(format!("timeout={}ms", timeout.as_millis()));
+        }
+        if let Some(client_id) = &self.client_id {
+            options.push(format!("client_id={}", client_id));
+        }
+        if !options.is_empty() {
+            write!(f, "?{}", options.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a whole number of milliseconds, seconds, minutes or hours, like `500ms` or `5s`
+fn parse_duration(s: &str) -> Option<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit())?;
+    let amount: u64 = s[..split].parse().ok()?;
+    match &s[split..] {
+        "ms" => Some(Duration::from_millis(amount)),
+        "s" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_secs(amount.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(amount.checked_mul(3600)?)),
+        _ => None,
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result, Tuple};
+
+/// The log is compacted once it holds this many records, and twice as many as the space
+/// holds tuples
+const COMPACT_AFTER: usize = 1000;
+
+const LOG: &str = "log";
+const SNAPSHOT: &str = "snapshot";
+
+/// A change to a persistent space. The changes of an operation are stored together, as a json
+/// array on a line of their own.
+#[derive(Serialize, Deserialize)]
+enum Record<T> {
+    /// The tuple was put under the id, which orders it among the other tuples
+    Put {
+        id: u64,
+        tuple: T,
+    },
+    Remove {
+        id: u64,
+    },
+    /// The tuple expires at the time, in milliseconds since the unix epoch
+    Expire {
+        id: u64,
+        at: u64,
+    },
+    /// The tuple no longer expires
+    Keep {
+        id: u64,
+    },
+}
+
+/// The write-ahead log of a persistent space.
+///
+/// The changes of an operation are appended to the log as a single line and synced to disk
+/// before anyone sees them, so a crash never leaves part of an operation behind. Records that
+/// would not be read back are refused. Compaction writes the tuples of the space to a new
+/// snapshot, which replaces the old one at once, and then empties the log.
+pub(crate) struct Wal {
+    dir: PathBuf,
+    log: File,
+    /// Bytes of whole lines in the log
+    len: u64,
+    /// Records of the operation in progress separated by commas, written when it commits
+    pending: Vec<u8>,
+    /// How many records are pending
+    uncommitted: usize,
+    /// A record that could not be encoded, reported when the operation commits
+    unencodable: Option<serde_json::Error>,
+    /// Records in the log since it was last compacted
+    records: usize,
+}
+
+/// The tuples of a persistent space as found on disk
+pub(crate) struct Recovered {
+    pub(crate) tuples: BTreeMap<u64, Tuple>,
+    pub(crate) expiries: HashMap<u64, Instant>,
+    /// The id after the highest one ever used
+    pub(crate) next: u64,
+}
+
+impl Wal {
+    /// Opens the log in the directory, creating both if missing, and replays the snapshot and
+    /// the log. An operation cut off by a crash at the end of the log is dropped as a whole.
+    pub(crate) fn open(dir: &Path) -> Result<(Wal, Recovered)> {
+        fs::create_dir_all(dir)?;
+        let mut recovered = Recovered {
+            tuples: BTreeMap::new(),
+            expiries: HashMap::new(),
+            next: 0,
+        };
+        replay(&dir.join(SNAPSHOT), &mut recovered)?;
+        let (records, intact) = replay(&dir.join(LOG), &mut recovered)?;
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG))?;
+        log.set_len(intact)?;
+        let wal = Wal {
+            dir: dir.to_path_buf(),
+            log,
+            len: intact,
+            pending: Vec::new(),
+            uncommitted: 0,
+            unencodable: None,
+            records,
+        };
+        Ok((wal, recovered))
+    }
+
+    pub(crate) fn put(&mut self, id: u64, tuple: &Tuple) {
+        self.record(&Record::Put { id, tuple });
+    }
+
+    pub(crate) fn remove(&mut self, id: u64) {
+        self.record(&Record::<&Tuple>::Remove { id });
+    }
+
+    pub(crate) fn expire(&mut self, id: u64, at: Instant) {
+        self.record(&Record::<&Tuple>::Expire {
+            id,
+            at: wall_clock(at),
+        });
+    }
+
+    pub(crate) fn keep(&mut self, id: u64) {
+        self.record(&Record::<&Tuple>::Keep { id });
+    }
+
+    fn record(&mut self, record: &Record<&Tuple>) {
+        let start = self.pending.len();
+        if start > 0 {
+            self.pending.push(b',');
+        }
+        let at = self.pending.len();
+        let encoded = serde_json::to_writer(&mut self.pending, record).and_then(|()| {
+            // Json has no infinite or NaN floats, which are written as null and then can not
+            // be read back, so the log would be refused when the space is opened again
+            serde_json::from_slice::<Record<Tuple>>(&self.pending[at..])
+                .map(|_| ())
+                .map_err(|e| {
+                    serde::ser::Error::custom(format!("the log could not be read back: {}", e))
+                })
+        });
+        match encoded {
+            Ok(()) => self.uncommitted += 1,
+            Err(e) => {
+                self.pending.truncate(start);
+                self.unencodable.get_or_insert(e);
+            }
+        }
+    }
+
+    /// Appends the records of the operation to the log as one line and syncs it, compacting it
+    /// if it has grown large. If the line can not be written the log is left as it was.
+    pub(crate) fn commit(
+        &mut self,
+        tuples: &BTreeMap<u64, Tuple>,
+        expiries: &HashMap<u64, Instant>,
+    ) -> Result<()> {
+        if let Some(e) = self.unencodable.take() {
+            self.discard();
+            return Err(e.into());
+        }
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut line = Vec::with_capacity(self.pending.len() + 3);
+        line.push(b'[');
+        line.extend_from_slice(&self.pending);
+        line.extend_from_slice(b"]\n");
+        let written = self
+            .log
+            .write_all(&line)
+            .and_then(|()| self.log.sync_data());
+        if let Err(e) = written {
+            // Lines after one cut off halfway would not be read back
+            let _ = self.log.set_len(self.len);
+            self.discard();
+            return Err(e.into());
+        }
+        self.len += line.len() as u64;
+        self.records += self.uncommitted;
+        self.discard();
+        // The records are in the log already, so a compaction that fails is left to a later one
+        if self.records > COMPACT_AFTER && self.records > 2 * tuples.len() {
+            let _ = self.compact(tuples, expiries);
+        }
+        Ok(())
+    }
+
+    /// Drops the records of the operation in progress, which was undone
+    pub(crate) fn discard(&mut self) {
+        self.pending.clear();
+        self.uncommitted = 0;
+        self.unencodable = None;
+    }
+
+    /// Replaces the snapshot with the tuples and empties the log
+    pub(crate) fn compact(
+        &mut self,
+        tuples: &BTreeMap<u64, Tuple>,
+        expiries: &HashMap<u64, Instant>,
+    ) -> Result<()> {
+        let mut snapshot = Vec::new();
+        for (id, tuple) in tuples {
+            let mut records = vec![Record::Put { id: *id, tuple }];
+            if let Some(at) = expiries.get(id) {
+                let at = wall_clock(*at);
+                records.push(Record::Expire { id: *id, at });
+            }
+            serde_json::to_writer(&mut snapshot, &records)?;
+            snapshot.push(b'\n');
+        }
+        let partial = self.dir.join("snapshot.tmp");
+        let mut file = File::create(&partial)?;
+        file.write_all(&snapshot)?;
+        file.sync_all()?;
+        fs::rename(&partial, self.dir.join(SNAPSHOT))?;
+        // Replaying the log again over the new snapshot leaves the same tuples, so a crash
+        // before it is emptied loses nothing
+        self.log.set_len(0)?;
+        self.len = 0;
+        self.records = 0;
+        Ok(())
+    }
+}
+
+/// Applies the records in the file, returning how many there were and how many bytes of the
+/// file hold whole lines
+fn replay(path: &Path, recovered: &mut Recovered) -> Result<(usize, u64)> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e.into()),
+    };
+    let mut records = 0;
+    let mut lines = 0;
+    let mut intact = 0;
+    // Only lines ending in a newline were written in full
+    while let Some(end) = bytes[intact..].iter().position(|b| *b == b'\n') {
+        let line = &bytes[intact..intact + end];
+        match serde_json::from_slice::<Vec<Record<Tuple>>>(line) {
+            Ok(operation) => {
+                records += operation.len();
+                for record in operation {
+                    apply(record, recovered);
+                }
+            }
+            Err(e) => {
+                return Err(Error::Io(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {} of {} is corrupt: {}", lines + 1, path.display(), e),
+                )))
+            }
+        }
+        lines += 1;
+        intact += end + 1;
+    }
+    Ok((records, intact as u64))
+}
+
+fn apply(record: Record<Tuple>, recovered: &mut Recovered) {
+    match record {
+        Record::Put { id, tuple } => {
+            recovered.tuples.insert(id, tuple);
+            recovered.next = recovered.next.max(id + 1);
+        }
+        Record::Remove { id } => {
+            recovered.tuples.remove(&id);
+            recovered.expiries.remove(&id);
+            recovered.next = recovered.next.max(id + 1);
+        }
+        Record::Expire { id, at } => {
+            if recovered.tuples.contains_key(&id) {
+                match instant(at) {
+                    Some(at) => recovered.expiries.insert(id, at),
+                    None => recovered.expiries.remove(&id),
+                };
+            }
+        }
+        Record::Keep { id } => {
+            recovered.expiries.remove(&id);
+        }
+    }
+}
+
+/// The time as milliseconds since the unix epoch, which unlike an instant outlives the process.
+/// Times too far off to be represented are written as the last millisecond.
+fn wall_clock(at: Instant) -> u64 {
+    match SystemTime::now().checked_add(at.saturating_duration_since(Instant::now())) {
+        Some(at) => {
+            let since = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+            u64::try_from(since.as_millis()).unwrap_or(u64::MAX)
+        }
+        None => u64::MAX,
+    }
+}
+
+/// The instant of a time written by `wall_clock`, which is now if the time has passed, or None
+/// if the time is too far off to be represented
+fn instant(at: u64) -> Option<Instant> {
+    let at = UNIX_EPOCH.checked_add(Duration::from_millis(at))?;
+    Instant::now().checked_add(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
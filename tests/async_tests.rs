@@ -0,0 +1,489 @@
+#![cfg(feature = "tokio")]
+
+use rspaces::{
+    asynchronous::{AsyncRemoteSpace, AsyncSpace},
+    new_template, new_tuple, Authenticator, Codec, ConnectOptions, Credentials, Error, ErrorCode,
+    FieldType, LocalSpace, Operation, Permissions, Rejection, RemoteSpace, Repository, Space,
+    SpaceEvent, Template, Tuple, TupleField,
+};
+use std::{sync::Arc, thread, time::Duration};
+
+fn repo_with_space() -> (Arc<Repository>, Arc<LocalSpace>) {
+    let repo = Arc::new(Repository::new());
+    let space = Arc::new(LocalSpace::new_sequential());
+    repo.add_space(String::from("space"), Arc::clone(&space));
+    (repo, space)
+}
+
+#[tokio::test]
+async fn local_waiters_do_not_block_the_runtime() {
+    let space = Arc::new(LocalSpace::new_sequential());
+    let waiters: Vec<_> = (0..1000)
+        .map(|i| {
+            let space = Arc::clone(&space);
+            tokio::spawn(async move {
+                let t = AsyncSpace::get(&*space, new_template!(i.actual()))
+                    .await
+                    .unwrap();
+                *t.get_field::<i32>(0)
+            })
+        })
+        .collect();
+    tokio::task::yield_now().await;
+    for i in (0..1000).rev() {
+        AsyncSpace::put(&*space, new_tuple!(i)).await.unwrap();
+    }
+    for (i, waiter) in waiters.into_iter().enumerate() {
+        assert_eq!(i as i32, waiter.await.unwrap());
+    }
+    assert!(space.is_empty());
+}
+
+#[tokio::test]
+async fn blocking_put_wakes_async_waiter() {
+    let space = Arc::new(LocalSpace::new_sequential());
+    let writer = Arc::clone(&space);
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        Space::put(&*writer, new_tuple!(5, 'a')).unwrap();
+    });
+    let t = AsyncSpace::query(&*space, new_template!(5.actual(), 'a'.formal()))
+        .await
+        .unwrap();
+    assert_eq!('a', *t.get_field::<char>(1));
+    assert_eq!(1, space.len());
+}
+
+#[tokio::test]
+async fn async_timeout() {
+    let space = LocalSpace::new_sequential();
+    let err = AsyncSpace::get_timeout(&space, new_template!(1.actual()), Duration::from_millis(50))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::TimedOut));
+    let err =
+        AsyncSpace::query_timeout(&space, new_template!(1.actual()), Duration::from_millis(50))
+            .await
+            .unwrap_err();
+    assert!(matches!(err, Error::TimedOut));
+}
+
+#[tokio::test]
+async fn cancelled_get_keeps_tuple() {
+    let space = Arc::new(LocalSpace::new_sequential());
+    let cancelled = tokio::time::timeout(
+        Duration::from_millis(50),
+        AsyncSpace::get(&*space, new_template!(1.actual())),
+    )
+    .await;
+    assert!(cancelled.is_err());
+    AsyncSpace::put(&*space, new_tuple!(1)).await.unwrap();
+    assert_eq!(1, space.len());
+    assert!(AsyncSpace::getp(&*space, new_template!(1.actual()))
+        .await
+        .is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_gate_serves_blocking_client() {
+    let (repo, space) = repo_with_space();
+    Repository::add_async_gate(
+        Arc::clone(&repo),
+        String::from("gate"),
+        String::from("127.0.0.1:3813"),
+    )
+    .await
+    .unwrap();
+    let client = tokio::task::spawn_blocking(|| {
+        let remote = RemoteSpace::new(String::from("127.0.0.1:3813/space")).unwrap();
+        let t = remote.get(new_template!(1.actual(), 'a'.formal())).unwrap();
+        remote.put(new_tuple!(2, 'b')).unwrap();
+        assert!(matches!(
+            remote.getp(new_template!(3.actual())),
+            Err(Error::NoMatch)
+        ));
+        *t.get_field::<char>(1)
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    AsyncSpace::put(&*space, new_tuple!(1, 'a')).await.unwrap();
+    assert_eq!('a', client.await.unwrap());
+    assert_eq!(1, space.len());
+    repo.close_gate(String::from("gate"));
+}
+
+#[tokio::test]
+async fn async_client_to_blocking_gate() {
+    let (repo, space) = repo_with_space();
+    Repository::add_gate(
+        Arc::clone(&repo),
+        String::from("gate"),
+        String::from("127.0.0.1:3814"),
+    )
+    .unwrap();
+    let remote = AsyncRemoteSpace::new(String::from("127.0.0.1:3814/space"))
+        .await
+        .unwrap();
+    remote.put(new_tuple!(1, 'a')).await.unwrap();
+    remote.put(new_tuple!(2, 'b')).await.unwrap();
+    let t = remote
+        .getp(new_template!(1.actual(), 'a'.formal()))
+        .await
+        .unwrap();
+    assert_eq!('a', *t.get_field::<char>(1));
+    assert_eq!(
+        1,
+        remote
+            .queryall(new_template!(0.formal(), 'a'.formal()))
+            .await
+            .unwrap()
+            .len()
+    );
+    assert_eq!(1, space.len());
+    let repo = Arc::clone(&repo);
+    tokio::task::spawn_blocking(move || repo.close_gate(String::from("gate")))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn async_client_to_async_gate() {
+    let (repo, _space) = repo_with_space();
+    Repository::add_async_gate(
+        Arc::clone(&repo),
+        String::from("gate"),
+        String::from("127.0.0.1:3815"),
+    )
+    .await
+    .unwrap();
+    let reader = AsyncRemoteSpace::new(String::from("127.0.0.1:3815/space"))
+        .await
+        .unwrap();
+    let writer = AsyncRemoteSpace::new(String::from("127.0.0.1:3815/space"))
+        .await
+        .unwrap();
+    let waiting = tokio::spawn(async move {
+        reader
+            .get(new_template!(String::from("job").actual(), 0.formal()))
+            .await
+            .unwrap()
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    writer
+        .put(new_tuple!(String::from("job"), 7))
+        .await
+        .unwrap();
+    let t = waiting.await.unwrap();
+    assert_eq!(7, *t.get_field::<i32>(1));
+    let err = writer
+        .get_timeout(new_template!(1.actual()), Duration::from_millis(50))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::TimedOut));
+
+    let missing = AsyncRemoteSpace::new(String::from("127.0.0.1:3815/nothing")).await;
+    assert!(matches!(missing, Err(Error::SpaceNotFound)));
+    repo.close_gate(String::from("gate"));
+}
+
+#[tokio::test]
+async fn async_requests_share_a_connection() {
+    let (repo, space) = repo_with_space();
+    Repository::add_async_gate(
+        Arc::clone(&repo),
+        String::from("gate"),
+        String::from("127.0.0.1:3818"),
+    )
+    .await
+    .unwrap();
+    let remote = Arc::new(
+        AsyncRemoteSpace::new(String::from("127.0.0.1:3818/space"))
+            .await
+            .unwrap(),
+    );
+    let clone = Arc::clone(&remote);
+    let waiting = tokio::spawn(async move { clone.get(new_template!(1.actual())).await.unwrap() });
+    // A cancelled request leaves the connection usable
+    let cancelled = tokio::time::timeout(
+        Duration::from_millis(20),
+        remote.query(new_template!(2.actual())),
+    )
+    .await;
+    assert!(cancelled.is_err());
+    remote.put(new_tuple!(3)).await.unwrap();
+    assert_eq!(
+        3,
+        *remote
+            .getp(new_template!(3.actual()))
+            .await
+            .unwrap()
+            .get_field::<i32>(0)
+    );
+    assert!(!waiting.is_finished());
+    remote.put(new_tuple!(1)).await.unwrap();
+    assert_eq!(1, *waiting.await.unwrap().get_field::<i32>(0));
+    assert!(space.is_empty());
+    repo.close_gate(String::from("gate"));
+}
+
+#[tokio::test]
+async fn async_msgpack() {
+    let (repo, space) = repo_with_space();
+    Repository::add_async_gate(
+        Arc::clone(&repo),
+        String::from("gate"),
+        String::from("127.0.0.1:3824"),
+    )
+    .await
+    .unwrap();
+    let remote =
+        AsyncRemoteSpace::with_codec(String::from("127.0.0.1:3824/space"), Codec::MessagePack)
+            .await
+            .unwrap();
+    remote
+        .put(new_tuple!(u128::MAX, 2.5, String::from("x")))
+        .await
+        .unwrap();
+    let t = remote
+        .get(new_template!(
+            0u128.formal(),
+            0.0.formal(),
+            String::new().formal()
+        ))
+        .await
+        .unwrap();
+    assert_eq!(u128::MAX, *t.get_field::<u128>(0));
+    assert_eq!(2.5, *t.get_field::<f64>(1));
+    assert!(space.is_empty());
+    repo.close_gate(String::from("gate"));
+}
+
+#[tokio::test]
+async fn async_gate_checks_permissions() {
+    let (repo, space) = repo_with_space();
+    repo.set_authenticator(Authenticator::token("secret", "reader"));
+    repo.grant("reader", "space", Permissions::READ);
+    Repository::add_async_gate(
+        Arc::clone(&repo),
+        String::from("gate"),
+        String::from("127.0.0.1:3828"),
+    )
+    .await
+    .unwrap();
+    let anonymous = AsyncRemoteSpace::new(String::from("127.0.0.1:3828/space")).await;
+    assert!(matches!(
+        anonymous,
+        Err(Error::Rejected(Rejection::Unauthorized, _))
+    ));
+    let options = ConnectOptions {
+        credentials: Some(Credentials::Token(String::from("secret"))),
+        ..Default::default()
+    };
+    let remote = AsyncRemoteSpace::with_options(String::from("127.0.0.1:3828/space"), options)
+        .await
+        .unwrap();
+    Space::put(&*space, new_tuple!(1)).unwrap();
+    assert_eq!(
+        1,
+        *remote
+            .query(new_template!(0.formal()))
+            .await
+            .unwrap()
+            .get_field::<i32>(0)
+    );
+    assert!(matches!(
+        remote.get(new_template!(0.formal())).await,
+        Err(Error::Remote(ErrorCode::Forbidden, _))
+    ));
+    assert_eq!(1, space.len());
+    repo.close_gate(String::from("gate"));
+}
+
+#[tokio::test]
+async fn async_time_to_live() {
+    let (repo, space) = repo_with_space();
+    Repository::add_async_gate(
+        Arc::clone(&repo),
+        String::from("gate"),
+        String::from("127.0.0.1:3835"),
+    )
+    .await
+    .unwrap();
+    let remote =
+        AsyncRemoteSpace::with_codec(String::from("127.0.0.1:3835/space"), Codec::MessagePack)
+            .await
+            .unwrap();
+    remote
+        .put_with_ttl(new_tuple!(1), Duration::from_millis(200))
+        .await
+        .unwrap();
+    let renewed = remote
+        .renew(new_template!(1.actual()), Duration::from_millis(200))
+        .await
+        .unwrap();
+    assert_eq!(1, renewed.len());
+    assert_eq!(1, space.len());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(space.is_empty());
+    assert!(matches!(
+        AsyncSpace::queryp(&remote, new_template!(1.actual())).await,
+        Err(Error::NoMatch)
+    ));
+    repo.close_gate(String::from("gate"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_gate_subscription() {
+    let (repo, space) = repo_with_space();
+    Repository::add_async_gate(
+        Arc::clone(&repo),
+        String::from("gate"),
+        String::from("127.0.0.1:3836"),
+    )
+    .await
+    .unwrap();
+    let events = tokio::task::spawn_blocking(|| {
+        let remote = RemoteSpace::new(String::from("127.0.0.1:3836/space")).unwrap();
+        let events = remote.subscribe(new_template!(0.formal())).unwrap();
+        (remote, events)
+    });
+    let (remote, events) = events.await.unwrap();
+    AsyncSpace::put(&*space, new_tuple!(1)).await.unwrap();
+    AsyncSpace::getp(&*space, new_template!(1.actual()))
+        .await
+        .unwrap();
+    let received = tokio::task::spawn_blocking(move || {
+        let timeout = Duration::from_secs(1);
+        let put = events.recv_timeout(timeout).unwrap();
+        let removed = events.recv_timeout(timeout).unwrap();
+        (put, removed)
+    });
+    assert!(matches!(
+        received.await.unwrap(),
+        (SpaceEvent::Put(_), SpaceEvent::Removed(_))
+    ));
+    tokio::task::spawn_blocking(move || drop(remote))
+        .await
+        .unwrap();
+    repo.close_gate(String::from("gate"));
+}
+
+#[tokio::test]
+async fn async_transactions() {
+    let (repo, space) = repo_with_space();
+    Repository::add_async_gate(
+        Arc::clone(&repo),
+        String::from("gate"),
+        String::from("127.0.0.1:3838"),
+    )
+    .await
+    .unwrap();
+    let remote = AsyncRemoteSpace::new(String::from("127.0.0.1:3838/space"))
+        .await
+        .unwrap();
+    AsyncSpace::put(&*space, new_tuple!(1)).await.unwrap();
+    let results = remote
+        .batch(vec![
+            Operation::Getp(new_template!(1.actual())),
+            Operation::Put(new_tuple!(2)),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(1, *results[0][0].get_field::<i32>(0));
+    assert!(matches!(
+        remote
+            .batch(vec![
+                Operation::Getall(new_template!(0.formal())),
+                Operation::Getp(new_template!(1.actual())),
+            ])
+            .await,
+        Err(Error::NoMatch)
+    ));
+    assert_eq!(1, space.len());
+    repo.close_gate(String::from("gate"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_gate_bounds_waiting_requests() {
+    let (repo, space) = repo_with_space();
+    Repository::add_async_gate(
+        Arc::clone(&repo),
+        String::from("gate"),
+        String::from("127.0.0.1:3842"),
+    )
+    .await
+    .unwrap();
+    let remote = Arc::new(
+        AsyncRemoteSpace::new(String::from("127.0.0.1:3842/space"))
+            .await
+            .unwrap(),
+    );
+    // A connection may have 64 requests waiting at once
+    let waiting: Vec<_> = (0..64)
+        .map(|_| {
+            let remote = Arc::clone(&remote);
+            tokio::spawn(async move { remote.get(new_template!(1.actual())).await })
+        })
+        .collect();
+    while space.waiting() < 64 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(matches!(
+        remote.get(new_template!(1.actual())).await,
+        Err(Error::Remote(ErrorCode::InvalidRequest, _))
+    ));
+    remote.put(new_tuple!(2)).await.unwrap();
+    for _ in 0..64 {
+        AsyncSpace::put(&*space, new_tuple!(1)).await.unwrap();
+    }
+    for waiter in waiting {
+        assert!(waiter.await.unwrap().is_ok());
+    }
+    assert_eq!(1, space.len());
+
+    // A get cut off by the connection ending leaves the tuple in the space
+    let clone = Arc::clone(&remote);
+    let cut_off = tokio::spawn(async move { clone.get(new_template!(1.actual())).await });
+    while space.waiting() < 1 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    repo.close_gate(String::from("gate"));
+    assert!(cut_off.await.unwrap().is_err());
+    while space.waiting() > 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    AsyncSpace::put(&*space, new_tuple!(1)).await.unwrap();
+    assert_eq!(2, space.len());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dropped_remote_get_keeps_tuple() {
+    let (repo, space) = repo_with_space();
+    Repository::add_async_gate(
+        Arc::clone(&repo),
+        String::from("gate"),
+        String::from("127.0.0.1:3843"),
+    )
+    .await
+    .unwrap();
+    let remote = AsyncRemoteSpace::new(String::from("127.0.0.1:3843/space"))
+        .await
+        .unwrap();
+    // The gate keeps waiting for the get after the future is dropped
+    let cancelled = tokio::time::timeout(
+        Duration::from_millis(50),
+        remote.get(new_template!(1.actual())),
+    )
+    .await;
+    assert!(cancelled.is_err());
+    assert_eq!(1, space.waiting());
+    AsyncSpace::put(&*space, new_tuple!(1)).await.unwrap();
+    // The tuple it takes is put back once the reply reaches the client
+    let mut tries = 0;
+    while space.is_empty() && tries < 100 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        tries += 1;
+    }
+    assert_eq!(1, space.len());
+    assert!(remote.getp(new_template!(1.actual())).await.is_ok());
+    repo.close_gate(String::from("gate"));
+}
@@ -0,0 +1,487 @@
+//! Runs the same scenarios against every kind of space on every backend, so a remote space
+//! behaves exactly like the local space behind it.
+
+#[cfg(feature = "tls")]
+mod certs;
+
+use rspaces::{
+    new_template, new_tuple, Codec, ConnectOptions, Error, FieldType, LocalSpace, Operation,
+    RemoteSpace, Repository, Space, SpaceType, Template, Tuple, TupleField,
+};
+use std::{path::PathBuf, sync::Arc, thread, time::Duration};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Sequential,
+    Queue,
+    Stack,
+    Pile,
+    Random,
+}
+
+const KINDS: [Kind; 5] = [
+    Kind::Sequential,
+    Kind::Queue,
+    Kind::Stack,
+    Kind::Pile,
+    Kind::Random,
+];
+
+impl Kind {
+    fn space(self) -> LocalSpace {
+        match self {
+            Kind::Sequential => LocalSpace::new_sequential(),
+            Kind::Queue => LocalSpace::new_queue(),
+            Kind::Stack => LocalSpace::new_stack(),
+            Kind::Pile => LocalSpace::new_pile(),
+            Kind::Random => LocalSpace::new_random(),
+        }
+    }
+
+    fn space_type(self) -> SpaceType {
+        match self {
+            Kind::Sequential => SpaceType::Sequential,
+            Kind::Queue => SpaceType::Queue,
+            Kind::Stack => SpaceType::Stack,
+            Kind::Pile => SpaceType::Pile,
+            Kind::Random => SpaceType::Random,
+        }
+    }
+}
+
+/// Opens a new, empty space of the given kind, along with the local space behind it
+trait Backend {
+    fn open(&mut self, kind: Kind) -> (Box<dyn Space>, Arc<LocalSpace>);
+}
+
+struct Local;
+
+impl Backend for Local {
+    fn open(&mut self, kind: Kind) -> (Box<dyn Space>, Arc<LocalSpace>) {
+        let local = Arc::new(kind.space());
+        (Box::new(Arc::clone(&local)), local)
+    }
+}
+
+/// Local spaces logging their changes to a directory of their own
+struct Persistent {
+    root: PathBuf,
+    opened: usize,
+}
+
+impl Persistent {
+    fn new() -> Persistent {
+        let root = std::env::temp_dir().join(format!("rspaces-conformance-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Persistent { root, opened: 0 }
+    }
+}
+
+impl Backend for Persistent {
+    fn open(&mut self, kind: Kind) -> (Box<dyn Space>, Arc<LocalSpace>) {
+        self.opened += 1;
+        let dir = self.root.join(format!("{:?}{}", kind, self.opened));
+        let local = Arc::new(LocalSpace::open_persistent(dir, kind.space_type()).unwrap());
+        (Box::new(Arc::clone(&local)), local)
+    }
+}
+
+impl Drop for Persistent {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Local spaces in a repository, reached through a remote space connected to its gate
+struct Gated {
+    repo: Arc<Repository>,
+    addr: String,
+    options: ConnectOptions,
+    opened: usize,
+    #[cfg(feature = "tokio")]
+    _runtime: Option<tokio::runtime::Runtime>,
+}
+
+impl Gated {
+    fn blocking(addr: &str, codec: Codec) -> Gated {
+        let repo = Arc::new(Repository::new());
+        Repository::add_gate(Arc::clone(&repo), String::from("gate"), addr.to_string())
+            .expect("could not open gate");
+        let options = ConnectOptions {
+            codec,
+            ..Default::default()
+        };
+        Gated::with_options(repo, addr, options)
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls(addr: &str) -> Gated {
+        use rspaces::{TlsAcceptor, TlsConnector};
+        let ca = certs::Authority::new("conformance ca");
+        let gate = ca.issue(&["127.0.0.1"]);
+        let repo = Arc::new(Repository::new());
+        let tls = TlsAcceptor::new(&gate.cert, &gate.key).unwrap();
+        Repository::add_gate_tls(
+            Arc::clone(&repo),
+            String::from("gate"),
+            addr.to_string(),
+            tls,
+        )
+        .expect("could not open gate");
+        let options = ConnectOptions {
+            tls: Some(TlsConnector::new(&ca.pem()).unwrap()),
+            ..Default::default()
+        };
+        Gated::with_options(repo, addr, options)
+    }
+
+    fn with_options(repo: Arc<Repository>, addr: &str, options: ConnectOptions) -> Gated {
+        Gated {
+            repo,
+            addr: addr.to_string(),
+            options,
+            opened: 0,
+            #[cfg(feature = "tokio")]
+            _runtime: None,
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    fn asynchronous(addr: &str) -> Gated {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let repo = Arc::new(Repository::new());
+        runtime
+            .block_on(Repository::add_async_gate(
+                Arc::clone(&repo),
+                String::from("gate"),
+                addr.to_string(),
+            ))
+            .expect("could not open gate");
+        let mut gated = Gated::with_options(repo, addr, ConnectOptions::default());
+        gated._runtime = Some(runtime);
+        gated
+    }
+}
+
+impl Backend for Gated {
+    fn open(&mut self, kind: Kind) -> (Box<dyn Space>, Arc<LocalSpace>) {
+        self.opened += 1;
+        let name = format!("{:?}{}", kind, self.opened);
+        let local = Arc::new(kind.space());
+        self.repo.add_space(name.clone(), Arc::clone(&local));
+        let conn = format!("{}/{}", self.addr, name);
+        let remote = RemoteSpace::with_options(conn, self.options.clone()).unwrap();
+        (Box::new(remote), local)
+    }
+}
+
+impl Drop for Gated {
+    fn drop(&mut self) {
+        self.repo.close_gate(String::from("gate"));
+    }
+}
+
+/// Local spaces in a repository, reached through a remote space connected to its in-memory gate
+struct InMemory {
+    repo: Arc<Repository>,
+    opened: usize,
+}
+
+impl InMemory {
+    fn new() -> InMemory {
+        let repo = Arc::new(Repository::new());
+        Repository::add_gate_in_memory(Arc::clone(&repo), String::from("gate"));
+        InMemory { repo, opened: 0 }
+    }
+}
+
+impl Backend for InMemory {
+    fn open(&mut self, kind: Kind) -> (Box<dyn Space>, Arc<LocalSpace>) {
+        self.opened += 1;
+        let name = format!("{:?}{}", kind, self.opened);
+        let local = Arc::new(kind.space());
+        self.repo.add_space(name.clone(), Arc::clone(&local));
+        let remote =
+            RemoteSpace::connect_in_memory(&self.repo, String::from("gate"), name).unwrap();
+        (Box::new(remote), local)
+    }
+}
+
+impl Drop for InMemory {
+    fn drop(&mut self) {
+        self.repo.close_gate(String::from("gate"));
+    }
+}
+
+/// Runs on a space and the local space behind it, which is the same space unless it is remote
+type Scenario = fn(&dyn Space, &LocalSpace, Kind);
+
+const SCENARIOS: [(&str, Scenario); 11] = [
+    ("empty", empty),
+    ("queryp", queryp),
+    ("getp", getp),
+    ("queryall", queryall),
+    ("getall", getall),
+    ("arity", arity),
+    ("timeouts", timeouts),
+    ("blocking", blocking),
+    ("time_to_live", time_to_live),
+    ("renew", renew),
+    ("batch", batch),
+];
+
+fn run(backend: &mut dyn Backend) {
+    for kind in KINDS {
+        for (_, scenario) in SCENARIOS {
+            let (space, local) = backend.open(kind);
+            scenario(&*space, &local, kind);
+        }
+    }
+}
+
+fn any() -> Template {
+    new_template!(0.formal(), 'x'.formal())
+}
+
+fn fill(space: &dyn Space) {
+    space.put_tuple(new_tuple!(1, 'a')).unwrap();
+    space.put_tuple(new_tuple!(2, 'b')).unwrap();
+    space.put_tuple(new_tuple!(3, 'a')).unwrap();
+}
+
+fn number(tuple: &Tuple) -> i32 {
+    *tuple.get_field::<i32>(0)
+}
+
+fn numbers(tuples: Vec<Tuple>) -> Vec<i32> {
+    tuples.iter().map(number).collect()
+}
+
+fn empty(space: &dyn Space, _local: &LocalSpace, _kind: Kind) {
+    assert!(matches!(space.getp(any()), Err(Error::NoMatch)));
+    assert!(matches!(space.queryp(any()), Err(Error::NoMatch)));
+    assert!(space.getall(any()).unwrap().is_empty());
+    assert!(space.queryall(any()).unwrap().is_empty());
+}
+
+fn queryp(space: &dyn Space, _local: &LocalSpace, kind: Kind) {
+    fill(space);
+    let first = number(&space.queryp(any()).unwrap());
+    let a = number(
+        &space
+            .queryp(new_template!(0.formal(), 'a'.actual()))
+            .unwrap(),
+    );
+    let two = space
+        .queryp(new_template!(2.actual(), 'x'.formal()))
+        .map(|t| number(&t));
+    match kind {
+        Kind::Sequential => assert_eq!((1, 1, Some(2)), (first, a, two.ok())),
+        Kind::Queue => assert_eq!((1, 1, None), (first, a, two.ok())),
+        Kind::Stack => assert_eq!((3, 3, None), (first, a, two.ok())),
+        Kind::Pile => assert_eq!((3, 3, Some(2)), (first, a, two.ok())),
+        Kind::Random => {
+            assert!([1, 2, 3].contains(&first));
+            assert!([1, 3].contains(&a));
+            assert_eq!(2, two.unwrap());
+        }
+    }
+    assert_eq!(vec![1, 2, 3], numbers(space.queryall(any()).unwrap()));
+}
+
+fn getp(space: &dyn Space, _local: &LocalSpace, kind: Kind) {
+    fill(space);
+    let mut order: Vec<_> = (0..3)
+        .map(|_| number(&space.getp(any()).unwrap()))
+        .collect();
+    assert!(matches!(space.getp(any()), Err(Error::NoMatch)));
+    match kind {
+        Kind::Sequential | Kind::Queue => assert_eq!(vec![1, 2, 3], order),
+        Kind::Stack | Kind::Pile => assert_eq!(vec![3, 2, 1], order),
+        Kind::Random => {
+            order.sort();
+            assert_eq!(vec![1, 2, 3], order);
+        }
+    }
+}
+
+fn queryall(space: &dyn Space, _local: &LocalSpace, _kind: Kind) {
+    fill(space);
+    let a = new_template!(0.formal(), 'a'.actual());
+    assert_eq!(vec![1, 3], numbers(space.queryall(a.clone()).unwrap()));
+    assert_eq!(vec![1, 3], numbers(space.queryall(a).unwrap()));
+    assert_eq!(vec![1, 2, 3], numbers(space.queryall(any()).unwrap()));
+}
+
+fn getall(space: &dyn Space, _local: &LocalSpace, _kind: Kind) {
+    fill(space);
+    let a = new_template!(0.formal(), 'a'.actual());
+    assert_eq!(vec![1, 3], numbers(space.getall(a.clone()).unwrap()));
+    assert!(space.getall(a).unwrap().is_empty());
+    assert_eq!(vec![2], numbers(space.getall(any()).unwrap()));
+    assert!(matches!(space.queryp(any()), Err(Error::NoMatch)));
+}
+
+fn arity(space: &dyn Space, _local: &LocalSpace, _kind: Kind) {
+    fill(space);
+    space.put_tuple(new_tuple!(4)).unwrap();
+    let one = new_template!(0.formal());
+    assert_eq!(vec![4], numbers(space.queryall(one.clone()).unwrap()));
+    assert!(matches!(
+        space.queryp(new_template!(0.formal(), 'x'.formal(), 0.formal())),
+        Err(Error::NoMatch)
+    ));
+    assert_eq!(vec![1, 2, 3], numbers(space.getall(any()).unwrap()));
+    assert_eq!(4, number(&space.getp(one).unwrap()));
+}
+
+fn timeouts(space: &dyn Space, _local: &LocalSpace, _kind: Kind) {
+    let five = new_template!(5.actual(), 'x'.formal());
+    let timeout = Duration::from_millis(20);
+    assert!(matches!(
+        space.get_timeout(five.clone(), timeout),
+        Err(Error::TimedOut)
+    ));
+    assert!(matches!(
+        space.query_timeout(five.clone(), timeout),
+        Err(Error::TimedOut)
+    ));
+    space.put_tuple(new_tuple!(5, 'z')).unwrap();
+    let t = space.query_timeout(five.clone(), timeout).unwrap();
+    assert_eq!('z', *t.get_field::<char>(1));
+    let t = space.get_timeout(five.clone(), timeout).unwrap();
+    assert_eq!('z', *t.get_field::<char>(1));
+    assert!(matches!(space.getp(five), Err(Error::NoMatch)));
+}
+
+/// Waits until as many readers are blocked on the space
+fn await_waiting(local: &LocalSpace, readers: usize) {
+    while local.waiting() < readers {
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn blocking(space: &dyn Space, local: &LocalSpace, _kind: Kind) {
+    let five = new_template!(5.actual(), 'x'.formal());
+    thread::scope(|s| {
+        // Waiters are served in order, so the query must be waiting before the get takes the tuple
+        let query = s.spawn(|| space.query(five.clone()).unwrap());
+        await_waiting(local, 1);
+        let get = s.spawn(|| space.get(five.clone()).unwrap());
+        await_waiting(local, 2);
+        space.put_tuple(new_tuple!(5, 'z')).unwrap();
+        assert_eq!('z', *query.join().unwrap().get_field::<char>(1));
+        assert_eq!('z', *get.join().unwrap().get_field::<char>(1));
+    });
+    assert!(matches!(space.queryp(five), Err(Error::NoMatch)));
+}
+
+fn time_to_live(space: &dyn Space, _local: &LocalSpace, kind: Kind) {
+    // Expiry does not depend on the ordering of the space, and takes a while to watch
+    if kind != Kind::Sequential {
+        return;
+    }
+    let ttl = Duration::from_millis(200);
+    space.put_with_ttl(new_tuple!(1, 'a'), ttl).unwrap();
+    space.put_tuple(new_tuple!(2, 'b')).unwrap();
+    assert_eq!(vec![1, 2], numbers(space.queryall(any()).unwrap()));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(vec![2], numbers(space.queryall(any()).unwrap()));
+    assert!(matches!(
+        space.getp(new_template!(1.actual(), 'x'.formal())),
+        Err(Error::NoMatch)
+    ));
+}
+
+fn renew(space: &dyn Space, _local: &LocalSpace, kind: Kind) {
+    if kind != Kind::Sequential {
+        return;
+    }
+    let ttl = Duration::from_millis(200);
+    space.put_with_ttl(new_tuple!(1, 'a'), ttl).unwrap();
+    space.put_with_ttl(new_tuple!(2, 'b'), ttl).unwrap();
+    space.put_tuple(new_tuple!(3, 'a')).unwrap();
+    // Only the matching tuples that were put with a time to live are renewed
+    let a = new_template!(0.formal(), 'a'.actual());
+    let renewed = space.renew(a, Duration::from_millis(600)).unwrap();
+    assert_eq!(vec![1], numbers(renewed));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(vec![1, 3], numbers(space.queryall(any()).unwrap()));
+    thread::sleep(Duration::from_millis(400));
+    assert_eq!(vec![3], numbers(space.queryall(any()).unwrap()));
+}
+
+fn batch(space: &dyn Space, _local: &LocalSpace, _kind: Kind) {
+    fill(space);
+    let a = new_template!(0.formal(), 'a'.actual());
+    // A step that fails undoes the steps before it
+    let failed = space.batch(vec![
+        Operation::Getall(a.clone()),
+        Operation::Put(new_tuple!(4, 'c')),
+        Operation::Getp(new_template!(9.actual(), 'x'.formal())),
+    ]);
+    assert!(matches!(failed, Err(Error::NoMatch)));
+    assert_eq!(vec![1, 2, 3], numbers(space.queryall(any()).unwrap()));
+
+    let results = space
+        .batch(vec![
+            Operation::Getall(a),
+            Operation::Put(new_tuple!(4, 'c')),
+            Operation::Queryall(any()),
+        ])
+        .unwrap();
+    assert_eq!(3, results.len());
+    assert_eq!(vec![1, 3], numbers(results[0].clone()));
+    assert!(results[1].is_empty());
+    assert_eq!(vec![2, 4], numbers(results[2].clone()));
+    assert_eq!(vec![2, 4], numbers(space.queryall(any()).unwrap()));
+}
+
+#[test]
+fn local_space() {
+    run(&mut Local);
+}
+
+#[test]
+fn persistent_space() {
+    run(&mut Persistent::new());
+}
+
+#[test]
+fn remote_space_over_gate() {
+    run(&mut Gated::blocking("127.0.0.1:3819", Codec::Json));
+}
+
+#[test]
+fn remote_space_over_gate_msgpack() {
+    run(&mut Gated::blocking("127.0.0.1:3823", Codec::MessagePack));
+}
+
+#[test]
+fn remote_space_over_in_memory_gate() {
+    run(&mut InMemory::new());
+}
+
+#[cfg(unix)]
+#[test]
+fn remote_space_over_unix_gate() {
+    let path =
+        std::env::temp_dir().join(format!("rspaces-conformance-{}.sock", std::process::id()));
+    let addr = format!("unix://{}", path.display());
+    run(&mut Gated::blocking(&addr, Codec::Json));
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn remote_space_over_tls_gate() {
+    run(&mut Gated::tls("127.0.0.1:3831"));
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn remote_space_over_async_gate() {
+    run(&mut Gated::asynchronous("127.0.0.1:3820"));
+}
@@ -0,0 +1,242 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rspaces::{
+    new_template, new_tuple, ErrorCode, FieldType, Hello, LocalSpace, Message, MessageType,
+    RemoteSpace, Repository, Space, Template, Tuple, TupleField, Welcome, PROTOCOL_VERSION,
+};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+fn write_frame(stream: &mut TcpStream, payload: &[u8]) {
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame).unwrap();
+}
+
+fn read_message(stream: &mut TcpStream) -> Message {
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).unwrap();
+    let mut payload = vec![0; u32::from_be_bytes(header) as usize];
+    stream.read_exact(&mut payload).unwrap();
+    serde_json::from_slice::<Message>(&payload).unwrap()
+}
+
+fn connect_raw(addr: &str, space: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let hello = Hello {
+        version: PROTOCOL_VERSION,
+        codec: String::from("json"),
+        client_id: String::new(),
+        space: space.to_string(),
+        credentials: None,
+    };
+    write_frame(&mut stream, &serde_json::to_vec(&hello).unwrap());
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).unwrap();
+    let mut payload = vec![0; u32::from_be_bytes(header) as usize];
+    stream.read_exact(&mut payload).unwrap();
+    let welcome = serde_json::from_slice(&payload).unwrap();
+    assert!(matches!(welcome, Welcome::Accepted { .. }));
+    stream
+}
+
+fn assert_error(message: Message, expected: ErrorCode) {
+    match message.action {
+        MessageType::Error { code, .. } => assert_eq!(expected, code),
+        action => panic!("expected an error reply but got {:?}", action),
+    }
+}
+
+fn put(stream: &mut TcpStream, tuple: Tuple) -> Message {
+    let m = Message {
+        id: 0,
+        action: MessageType::Put,
+        tuple: Vec::from([tuple]),
+        template: new_template!(),
+    };
+    write_frame(stream, &serde_json::to_vec(&m).unwrap());
+    read_message(stream)
+}
+
+fn start_gate(addr: &str) -> (Arc<Repository>, Arc<LocalSpace>) {
+    let repo = Arc::new(Repository::new());
+    let space = Arc::new(LocalSpace::new_sequential());
+    repo.add_space(String::from("space"), Arc::clone(&space));
+    Repository::add_gate(Arc::clone(&repo), String::from("gate"), addr.to_string())
+        .expect("could not connect");
+    (repo, space)
+}
+
+#[test]
+fn garbage_frames() {
+    let (repo, space) = start_gate("127.0.0.1:3807");
+    let mut stream = connect_raw("127.0.0.1:3807", "space");
+    let mut rng = StdRng::seed_from_u64(321);
+    for i in 0..200 {
+        let len = rng.gen_range(0..2048);
+        let payload: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+        write_frame(&mut stream, &payload);
+        assert_error(read_message(&mut stream), ErrorCode::Malformed);
+        if i % 20 == 0 {
+            assert_eq!(MessageType::Ok, put(&mut stream, new_tuple!(i, 'a')).action);
+        }
+    }
+    let tuples = space
+        .queryall(new_template!(0.formal(), 'a'.formal()))
+        .unwrap();
+    assert_eq!(10, tuples.len());
+    repo.close_gate(String::from("gate"));
+}
+
+#[test]
+fn garbage_json() {
+    let (repo, _space) = start_gate("127.0.0.1:3808");
+    let mut stream = connect_raw("127.0.0.1:3808", "space");
+    let inputs = [
+        "{}",
+        "null",
+        "[1, 2, 3]",
+        "{\"action\": \"Get\"}",
+        "{\"action\": \"Dance\", \"tuple\": [], \"template\": {\"fields\": []}}",
+        "{\"action\": \"Put\", \"tuple\": [{\"fields\": [{\"field\": \"nope\", \"value\": 1}]}], \"template\": {\"fields\": []}}",
+        "{\"action\": {\"GetTimeout\": \"soon\"}, \"tuple\": [], \"template\": {\"fields\": []}}",
+    ];
+    for input in inputs.iter() {
+        write_frame(&mut stream, input.as_bytes());
+        assert_error(read_message(&mut stream), ErrorCode::Malformed);
+    }
+    assert_eq!(MessageType::Ok, put(&mut stream, new_tuple!(1, 'a')).action);
+    repo.close_gate(String::from("gate"));
+}
+
+#[test]
+fn invalid_requests() {
+    let (repo, _space) = start_gate("127.0.0.1:3809");
+    let mut stream = connect_raw("127.0.0.1:3809", "space");
+    for action in [MessageType::Ok, MessageType::NoMatch, MessageType::TimedOut] {
+        let m = Message {
+            id: 0,
+            action,
+            tuple: Vec::new(),
+            template: new_template!(),
+        };
+        write_frame(&mut stream, &serde_json::to_vec(&m).unwrap());
+        assert_error(read_message(&mut stream), ErrorCode::InvalidRequest);
+    }
+    let m = Message {
+        id: 0,
+        action: MessageType::Put,
+        tuple: Vec::new(),
+        template: new_template!(),
+    };
+    write_frame(&mut stream, &serde_json::to_vec(&m).unwrap());
+    assert_error(read_message(&mut stream), ErrorCode::InvalidRequest);
+    assert_eq!(MessageType::Ok, put(&mut stream, new_tuple!(1, 'a')).action);
+    repo.close_gate(String::from("gate"));
+}
+
+#[test]
+fn broken_framing_closes_connection() {
+    let (repo, _space) = start_gate("127.0.0.1:3810");
+    let mut stream = connect_raw("127.0.0.1:3810", "space");
+    stream.write_all(&[0xff; 64]).unwrap();
+    assert_error(read_message(&mut stream), ErrorCode::Protocol);
+    let mut buffer = [0; 1];
+    match stream.read(&mut buffer) {
+        Ok(0) | Err(_) => {}
+        Ok(_) => panic!("gate should have closed the connection"),
+    }
+
+    let remote = RemoteSpace::new(String::from("127.0.0.1:3810/space")).unwrap();
+    remote.put(new_tuple!(1, 'a')).unwrap();
+    repo.close_gate(String::from("gate"));
+}
+
+#[test]
+fn silent_client_does_not_stall_gate() {
+    let (repo, _space) = start_gate("127.0.0.1:3811");
+    let _silent = TcpStream::connect("127.0.0.1:3811").unwrap();
+    let start = Instant::now();
+    let remote = RemoteSpace::new(String::from("127.0.0.1:3811/space")).unwrap();
+    remote.put(new_tuple!(1, 'a')).unwrap();
+    // The silent client is only dropped after 5 seconds, other clients must not wait for that
+    assert!(start.elapsed() < Duration::from_millis(4000));
+    repo.close_gate(String::from("gate"));
+}
+
+fn request(stream: &mut TcpStream, id: u64, action: MessageType, template: Template) {
+    let m = Message {
+        id,
+        action,
+        tuple: Vec::new(),
+        template,
+    };
+    write_frame(stream, &serde_json::to_vec(&m).unwrap());
+}
+
+#[test]
+fn waiting_requests_are_bounded() {
+    let (repo, _space) = start_gate("127.0.0.1:3839");
+    let mut stream = connect_raw("127.0.0.1:3839", "space");
+    // A connection may have 64 requests waiting at once
+    for id in 0..65 {
+        request(&mut stream, id, MessageType::Get, new_template!(1.actual()));
+    }
+    let reply = read_message(&mut stream);
+    assert_eq!(64, reply.id);
+    assert_error(reply, ErrorCode::InvalidRequest);
+    assert_eq!(MessageType::Ok, put(&mut stream, new_tuple!(2)).action);
+    repo.close_gate(String::from("gate"));
+}
+
+#[test]
+fn waiting_requests_end_with_connection() {
+    let (repo, space) = start_gate("127.0.0.1:3840");
+    let mut stream = connect_raw("127.0.0.1:3840", "space");
+    request(&mut stream, 0, MessageType::Get, new_template!(1.actual()));
+    request(
+        &mut stream,
+        1,
+        MessageType::GetTimeout(Duration::from_secs(5)),
+        new_template!(1.actual()),
+    );
+    std::thread::sleep(Duration::from_millis(50));
+    drop(stream);
+    // Whether the readers stopped waiting or took the tuples first, the tuples stay in the space
+    space.put(new_tuple!(1)).unwrap();
+    space.put(new_tuple!(1)).unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(2, space.len());
+    repo.close_gate(String::from("gate"));
+}
+
+#[test]
+fn subscription_not_read_is_dropped() {
+    let (repo, space) = start_gate("127.0.0.1:3841");
+    let mut stream = connect_raw("127.0.0.1:3841", "space");
+    let template = new_template!(0.formal(), String::new().formal());
+    request(&mut stream, 7, MessageType::Subscribe, template);
+    assert_eq!(MessageType::Ok, read_message(&mut stream).action);
+    // Far more events than the socket buffers and the backlog of the subscription hold
+    let padding = "x".repeat(1000);
+    for i in 0..20000 {
+        space.put(new_tuple!(i, padding.clone())).unwrap();
+    }
+    let mut events = 0;
+    let reply = loop {
+        let m = read_message(&mut stream);
+        match m.action {
+            MessageType::Event(_) => events += 1,
+            _ => break m,
+        }
+    };
+    assert_eq!(7, reply.id);
+    assert_error(reply, ErrorCode::SpaceFailure);
+    assert!(events < 20000);
+    // The connection carries on
+    assert_eq!(MessageType::Ok, put(&mut stream, new_tuple!(1)).action);
+    repo.close_gate(String::from("gate"));
+}
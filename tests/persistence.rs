@@ -0,0 +1,334 @@
+use rspaces::{
+    new_template, new_tuple, Error, FieldType, LocalSpace, Space, SpaceType, Template, Tuple,
+    TupleField,
+};
+use std::{
+    fs::{self, OpenOptions},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+/// An empty directory for a persistent space, unique to the test
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rspaces-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn numbers(tuples: Vec<Tuple>) -> Vec<i32> {
+    tuples.iter().map(|t| *t.get_field::<i32>(0)).collect()
+}
+
+fn any() -> Template {
+    new_template!(0.formal())
+}
+
+fn any_pair() -> Template {
+    new_template!(0.formal(), 0.0.formal())
+}
+
+#[test]
+fn replay_keeps_order() {
+    for kind in [
+        SpaceType::Sequential,
+        SpaceType::Queue,
+        SpaceType::Stack,
+        SpaceType::Pile,
+    ] {
+        let dir = dir(&format!("order-{:?}", kind));
+        let space = LocalSpace::open_persistent(&dir, kind).unwrap();
+        let expected = match kind {
+            SpaceType::Sequential | SpaceType::Queue => LocalSpace::new_queue(),
+            _ => LocalSpace::new_stack(),
+        };
+        for n in 1..=5 {
+            space.put(new_tuple!(n)).unwrap();
+            expected.put(new_tuple!(n)).unwrap();
+        }
+        space.getp(any()).unwrap();
+        expected.getp(any()).unwrap();
+        drop(space);
+
+        let space = LocalSpace::open_persistent(&dir, kind).unwrap();
+        space.put(new_tuple!(6)).unwrap();
+        expected.put(new_tuple!(6)).unwrap();
+        assert_eq!(5, space.len(), "{:?}", kind);
+        for _ in 0..5 {
+            assert_eq!(
+                numbers(vec![expected.getp(any()).unwrap()]),
+                numbers(vec![space.getp(any()).unwrap()]),
+                "{:?}",
+                kind
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn every_removal_is_logged() {
+    let dir = dir("removals");
+    let space = Arc::new(LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap());
+    for n in 1..=6 {
+        space.put(new_tuple!(n)).unwrap();
+    }
+    space.get(new_template!(1.actual())).unwrap();
+    space.getp(new_template!(2.actual())).unwrap();
+    space.get_where(|t| *t.get_field::<i32>(0) == 3).unwrap();
+    space.getall(new_template!(4.actual())).unwrap();
+    // A tuple handed straight to a blocked reader is removed as it is put
+    let clone = Arc::clone(&space);
+    let waiting = thread::spawn(move || clone.get(new_template!(7.actual())).unwrap());
+    thread::sleep(Duration::from_millis(50));
+    space.put(new_tuple!(7)).unwrap();
+    waiting.join().unwrap();
+    drop(space);
+
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    assert_eq!(vec![5, 6], numbers(space.queryall(any()).unwrap()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn truncated_log() {
+    let dir = dir("truncated");
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Queue).unwrap();
+    for n in 1..=3 {
+        space.put(new_tuple!(n, 'p')).unwrap();
+    }
+    drop(space);
+
+    // The process died while appending the last record
+    let log = OpenOptions::new()
+        .write(true)
+        .open(dir.join("log"))
+        .unwrap();
+    let len = log.metadata().unwrap().len();
+    log.set_len(len - 10).unwrap();
+    drop(log);
+
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Queue).unwrap();
+    assert_eq!(
+        vec![1, 2],
+        numbers(
+            space
+                .queryall(new_template!(0.formal(), 'x'.formal()))
+                .unwrap()
+        )
+    );
+    // Records appended after recovery follow the last whole record
+    space.put(new_tuple!(4, 'p')).unwrap();
+    drop(space);
+
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Queue).unwrap();
+    assert_eq!(
+        vec![1, 2, 4],
+        numbers(
+            space
+                .queryall(new_template!(0.formal(), 'x'.formal()))
+                .unwrap()
+        )
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Cuts the last line of the log short, as if the process died while appending it
+fn cut_last_line(dir: &Path) {
+    let log = fs::read(dir.join("log")).unwrap();
+    let last = log[..log.len() - 1]
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map_or(0, |n| n + 1);
+    // Halfway through the records of the last operation
+    fs::write(dir.join("log"), &log[..(last + log.len()) / 2]).unwrap();
+}
+
+#[test]
+fn truncated_operation() {
+    let dir = dir("truncated-operation");
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    for n in 1..=4 {
+        space.put(new_tuple!(n)).unwrap();
+    }
+    assert_eq!(4, space.getall(any()).unwrap().len());
+    drop(space);
+
+    cut_last_line(&dir);
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    // None of the removals of the getall survived, rather than the first ones
+    assert_eq!(vec![1, 2, 3, 4], numbers(space.queryall(any()).unwrap()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn corrupt_log() {
+    let dir = dir("corrupt");
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    space.put(new_tuple!(1)).unwrap();
+    space.put(new_tuple!(2)).unwrap();
+    drop(space);
+
+    // Unlike a record cut short at the end, a broken record followed by others is not a crash
+    let mut log = fs::read(dir.join("log")).unwrap();
+    log[2] = b'#';
+    fs::write(dir.join("log"), log).unwrap();
+    assert!(matches!(
+        LocalSpace::open_persistent(&dir, SpaceType::Sequential),
+        Err(Error::Io(_))
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compaction() {
+    let dir = dir("compaction");
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    space.put(new_tuple!(0)).unwrap();
+    for n in 1..=1000 {
+        space.put(new_tuple!(n)).unwrap();
+        space.getp(new_template!(n.actual())).unwrap();
+    }
+    // The log was compacted along the way, it would hold 2001 records otherwise
+    let log = fs::read(dir.join("log")).unwrap();
+    assert!(log.iter().filter(|b| **b == b'\n').count() <= 1001);
+    assert!(dir.join("snapshot").exists());
+
+    space.put(new_tuple!(1001)).unwrap();
+    space.compact().unwrap();
+    assert_eq!(0, fs::metadata(dir.join("log")).unwrap().len());
+    space.put(new_tuple!(1002)).unwrap();
+    drop(space);
+
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    assert_eq!(vec![0, 1001, 1002], numbers(space.getall(any()).unwrap()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn expiry_survives_restarts() {
+    let dir = dir("expiry");
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    space
+        .put_with_ttl(new_tuple!(1), Duration::from_millis(300))
+        .unwrap();
+    space
+        .put_with_ttl(new_tuple!(2), Duration::from_secs(3600))
+        .unwrap();
+    space.put(new_tuple!(3)).unwrap();
+    drop(space);
+
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    assert_eq!(vec![1, 2, 3], numbers(space.queryall(any()).unwrap()));
+    thread::sleep(Duration::from_millis(400));
+    assert_eq!(vec![2, 3], numbers(space.queryall(any()).unwrap()));
+    // The reaper removes the expired tuple from the log as well
+    let reaped = || String::from_utf8(fs::read(dir.join("log")).unwrap()).unwrap();
+    for _ in 0..20 {
+        if reaped().contains("Remove") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(reaped().contains("Remove"));
+    space.compact().unwrap();
+    drop(space);
+
+    // The lease of the other tuple was kept through the snapshot
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    let renewed = space.renew(any(), Duration::from_secs(60)).unwrap();
+    assert_eq!(vec![2], numbers(renewed));
+
+    // A lease too long to be represented is dropped, in the log as well
+    space.renew(any(), Duration::MAX).unwrap();
+    drop(space);
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    assert_eq!(vec![2, 3], numbers(space.queryall(any()).unwrap()));
+    assert!(space
+        .renew(any(), Duration::from_secs(60))
+        .unwrap()
+        .is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rolled_back_transaction() {
+    let dir = dir("transaction");
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    space.put(new_tuple!(1)).unwrap();
+    space
+        .put_with_ttl(new_tuple!(2), Duration::from_secs(3600))
+        .unwrap();
+    let result = space.transaction(|tx| {
+        tx.getall(any())?;
+        tx.put(new_tuple!(3))?;
+        tx.getp(new_template!(4.actual()))
+    });
+    assert!(matches!(result, Err(Error::NoMatch)));
+    space
+        .transaction(|tx| {
+            tx.getp(new_template!(1.actual()))?;
+            tx.put(new_tuple!(5))
+        })
+        .unwrap();
+    drop(space);
+
+    // Only the committed transaction reached the log, and the lease survived the rollback
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    assert_eq!(vec![2, 5], numbers(space.queryall(any()).unwrap()));
+    let renewed = space.renew(any(), Duration::from_secs(60)).unwrap();
+    assert_eq!(vec![2], numbers(renewed));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn truncated_transaction() {
+    let dir = dir("truncated-transaction");
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    space.put(new_tuple!(1)).unwrap();
+    space
+        .put_with_ttl(new_tuple!(2), Duration::from_secs(3600))
+        .unwrap();
+    space
+        .transaction(|tx| {
+            tx.getp(new_template!(1.actual()))?;
+            tx.put(new_tuple!(5))
+        })
+        .unwrap();
+    drop(space);
+
+    // The process died while logging the transaction, which is lost as a whole
+    cut_last_line(&dir);
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    assert_eq!(vec![1, 2], numbers(space.queryall(any()).unwrap()));
+    let renewed = space.renew(any(), Duration::from_secs(60)).unwrap();
+    assert_eq!(vec![2], numbers(renewed));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unreadable_tuples_are_refused() {
+    let dir = dir("floats");
+    let space = Arc::new(LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap());
+    let events = space.subscribe(any_pair());
+    let reader = Arc::clone(&space);
+    let waiting = thread::spawn(move || reader.get_timeout(any_pair(), Duration::from_secs(1)));
+    thread::sleep(Duration::from_millis(50));
+    for x in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        assert!(matches!(
+            space.put(new_tuple!(1, x)),
+            Err(Error::Serialization(_))
+        ));
+    }
+    // Nobody saw the tuples, which were never in the space
+    assert!(matches!(waiting.join().unwrap(), Err(Error::TimedOut)));
+    assert!(events.try_recv().is_err());
+    assert!(space.is_empty());
+    space.put(new_tuple!(2, 0.5)).unwrap();
+    drop((space, events));
+
+    let space = LocalSpace::open_persistent(&dir, SpaceType::Sequential).unwrap();
+    assert_eq!(vec![2], numbers(space.queryall(any_pair()).unwrap()));
+    fs::remove_dir_all(&dir).unwrap();
+}
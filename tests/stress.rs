@@ -0,0 +1,76 @@
+use rspaces::{new_template, new_tuple, FieldType, LocalSpace, Space, Template, Tuple, TupleField};
+use std::{sync::Arc, thread, time::Duration};
+
+const READERS: i32 = 2000;
+
+/// Waits until every reader is blocked on the space
+fn wait_for_readers(space: &LocalSpace) {
+    while space.waiting() < READERS as usize {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert!(space.is_empty());
+}
+
+#[test]
+fn thousands_of_blocked_readers() {
+    let space = Arc::new(LocalSpace::new_sequential());
+    let handles: Vec<_> = (0..READERS)
+        .map(|i| {
+            let reader = Arc::clone(&space);
+            thread::spawn(move || {
+                let t = reader.get(new_template!(i.actual(), 'a'.formal())).unwrap();
+                assert_eq!(i, *t.get_field::<i32>(0));
+            })
+        })
+        .collect();
+    wait_for_readers(&space);
+
+    //Tuples nobody waits for should not disturb the readers
+    for i in 0..READERS {
+        space.put(new_tuple!(i, 0.5)).unwrap();
+    }
+    assert_eq!(READERS as usize, space.waiting());
+    // Every tuple goes to the one reader waiting for it, who is no longer waiting once it is put
+    for i in (0..READERS).rev() {
+        space.put(new_tuple!(i, 'a')).unwrap();
+        assert_eq!(i as usize, space.waiting());
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(READERS as usize, space.len());
+}
+
+#[test]
+fn thousands_of_readers_on_one_template() {
+    let space = Arc::new(LocalSpace::new_sequential());
+    let handles: Vec<_> = (0..READERS)
+        .map(|_| {
+            let reader = Arc::clone(&space);
+            thread::spawn(move || reader.get(new_template!(0.formal())).unwrap())
+        })
+        .collect();
+    wait_for_readers(&space);
+
+    let writers: Vec<_> = (0..4)
+        .map(|w| {
+            let writer = Arc::clone(&space);
+            thread::spawn(move || {
+                for i in 0..READERS / 4 {
+                    writer.put(new_tuple!(w * READERS + i)).unwrap();
+                }
+            })
+        })
+        .collect();
+    for w in writers {
+        w.join().unwrap();
+    }
+    let mut received: Vec<i32> = handles
+        .into_iter()
+        .map(|h| *h.join().unwrap().get_field::<i32>(0))
+        .collect();
+    received.sort();
+    received.dedup();
+    assert_eq!(READERS as usize, received.len());
+    assert!(space.is_empty());
+}
@@ -1,18 +1,31 @@
 #[cfg(test)]
+// The baseline tests predate these lints and are kept as they were written
+#[allow(
+    clippy::assertions_on_constants,
+    clippy::get_first,
+    clippy::explicit_auto_deref,
+    clippy::cmp_owned
+)]
 mod tests {
     use core::time;
     use rspace_macro::TupleField;
     use rspaces::{
-        new_template, new_tuple, FieldType, LocalSpace, Message, MessageType, RemoteSpace,
-        Repository, Space, Template, TemplateType, Tuple, TupleField,
+        any, new_template, new_tuple, one_of, regex, starts_with, Authenticator, Codec,
+        ConnectOptions, Credentials, Endpoint, Error, ErrorCode, FieldError, FieldType, Hello,
+        IntoTuple, LocalSpace, Message, MessageType, Operation, OrderedFieldType, Permissions,
+        RangeFieldType, Rejection, RemoteSpace, Repository, Space, SpaceEvent, SpaceUri, Template,
+        TemplateType, Tuple, TupleField, UriError, Welcome, EVERYONE, MAX_FRAME_SIZE,
+        PROTOCOL_VERSION,
     };
     use serde::{Deserialize, Serialize};
     use std::{
         any::Any,
         io::{Read, Write},
         net::TcpStream,
-        sync::Arc,
+        panic::{self, AssertUnwindSafe},
+        sync::{mpsc::RecvTimeoutError, Arc},
         thread,
+        time::Duration,
     };
 
     #[test]
@@ -446,6 +459,27 @@ mod tests {
         y: f64,
     }
 
+    #[derive(Serialize, Deserialize, Clone, PartialEq, PartialOrd, TupleField, Debug)]
+    struct Version(u32, u32);
+
+    #[test]
+    fn derived_ordered_fields() {
+        let space = LocalSpace::new_sequential();
+        space.put(new_tuple!(Version(1, 4))).unwrap();
+        space.put(new_tuple!(Version(2, 0))).unwrap();
+        let tuple = space
+            .queryp(new_template!(Version(1, 9).greater()))
+            .unwrap();
+        assert_eq!(Version(2, 0), *tuple.get_field::<Version>(0));
+        let template = new_template!((Version(1, 0)..Version(2, 0)).range());
+        assert_eq!(1, space.queryall(template).unwrap().len());
+        // Derived types without an ordering still match on equality
+        space.put(new_tuple!(TestStruct { x: 1, y: 2.0 })).unwrap();
+        assert!(space
+            .queryp(new_template!(TestStruct { x: 1, y: 2.0 }.actual()))
+            .is_ok());
+    }
+
     #[test]
     fn seri_test_custom() {
         let a: i32 = 5;
@@ -502,6 +536,7 @@ mod tests {
     fn message_test() {
         let space = LocalSpace::new_sequential();
         let m = Message {
+            id: 0,
             action: MessageType::Put,
             tuple: Vec::from([new_tuple!(5, 'b')]),
             template: new_template!(),
@@ -514,6 +549,7 @@ mod tests {
         assert_eq!('b', *tuple.get_field::<char>(1));
         space.put(tuple).unwrap();
         let m = Message {
+            id: 0,
             action: MessageType::Get,
             tuple: Vec::new(),
             template: new_template!(5.actual(), 'a'.formal()),
@@ -535,23 +571,15 @@ mod tests {
         thread::spawn(move || match TcpStream::connect("localhost:3800") {
             Ok(mut stream) => {
                 let m = Message {
+                    id: 0,
                     action: MessageType::Get,
                     tuple: Vec::new(),
                     template: new_template!(5.actual(), 'b'.formal()),
                 };
-                let m_json = serde_json::to_string(&m).unwrap();
-                let mut buffer = [0; 1024];
-                let spacetext = "space".as_bytes();
-                stream.write(spacetext).unwrap();
-                stream.flush().expect("should flush");
-                let n = stream.read(&mut buffer).unwrap();
-                let inc_string = String::from_utf8_lossy(&buffer[..n]);
-                assert_eq!("t", inc_string);
-                stream.write(m_json.as_bytes()).unwrap();
-
-                let n = stream.read(&mut buffer).unwrap();
-                let inc_string = String::from_utf8_lossy(&buffer[..n]);
-                let mut message = serde_json::from_str::<Message>(&inc_string).unwrap();
+                greet(&mut stream, "space");
+                write_message(&mut stream, &m);
+
+                let mut message = read_message(&mut stream);
                 let tuple = message.tuple.remove(0);
                 assert_eq!(5, *tuple.get_field::<i32>(0));
                 assert_eq!('b', *tuple.get_field::<char>(1));
@@ -629,4 +657,1966 @@ mod tests {
         assert_eq!(5, *t.get_field::<i32>(0));
         assert_eq!('b', *t.get_field::<char>(1));
     }
+
+    fn write_message(stream: &mut TcpStream, m: &Message) {
+        let m_json = serde_json::to_vec(m).unwrap();
+        let mut frame = (m_json.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&m_json);
+        stream.write_all(&frame).unwrap();
+    }
+
+    fn read_message(stream: &mut TcpStream) -> Message {
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).unwrap();
+        let mut payload = vec![0; u32::from_be_bytes(header) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        serde_json::from_slice::<Message>(&payload).unwrap()
+    }
+
+    fn connect_raw(addr: &str, space: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        greet(&mut stream, space);
+        stream
+    }
+
+    /// Asks the gate for the space with a json hello, expecting to be let in
+    fn greet(stream: &mut TcpStream, space: &str) {
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            codec: String::from("json"),
+            client_id: String::new(),
+            space: space.to_string(),
+            credentials: None,
+        };
+        let payload = serde_json::to_vec(&hello).unwrap();
+        stream
+            .write_all(&(payload.len() as u32).to_be_bytes())
+            .unwrap();
+        stream.write_all(&payload).unwrap();
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).unwrap();
+        let mut payload = vec![0; u32::from_be_bytes(header) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        let welcome = serde_json::from_slice(&payload).unwrap();
+        assert!(matches!(welcome, Welcome::Accepted { .. }));
+    }
+
+    #[test]
+    fn gate_large_payload() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3802"),
+        )
+        .expect("could not connect");
+        let remote = RemoteSpace::new(String::from("127.0.0.1:3802/space")).unwrap();
+        let text = "x".repeat(64 * 1024);
+        for i in 0..10 {
+            remote.put(new_tuple!(i, text.clone())).unwrap();
+        }
+        let template = new_template!(0.formal(), String::new().formal());
+        let tuples = remote.getall(template).unwrap();
+        assert_eq!(10, tuples.len());
+        for tuple in tuples.iter() {
+            assert_eq!(text, *tuple.get_field::<String>(1));
+        }
+        repo.close_gate(String::from("gate"));
+    }
+
+    #[test]
+    fn gate_coalesced_frames() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3803"),
+        )
+        .expect("could not connect");
+        let mut stream = connect_raw("127.0.0.1:3803", "space");
+        let mut bytes = Vec::new();
+        for i in 0..2 {
+            let m = Message {
+                id: 0,
+                action: MessageType::Put,
+                tuple: Vec::from([new_tuple!(i, 'a')]),
+                template: new_template!(),
+            };
+            let m_json = serde_json::to_vec(&m).unwrap();
+            bytes.extend_from_slice(&(m_json.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&m_json);
+        }
+        stream.write_all(&bytes).unwrap();
+        assert_eq!(MessageType::Ok, read_message(&mut stream).action);
+        assert_eq!(MessageType::Ok, read_message(&mut stream).action);
+        let tuples = space
+            .queryall(new_template!(0.formal(), 'a'.formal()))
+            .unwrap();
+        assert_eq!(2, tuples.len());
+        repo.close_gate(String::from("gate"));
+    }
+
+    #[test]
+    fn gate_oversized_frame() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3804"),
+        )
+        .expect("could not connect");
+        let mut stream = connect_raw("127.0.0.1:3804", "space");
+        stream
+            .write_all(&(MAX_FRAME_SIZE as u32 + 1).to_be_bytes())
+            .unwrap();
+        match read_message(&mut stream).action {
+            MessageType::Error { code, .. } => assert_eq!(ErrorCode::Protocol, code),
+            action => panic!("expected an error reply but got {:?}", action),
+        }
+        let mut buffer = [0; 1];
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => {}
+            Ok(_) => panic!("gate should have closed the connection"),
+        }
+        repo.close_gate(String::from("gate"));
+    }
+
+    #[test]
+    fn get_timeout() {
+        let sender = Arc::new(LocalSpace::new_sequential());
+        let reciever = Arc::clone(&sender);
+        let q = new_template!(5.actual(), 'a'.formal());
+        let err = reciever
+            .get_timeout(q.clone(), Duration::from_millis(50))
+            .unwrap_err();
+        assert!(matches!(err, Error::TimedOut));
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            sender.put(new_tuple!(5, 'b')).unwrap();
+        });
+        let t = reciever
+            .get_timeout(q.clone(), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!('b', *t.get_field::<char>(1));
+        assert!(reciever.queryp(q).is_err());
+    }
+
+    #[test]
+    fn query_timeout() {
+        let sender = Arc::new(LocalSpace::new_sequential());
+        let reciever = Arc::clone(&sender);
+        let q = new_template!(5.actual(), 'a'.formal());
+        let err = reciever
+            .query_timeout(q.clone(), Duration::from_millis(50))
+            .unwrap_err();
+        assert!(matches!(err, Error::TimedOut));
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            sender.put(new_tuple!(5, 'b')).unwrap();
+        });
+        let t = reciever
+            .query_timeout(q.clone(), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!('b', *t.get_field::<char>(1));
+        assert!(reciever.queryp(q).is_ok());
+    }
+
+    #[test]
+    fn gate_timeout() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3805"),
+        )
+        .expect("could not connect");
+        let remote = RemoteSpace::new(String::from("127.0.0.1:3805/space")).unwrap();
+        let q = new_template!(5.actual(), 'a'.formal());
+        let err = remote
+            .get_timeout(q.clone(), Duration::from_millis(50))
+            .unwrap_err();
+        assert!(matches!(err, Error::TimedOut));
+        let err = remote
+            .query_timeout(q.clone(), Duration::from_millis(50))
+            .unwrap_err();
+        assert!(matches!(err, Error::TimedOut));
+
+        space.put(new_tuple!(5, 'b')).unwrap();
+        let t = remote
+            .query_timeout(q.clone(), Duration::from_secs(5))
+            .unwrap();
+        assert_eq!('b', *t.get_field::<char>(1));
+        let t = remote.get_timeout(q, Duration::from_secs(5)).unwrap();
+        assert_eq!('b', *t.get_field::<char>(1));
+        repo.close_gate(String::from("gate"));
+    }
+
+    #[test]
+    fn error_kinds() {
+        let space = LocalSpace::new_sequential();
+        let q = new_template!(5.actual(), 'a'.formal());
+        assert!(matches!(space.getp(q.clone()), Err(Error::NoMatch)));
+        assert!(matches!(space.queryp(q), Err(Error::NoMatch)));
+        assert!(matches!(
+            RemoteSpace::new(String::from("127.0.0.1:3806")),
+            Err(Error::InvalidAddress(_))
+        ));
+        assert!(matches!(
+            RemoteSpace::new(String::from("127.0.0.1:1/space")),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn gate_error_kinds() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3806"),
+        )
+        .expect("could not connect");
+        assert!(matches!(
+            RemoteSpace::new(String::from("127.0.0.1:3806/nospace")),
+            Err(Error::SpaceNotFound)
+        ));
+        let remote = RemoteSpace::new(String::from("127.0.0.1:3806/space")).unwrap();
+        let q = new_template!(5.actual(), 'a'.formal());
+        assert!(matches!(remote.getp(q.clone()), Err(Error::NoMatch)));
+        assert!(matches!(remote.queryp(q), Err(Error::NoMatch)));
+        repo.close_gate(String::from("gate"));
+    }
+
+    #[test]
+    fn try_get_field() {
+        let tuple = new_tuple!(5, 'b');
+        assert_eq!(Ok(&5), tuple.try_get_field::<i32>(0));
+        assert_eq!(Ok(&'b'), tuple.try_get_field::<char>(1));
+        assert_eq!(
+            Err(FieldError::OutOfBounds { index: 2, len: 2 }),
+            tuple.try_get_field::<char>(2)
+        );
+        match tuple.try_get_field::<u64>(0) {
+            Err(FieldError::TypeMismatch { index, found, .. }) => {
+                assert_eq!(0, index);
+                assert_eq!("i32", found);
+            }
+            other => panic!("expected a type mismatch but got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn typed_tuples() {
+        let space = LocalSpace::new_sequential();
+        space.put((5, String::from("name"))).unwrap();
+        space.put((6, TestStruct { x: 1, y: 2.0 }, true)).unwrap();
+
+        let t = space
+            .getp(new_template!(5.actual(), String::new().formal()))
+            .unwrap();
+        let (id, name): (i32, String) = t.into_typed().unwrap();
+        assert_eq!(5, id);
+        assert_eq!("name", name);
+
+        let t = space
+            .queryp(new_template!(
+                6.actual(),
+                TestStruct { x: 0, y: 0.0 }.formal(),
+                true.formal()
+            ))
+            .unwrap();
+        let (_, s, flag): (i32, TestStruct, bool) = t.clone().into_typed().unwrap();
+        assert_eq!(1, s.x);
+        assert!(flag);
+        assert_eq!(
+            Err(FieldError::ArityMismatch {
+                expected: 2,
+                found: 3
+            }),
+            t.clone().into_typed::<(i32, TestStruct)>()
+        );
+        assert!(matches!(
+            t.into_typed::<(i32, i32, bool)>(),
+            Err(FieldError::TypeMismatch { index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn put_through_dyn_space() {
+        let space = Arc::new(LocalSpace::new_sequential());
+        let spaces: Vec<Arc<dyn Space>> = vec![space.clone(), Arc::new(space.clone())];
+        for (n, dynamic) in spaces.iter().enumerate() {
+            dynamic.put_tuple(new_tuple!(n as i32, 'a')).unwrap();
+            dynamic.put_tuple((n as i32, 'b').into_tuple()).unwrap();
+        }
+        // An Arc of a trait object is a space itself, so it can take rust tuples with put
+        spaces[0].put((2, 'c')).unwrap();
+        let found: Vec<(i32, char)> = space
+            .snapshot()
+            .into_iter()
+            .map(|t| t.into_typed().unwrap())
+            .collect();
+        assert_eq!(
+            vec![(0, 'a'), (0, 'b'), (1, 'a'), (1, 'b'), (2, 'c')],
+            found
+        );
+    }
+
+    fn put_mixed_arity(space: &LocalSpace) {
+        space.put(new_tuple!(1)).unwrap();
+        space.put(new_tuple!(2, 'a')).unwrap();
+        space.put(new_tuple!(3, 'b', true)).unwrap();
+        space.put(new_tuple!(4, 'c')).unwrap();
+    }
+
+    #[test]
+    fn arity_sequential() {
+        let space = LocalSpace::new_sequential();
+        put_mixed_arity(&space);
+        let t = space.getp(new_template!(0.formal(), 'a'.formal())).unwrap();
+        assert_eq!(2, *t.get_field::<i32>(0));
+        let t = space.getp(new_template!(0.formal())).unwrap();
+        assert_eq!(1, t.len());
+        assert!(space.getp(new_template!(0.formal())).is_err());
+        let t = space.getp(new_template!(0.formal()).prefix()).unwrap();
+        assert_eq!(3, *t.get_field::<i32>(0));
+        assert!(space.queryall(new_template!()).unwrap().is_empty());
+        assert_eq!(
+            1,
+            space
+                .queryall(new_template!(0.formal(), 'a'.formal()))
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn arity_queue() {
+        let space = LocalSpace::new_queue();
+        put_mixed_arity(&space);
+        assert!(space.getp(new_template!(0.formal(), 'a'.formal())).is_err());
+        let t = space.getp(new_template!(0.formal()).prefix()).unwrap();
+        assert_eq!(1, *t.get_field::<i32>(0));
+        assert!(space.getp(new_template!(0.formal())).is_err());
+        let t = space.getp(new_template!(0.formal(), 'a'.formal())).unwrap();
+        assert_eq!(2, *t.get_field::<i32>(0));
+    }
+
+    #[test]
+    fn arity_stack() {
+        let space = LocalSpace::new_stack();
+        put_mixed_arity(&space);
+        assert!(space
+            .getp(new_template!(0.formal(), 'a'.formal(), true.formal()))
+            .is_err());
+        let t = space.getp(new_template!(0.formal()).prefix()).unwrap();
+        assert_eq!(4, *t.get_field::<i32>(0));
+        let t = space
+            .getp(new_template!(0.formal(), 'a'.formal(), true.formal()))
+            .unwrap();
+        assert_eq!(3, *t.get_field::<i32>(0));
+    }
+
+    #[test]
+    fn arity_pile() {
+        let space = LocalSpace::new_pile();
+        put_mixed_arity(&space);
+        let t = space.getp(new_template!(0.formal())).unwrap();
+        assert_eq!(1, *t.get_field::<i32>(0));
+        let t = space.getp(new_template!(0.formal(), 'a'.formal())).unwrap();
+        assert_eq!(4, *t.get_field::<i32>(0));
+        let t = space
+            .getp(new_template!(0.formal(), 'a'.formal()).prefix())
+            .unwrap();
+        assert_eq!(3, *t.get_field::<i32>(0));
+        let all = space.getall(new_template!().prefix()).unwrap();
+        assert_eq!(1, all.len());
+    }
+
+    #[test]
+    fn matcher_fields() {
+        let space = LocalSpace::new_sequential();
+        for priority in 1..=6 {
+            space
+                .put(new_tuple!(format!("job-{}", priority), priority))
+                .unwrap();
+        }
+        let count = |template: Template| space.queryall(template).unwrap().len();
+        assert_eq!(3, count(new_template!(any(), 3.greater())));
+        assert_eq!(4, count(new_template!(any(), 3.at_least())));
+        assert_eq!(2, count(new_template!(any(), 3.less())));
+        assert_eq!(3, count(new_template!(any(), 3.at_most())));
+        assert_eq!(3, count(new_template!(any(), (2..5).range())));
+        assert_eq!(4, count(new_template!(any(), (2..=5).range())));
+        assert_eq!(2, count(new_template!(any(), one_of([1, 6, 9]))));
+        assert_eq!(6, count(new_template!(starts_with("job-"), any())));
+        assert_eq!(0, count(new_template!(starts_with("task-"), any())));
+        assert_eq!(
+            2,
+            count(new_template!(regex(r"^job-[45]$").unwrap(), any()))
+        );
+        // Matchers only compare against fields of the same type
+        let wide: i64 = 0;
+        assert_eq!(0, count(new_template!(any(), wide.greater())));
+        assert_eq!(0, count(new_template!(any(), 'a'.greater())));
+        assert!(matches!(regex("(unclosed"), Err(Error::InvalidPattern(_))));
+    }
+
+    #[test]
+    fn matcher_fields_serialization() {
+        let space = LocalSpace::new_sequential();
+        space
+            .put(new_tuple!(String::from("job-1"), 4, 'c'))
+            .unwrap();
+        let template = new_template!(regex("^job").unwrap(), (1..10).range(), one_of(['b', 'c']));
+        let t_json = serde_json::to_string(&template).unwrap();
+        let template: Template = serde_json::from_str(&t_json).unwrap();
+        assert!(space.queryp(template).is_ok());
+        let template = new_template!(starts_with("job"), 3.greater(), any());
+        let t_json = serde_json::to_string(&template).unwrap();
+        let template: Template = serde_json::from_str(&t_json).unwrap();
+        assert!(space.queryp(template).is_ok());
+    }
+
+    #[test]
+    fn gate_matcher_fields() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3812"),
+        )
+        .expect("could not connect");
+        let remote = RemoteSpace::new(String::from("127.0.0.1:3812/space")).unwrap();
+        for priority in 1..=6 {
+            remote
+                .put(new_tuple!(String::from("job"), priority))
+                .unwrap();
+        }
+        let template = new_template!(starts_with("jo"), 3.greater());
+        assert_eq!(3, remote.getall(template).unwrap().len());
+        let t = remote
+            .getp(new_template!(regex("b$").unwrap(), (1..3).range()))
+            .unwrap();
+        assert_eq!(1, *t.get_field::<i32>(1));
+        repo.close_gate(String::from("gate"));
+    }
+
+    fn is_even(t: &Tuple) -> bool {
+        t.try_get_field::<i32>(0).is_ok_and(|x| x % 2 == 0)
+    }
+
+    #[test]
+    fn closure_queries() {
+        let space = LocalSpace::new_sequential();
+        for i in 1..=6 {
+            space.put(new_tuple!(i, 'a')).unwrap();
+        }
+        space.put(new_tuple!(String::from("seven"))).unwrap();
+
+        let t = space.queryp_where(is_even).unwrap();
+        assert_eq!(2, *t.get_field::<i32>(0));
+        let t = space.getp_where(is_even).unwrap();
+        assert_eq!(2, *t.get_field::<i32>(0));
+        assert_eq!(2, space.queryall_where(is_even).unwrap().len());
+        assert_eq!(6, space.queryall_where(|_| true).unwrap().len());
+
+        let tuples = space.getall_where(is_even).unwrap();
+        let values: Vec<i32> = tuples.iter().map(|t| *t.get_field::<i32>(0)).collect();
+        assert_eq!(vec![4, 6], values);
+        assert!(matches!(space.getp_where(is_even), Err(Error::NoMatch)));
+        assert!(space.queryp_where(|t| t.len() == 1).is_ok());
+    }
+
+    #[test]
+    fn closure_queries_ordering() {
+        let queue = LocalSpace::new_queue();
+        let stack = LocalSpace::new_stack();
+        let pile = LocalSpace::new_pile();
+        for i in 1..=4 {
+            queue.put(new_tuple!(i)).unwrap();
+            stack.put(new_tuple!(i)).unwrap();
+            pile.put(new_tuple!(i)).unwrap();
+        }
+        //Queues and stacks only look at the first and last tuple
+        assert!(queue.getp_where(is_even).is_err());
+        assert!(stack.getp_where(|t| !is_even(t)).is_err());
+        assert_eq!(4, *stack.getp_where(is_even).unwrap().get_field::<i32>(0));
+        assert_eq!(
+            3,
+            *pile
+                .getp_where(|t| !is_even(t))
+                .unwrap()
+                .get_field::<i32>(0)
+        );
+    }
+
+    #[test]
+    fn closure_queries_random() {
+        let space = LocalSpace::new_random();
+        for i in 1..=10 {
+            space.put(new_tuple!(i)).unwrap();
+        }
+        for _ in 0..5 {
+            let t = space.getp_where(is_even).unwrap();
+            assert!(is_even(&t));
+        }
+        assert!(space.getp_where(is_even).is_err());
+        assert_eq!(5, space.queryall_where(|_| true).unwrap().len());
+    }
+
+    #[test]
+    fn closure_queries_block() {
+        let sender = Arc::new(LocalSpace::new_sequential());
+        let reciever = Arc::clone(&sender);
+        let handle = thread::spawn(move || reciever.get_where(is_even).unwrap());
+        thread::sleep(Duration::from_millis(50));
+        sender.put(new_tuple!(3)).unwrap();
+        sender.put(new_tuple!(8)).unwrap();
+        let t = handle.join().unwrap();
+        assert_eq!(8, *t.get_field::<i32>(0));
+        assert_eq!(
+            3,
+            *sender.query_where(|_| true).unwrap().get_field::<i32>(0)
+        );
+    }
+
+    #[test]
+    fn indexed_queries_match_scan() {
+        let space = LocalSpace::new_sequential();
+        for i in 0..500 {
+            match i % 4 {
+                0 => space.put(new_tuple!(i % 10, 'a')).unwrap(),
+                1 => space
+                    .put(new_tuple!(String::from("job"), i % 7, true))
+                    .unwrap(),
+                2 => space.put(new_tuple!(i as u64)).unwrap(),
+                _ => space.put(new_tuple!('x', i % 3, 2.5)).unwrap(),
+            }
+        }
+        let templates = vec![
+            new_template!(3.actual(), 'a'.formal()),
+            new_template!(String::from("job").actual(), 4.actual(), true.formal()),
+            new_template!(String::from("job").actual()).prefix(),
+            new_template!('x'.actual(), 1.actual(), 0.0.formal()),
+            new_template!(10u64.greater()),
+            new_template!(any(), 2.actual()).prefix(),
+            new_template!(one_of([1, 2]), any()),
+            new_template!(starts_with("jo"), 3.less(), any()),
+            new_template!(7.actual(), 'b'.actual()),
+            new_template!(2.5.actual()),
+        ];
+        for template in templates {
+            let indexed = space.queryall(template.clone()).unwrap();
+            let scanned = space.queryall_where(|t| template.query(t)).unwrap();
+            assert_eq!(format!("{:?}", scanned), format!("{:?}", indexed));
+        }
+        assert_eq!(500, space.len());
+    }
+
+    #[test]
+    fn getall_keeps_order() {
+        let space = LocalSpace::new_sequential();
+        for i in 0..10 {
+            space.put(new_tuple!(i % 2, i)).unwrap();
+        }
+        let odd = space.getall(new_template!(1.actual(), 0.formal())).unwrap();
+        let values: Vec<i32> = odd.iter().map(|t| *t.get_field::<i32>(1)).collect();
+        assert_eq!(vec![1, 3, 5, 7, 9], values);
+        let even = space
+            .queryall(new_template!(0.formal(), 0.formal()))
+            .unwrap();
+        let values: Vec<i32> = even.iter().map(|t| *t.get_field::<i32>(1)).collect();
+        assert_eq!(vec![0, 2, 4, 6, 8], values);
+    }
+
+    #[test]
+    fn indexed_ordering() {
+        let sequential = LocalSpace::new_sequential();
+        let pile = LocalSpace::new_pile();
+        for i in 0..100 {
+            sequential.put(new_tuple!(i % 5, i)).unwrap();
+            pile.put(new_tuple!(i % 5, i)).unwrap();
+        }
+        let template = new_template!(3.actual(), 0.formal());
+        for expected in [3, 8, 13] {
+            let t = sequential.getp(template.clone()).unwrap();
+            assert_eq!(expected, *t.get_field::<i32>(1));
+        }
+        for expected in [98, 93, 88] {
+            let t = pile.getp(template.clone()).unwrap();
+            assert_eq!(expected, *t.get_field::<i32>(1));
+        }
+    }
+
+    #[test]
+    fn indexed_custom_fields() {
+        let space = LocalSpace::new_sequential();
+        space
+            .put(new_tuple!(TestStruct { x: 1, y: 0.5 }, 5))
+            .unwrap();
+        space
+            .put(new_tuple!(TestStruct { x: 2, y: 0.5 }, 6))
+            .unwrap();
+        let t = space
+            .getp(new_template!(
+                TestStruct { x: 2, y: 0.5 }.actual(),
+                0.formal()
+            ))
+            .unwrap();
+        assert_eq!(6, *t.get_field::<i32>(1));
+        assert!(space
+            .getp(new_template!(
+                TestStruct { x: 3, y: 0.5 }.actual(),
+                0.formal()
+            ))
+            .is_err());
+    }
+
+    /// Waits until as many readers are blocked on the space
+    fn await_waiting(space: &LocalSpace, readers: usize) {
+        while space.waiting() < readers {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn blocked_readers_fifo() {
+        let space = Arc::new(LocalSpace::new_sequential());
+        let mut handles = Vec::new();
+        for _ in 0..5 {
+            let reader = Arc::clone(&space);
+            handles.push(thread::spawn(move || {
+                let t = reader.get(new_template!(1.actual(), 0.formal())).unwrap();
+                *t.get_field::<i32>(1)
+            }));
+            // Each reader blocks before the next one comes
+            await_waiting(&space, handles.len());
+        }
+        for i in 0..5 {
+            space.put(new_tuple!(1, i)).unwrap();
+        }
+        let received: Vec<i32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(vec![0, 1, 2, 3, 4], received);
+        assert!(space.is_empty());
+    }
+
+    #[test]
+    fn blocked_query_and_get() {
+        let space = Arc::new(LocalSpace::new_sequential());
+        let querier = Arc::clone(&space);
+        let query = thread::spawn(move || querier.query(new_template!(2.actual())).unwrap());
+        await_waiting(&space, 1);
+        let getter = Arc::clone(&space);
+        let get = thread::spawn(move || getter.get(new_template!(2.actual())).unwrap());
+        await_waiting(&space, 2);
+        let other = Arc::clone(&space);
+        let blocked = thread::spawn(move || {
+            other.get_timeout(new_template!(2.actual()), Duration::from_millis(200))
+        });
+        await_waiting(&space, 3);
+
+        space.put(new_tuple!(1)).unwrap();
+        space.put(new_tuple!(2)).unwrap();
+        assert_eq!(2, *query.join().unwrap().get_field::<i32>(0));
+        assert_eq!(2, *get.join().unwrap().get_field::<i32>(0));
+        assert!(matches!(blocked.join().unwrap(), Err(Error::TimedOut)));
+        assert_eq!(1, space.len());
+    }
+
+    #[test]
+    fn blocked_queue_reader_wakes_on_new_head() {
+        let space = Arc::new(LocalSpace::new_queue());
+        space.put(new_tuple!('a')).unwrap();
+        let reader = Arc::clone(&space);
+        let handle = thread::spawn(move || reader.get(new_template!('b'.actual())).unwrap());
+        await_waiting(&space, 1);
+        space.put(new_tuple!('b')).unwrap();
+        // The tuple is behind the head, so the reader keeps waiting
+        assert_eq!(1, space.waiting());
+        space.getp(new_template!('a'.actual())).unwrap();
+        assert_eq!('b', *handle.join().unwrap().get_field::<char>(0));
+        assert!(space.is_empty());
+    }
+
+    #[test]
+    fn blocked_queue_readers_see_head_exposed_by_get() {
+        let space = Arc::new(LocalSpace::new_queue());
+        space.put(new_tuple!('c')).unwrap();
+        let first = Arc::clone(&space);
+        let b = thread::spawn(move || {
+            first.get_timeout(new_template!('b'.actual()), Duration::from_secs(3))
+        });
+        await_waiting(&space, 1);
+        let second = Arc::clone(&space);
+        let a = thread::spawn(move || {
+            second.get_timeout(new_template!('a'.actual()), Duration::from_secs(3))
+        });
+        await_waiting(&space, 2);
+        space.put(new_tuple!('a')).unwrap();
+        space.put(new_tuple!('b')).unwrap();
+        space.getp(new_template!('c'.actual())).unwrap();
+        assert_eq!('a', *a.join().unwrap().unwrap().get_field::<char>(0));
+        assert_eq!('b', *b.join().unwrap().unwrap().get_field::<char>(0));
+        assert!(space.is_empty());
+    }
+
+    #[test]
+    fn blocked_closure_reader() {
+        let space = Arc::new(LocalSpace::new_sequential());
+        let closure_reader = Arc::clone(&space);
+        let closure = thread::spawn(move || closure_reader.get_where(|t| t.len() == 2).unwrap());
+        await_waiting(&space, 1);
+        let template_reader = Arc::clone(&space);
+        let template =
+            thread::spawn(move || template_reader.get(new_template!(3.actual())).unwrap());
+        await_waiting(&space, 2);
+        space.put(new_tuple!(3)).unwrap();
+        space.put(new_tuple!(3, 4)).unwrap();
+        assert_eq!(3, *template.join().unwrap().get_field::<i32>(0));
+        assert_eq!(4, *closure.join().unwrap().get_field::<i32>(1));
+        assert!(space.is_empty());
+    }
+
+    #[test]
+    fn remote_requests_share_a_connection() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3816"),
+        )
+        .expect("could not connect");
+        let remote = Arc::new(RemoteSpace::new(String::from("127.0.0.1:3816/space")).unwrap());
+        let readers: Vec<_> = (0..3)
+            .map(|i| {
+                let remote = Arc::clone(&remote);
+                thread::spawn(move || remote.get(new_template!(i.actual())).unwrap())
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(50));
+        // The blocked gets do not hold up requests sent after them
+        remote.put(new_tuple!(9)).unwrap();
+        assert_eq!(
+            9,
+            *remote
+                .getp(new_template!(9.actual()))
+                .unwrap()
+                .get_field::<i32>(0)
+        );
+        for i in (0..3).rev() {
+            remote.put(new_tuple!(i)).unwrap();
+        }
+        for (i, reader) in readers.into_iter().enumerate() {
+            assert_eq!(i as i32, *reader.join().unwrap().get_field::<i32>(0));
+        }
+        assert!(space.is_empty());
+        repo.close_gate(String::from("gate"));
+    }
+
+    #[test]
+    fn gate_replies_carry_request_ids() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3817"),
+        )
+        .expect("could not connect");
+        let mut stream = connect_raw("127.0.0.1:3817", "space");
+        let mut send = |id, action, tuple: Vec<Tuple>, template| {
+            let m = Message {
+                id,
+                action,
+                tuple,
+                template,
+            };
+            let m_json = serde_json::to_vec(&m).unwrap();
+            stream
+                .write_all(&(m_json.len() as u32).to_be_bytes())
+                .unwrap();
+            stream.write_all(&m_json).unwrap();
+        };
+        send(1, MessageType::Get, Vec::new(), new_template!(1.actual()));
+        send(2, MessageType::Put, vec![new_tuple!(1)], new_template!());
+        let replies = [read_message(&mut stream), read_message(&mut stream)];
+        let mut ids: Vec<_> = replies.iter().map(|m| m.id).collect();
+        ids.sort();
+        assert_eq!(vec![1, 2], ids);
+        let get = replies.iter().find(|m| m.id == 1).unwrap();
+        assert_eq!(MessageType::Ok, get.action);
+        assert_eq!(1, *get.tuple[0].get_field::<i32>(0));
+        repo.close_gate(String::from("gate"));
+    }
+
+    fn every_field() -> Tuple {
+        new_tuple!(
+            -8i8,
+            -16i16,
+            -32i32,
+            -64i64,
+            -128i128,
+            8u8,
+            16u16,
+            32u32,
+            u64::MAX,
+            u128::MAX,
+            usize::MAX,
+            isize::MIN,
+            1.5f32,
+            -2.25f64,
+            'ø',
+            String::from("text"),
+            true,
+            TestStruct { x: 3, y: 0.5 }
+        )
+    }
+
+    fn check_every_field(t: &Tuple) {
+        assert_eq!(18, t.len());
+        assert_eq!(-8, *t.get_field::<i8>(0));
+        assert_eq!(-16, *t.get_field::<i16>(1));
+        assert_eq!(-32, *t.get_field::<i32>(2));
+        assert_eq!(-64, *t.get_field::<i64>(3));
+        assert_eq!(-128, *t.get_field::<i128>(4));
+        assert_eq!(8, *t.get_field::<u8>(5));
+        assert_eq!(16, *t.get_field::<u16>(6));
+        assert_eq!(32, *t.get_field::<u32>(7));
+        assert_eq!(u64::MAX, *t.get_field::<u64>(8));
+        assert_eq!(u128::MAX, *t.get_field::<u128>(9));
+        assert_eq!(usize::MAX, *t.get_field::<usize>(10));
+        assert_eq!(isize::MIN, *t.get_field::<isize>(11));
+        assert_eq!(1.5, *t.get_field::<f32>(12));
+        assert_eq!(-2.25, *t.get_field::<f64>(13));
+        assert_eq!('ø', *t.get_field::<char>(14));
+        assert_eq!("text", t.get_field::<String>(15));
+        assert!(*t.get_field::<bool>(16));
+        assert_eq!(TestStruct { x: 3, y: 0.5 }, *t.get_field::<TestStruct>(17));
+    }
+
+    #[test]
+    fn codec_round_trip() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3821"),
+        )
+        .expect("could not connect");
+        for codec in [Codec::Json, Codec::MessagePack] {
+            let remote =
+                RemoteSpace::with_codec(String::from("127.0.0.1:3821/space"), codec).unwrap();
+            remote.put(every_field()).unwrap();
+            let all = new_template!(any()).prefix();
+            check_every_field(&space.queryp(all.clone()).unwrap());
+            check_every_field(&remote.queryp(all).unwrap());
+            let matching = new_template!(
+                (-8i8).actual(),
+                (-20i16..0).range(),
+                one_of([-32, 32]),
+                (-65i64).greater(),
+                (-128i128).at_most(),
+                any(),
+                16u16.formal(),
+                (0u32..=32).range(),
+                u64::MAX.at_least(),
+                u128::MAX.actual(),
+                usize::MAX.actual(),
+                0isize.less(),
+                1.5f32.actual(),
+                0.0f64.less(),
+                'a'.formal(),
+                regex("^te").unwrap(),
+                true.actual(),
+                TestStruct { x: 3, y: 0.5 }.actual()
+            );
+            check_every_field(&remote.getp(matching).unwrap());
+            assert!(space.is_empty());
+            let text = new_template!(starts_with("te"));
+            remote.put(new_tuple!(String::from("text"))).unwrap();
+            assert_eq!("text", remote.getp(text).unwrap().get_field::<String>(0));
+        }
+        repo.close_gate(String::from("gate"));
+    }
+
+    fn greet_raw(addr: &str, hello: &Hello) -> Welcome {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let payload = serde_json::to_vec(hello).unwrap();
+        stream
+            .write_all(&(payload.len() as u32).to_be_bytes())
+            .unwrap();
+        stream.write_all(&payload).unwrap();
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).unwrap();
+        let mut payload = vec![0; u32::from_be_bytes(header) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        serde_json::from_slice(&payload).unwrap()
+    }
+
+    #[test]
+    fn undecodable_reply_fails_only_its_request() {
+        // A gate answering the first request with a tuple of a type the client does not know
+        let listener = std::net::TcpListener::bind("127.0.0.1:3844").unwrap();
+        let gate = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0; 4];
+            stream.read_exact(&mut header).unwrap();
+            let mut hello = vec![0; u32::from_be_bytes(header) as usize];
+            stream.read_exact(&mut hello).unwrap();
+            let welcome = serde_json::to_vec(&Welcome::Accepted {
+                version: PROTOCOL_VERSION,
+                client_id: String::from("client"),
+            })
+            .unwrap();
+            stream
+                .write_all(&(welcome.len() as u32).to_be_bytes())
+                .unwrap();
+            stream.write_all(&welcome).unwrap();
+            for n in 1..=2 {
+                let request = read_message(&mut stream);
+                let reply = Message {
+                    id: request.id,
+                    action: MessageType::Ok,
+                    tuple: vec![new_tuple!(n)],
+                    template: new_template!(),
+                };
+                let mut payload = serde_json::to_string(&reply).unwrap();
+                if n == 1 {
+                    payload = payload.replace("\"i32\"", "\"unknown\"");
+                }
+                stream
+                    .write_all(&(payload.len() as u32).to_be_bytes())
+                    .unwrap();
+                stream.write_all(payload.as_bytes()).unwrap();
+            }
+        });
+
+        let remote = RemoteSpace::new(String::from("127.0.0.1:3844/space")).unwrap();
+        assert!(matches!(
+            remote.queryp(new_template!(0.formal())),
+            Err(Error::Serialization(_))
+        ));
+        // The connection carries on
+        let t = remote.queryp(new_template!(0.formal())).unwrap();
+        assert_eq!(2, *t.get_field::<i32>(0));
+        gate.join().unwrap();
+    }
+
+    #[test]
+    fn handshake_rejections() {
+        let repo = Arc::new(Repository::new());
+        repo.add_space(
+            String::from("space"),
+            Arc::new(LocalSpace::new_sequential()),
+        );
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3822"),
+        )
+        .expect("could not connect");
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            codec: String::from("json"),
+            client_id: String::new(),
+            space: String::from("space"),
+            credentials: None,
+        };
+        let rejection = |hello: &Hello| match greet_raw("127.0.0.1:3822", hello) {
+            Welcome::Rejected { reason, .. } => reason,
+            welcome => panic!("expected a rejection but got {:?}", welcome),
+        };
+        let newer = Hello {
+            version: PROTOCOL_VERSION + 1,
+            ..hello.clone()
+        };
+        assert_eq!(Rejection::VersionMismatch, rejection(&newer));
+        let bogus = Hello {
+            codec: String::from("bogus"),
+            ..hello.clone()
+        };
+        assert_eq!(Rejection::UnsupportedCodec, rejection(&bogus));
+        let missing = Hello {
+            space: String::from("nospace"),
+            ..hello.clone()
+        };
+        assert_eq!(Rejection::UnknownSpace, rejection(&missing));
+
+        let mut stream = TcpStream::connect("127.0.0.1:3822").unwrap();
+        stream.write_all(&[0, 0, 0, 2, b'{', b'!']).unwrap();
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).unwrap();
+        let mut payload = vec![0; u32::from_be_bytes(header) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        assert!(matches!(
+            serde_json::from_slice(&payload).unwrap(),
+            Welcome::Rejected {
+                reason: Rejection::Malformed,
+                ..
+            }
+        ));
+        repo.close_gate(String::from("gate"));
+    }
+
+    #[test]
+    fn handshake_client_ids() {
+        let repo = Arc::new(Repository::new());
+        repo.add_space(
+            String::from("space"),
+            Arc::new(LocalSpace::new_sequential()),
+        );
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3825"),
+        )
+        .expect("could not connect");
+        let options = ConnectOptions {
+            client_id: Some(String::from("worker")),
+            ..Default::default()
+        };
+        let named =
+            RemoteSpace::with_options(String::from("127.0.0.1:3825/space"), options).unwrap();
+        assert_eq!("worker", named.client_id());
+        let first = RemoteSpace::new(String::from("127.0.0.1:3825/space")).unwrap();
+        let second = RemoteSpace::new(String::from("127.0.0.1:3825/space")).unwrap();
+        assert!(!first.client_id().is_empty());
+        assert_ne!(first.client_id(), second.client_id());
+
+        // A bare space name is not a hello
+        let mut bare = TcpStream::connect("127.0.0.1:3825").unwrap();
+        bare.write_all(b"space").unwrap();
+        let mut header = [0; 4];
+        bare.read_exact(&mut header).unwrap();
+        let mut payload = vec![0; u32::from_be_bytes(header) as usize];
+        bare.read_exact(&mut payload).unwrap();
+        assert!(matches!(
+            serde_json::from_slice(&payload).unwrap(),
+            Welcome::Rejected {
+                reason: Rejection::Malformed,
+                ..
+            }
+        ));
+        repo.close_gate(String::from("gate"));
+    }
+
+    fn connect_as(addr: &str, credentials: Option<Credentials>) -> Result<RemoteSpace, Error> {
+        let options = ConnectOptions {
+            credentials,
+            ..Default::default()
+        };
+        RemoteSpace::with_options(addr.to_string(), options)
+    }
+
+    fn rejection(result: Result<RemoteSpace, Error>) -> Rejection {
+        match result {
+            Err(Error::Rejected(reason, _)) => reason,
+            Err(e) => panic!("expected a rejection but got {}", e),
+            Ok(_) => panic!("expected a rejection but got a connection"),
+        }
+    }
+
+    #[test]
+    fn gate_token_auth() {
+        let repo = Arc::new(Repository::new());
+        let jobs = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("jobs"), Arc::clone(&jobs));
+        repo.add_space(
+            String::from("secret"),
+            Arc::new(LocalSpace::new_sequential()),
+        );
+        repo.set_authenticator(Authenticator::token("letmein", "worker"));
+        repo.grant("worker", "jobs", Permissions::READ | Permissions::TAKE);
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3826"),
+        )
+        .expect("could not connect");
+        let token = |t: &str| Some(Credentials::Token(t.to_string()));
+
+        let anonymous = connect_as("127.0.0.1:3826/jobs", None);
+        assert_eq!(Rejection::Unauthorized, rejection(anonymous));
+        let wrong = connect_as("127.0.0.1:3826/jobs", token("letmeout"));
+        assert_eq!(Rejection::Unauthorized, rejection(wrong));
+        let secret = connect_as("127.0.0.1:3826/secret", token("letmein"));
+        assert_eq!(Rejection::Unauthorized, rejection(secret));
+        // Spaces that do not exist look the same as spaces the client may not use
+        let missing = connect_as("127.0.0.1:3826/missing", token("letmein"));
+        assert_eq!(Rejection::Unauthorized, rejection(missing));
+
+        let remote = connect_as("127.0.0.1:3826/jobs", token("letmein")).unwrap();
+        jobs.put(new_tuple!(1)).unwrap();
+        assert_eq!(1, remote.queryall(new_template!(0.formal())).unwrap().len());
+        assert!(matches!(
+            remote.put(new_tuple!(2)),
+            Err(Error::Remote(ErrorCode::Forbidden, _))
+        ));
+        assert_eq!(
+            1,
+            *remote
+                .getp(new_template!(0.formal()))
+                .unwrap()
+                .get_field::<i32>(0)
+        );
+        assert!(jobs.is_empty());
+        repo.close_gate(String::from("gate"));
+    }
+
+    #[test]
+    fn gate_user_auth() {
+        let path = std::env::temp_dir().join(format!("rspaces-users-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "# users of the gate\nalice:wonderland\n\nbob:builder\n",
+        )
+        .unwrap();
+        let authenticator = Authenticator::users_from_file(&path).unwrap();
+        std::fs::write(&path, "alice\n").unwrap();
+        assert!(matches!(
+            Authenticator::users_from_file(&path),
+            Err(Error::Io(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        repo.set_authenticator(authenticator);
+        repo.grant("alice", "space", Permissions::PUT);
+        repo.grant(EVERYONE, "space", Permissions::READ);
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3827"),
+        )
+        .expect("could not connect");
+        let user = |name: &str, password: &str| {
+            Some(Credentials::User {
+                name: name.to_string(),
+                password: password.to_string(),
+            })
+        };
+        let wrong = connect_as("127.0.0.1:3827/space", user("alice", "builder"));
+        assert_eq!(Rejection::Unauthorized, rejection(wrong));
+        let token = connect_as(
+            "127.0.0.1:3827/space",
+            Some(Credentials::Token(String::from("wonderland"))),
+        );
+        assert_eq!(Rejection::Unauthorized, rejection(token));
+
+        let alice = connect_as("127.0.0.1:3827/space", user("alice", "wonderland")).unwrap();
+        let bob = connect_as("127.0.0.1:3827/space", user("bob", "builder")).unwrap();
+        alice.put(new_tuple!(1)).unwrap();
+        assert!(matches!(
+            bob.put(new_tuple!(2)),
+            Err(Error::Remote(ErrorCode::Forbidden, _))
+        ));
+        assert_eq!(
+            1,
+            *bob.queryp(new_template!(0.formal()))
+                .unwrap()
+                .get_field::<i32>(0)
+        );
+        for remote in [&alice, &bob] {
+            assert!(matches!(
+                remote.getp(new_template!(0.formal())),
+                Err(Error::Remote(ErrorCode::Forbidden, _))
+            ));
+            assert!(matches!(
+                remote.get_timeout(new_template!(0.formal()), Duration::from_millis(10)),
+                Err(Error::Remote(ErrorCode::Forbidden, _))
+            ));
+        }
+
+        // Permissions are looked up when connecting
+        repo.revoke(EVERYONE, "space");
+        let late = connect_as("127.0.0.1:3827/space", user("bob", "builder"));
+        assert_eq!(Rejection::Unauthorized, rejection(late));
+        assert_eq!(1, bob.queryall(new_template!(0.formal())).unwrap().len());
+        assert_eq!(1, space.len());
+        repo.close_gate(String::from("gate"));
+    }
+
+    #[test]
+    fn space_uris() {
+        let uri: SpaceUri = "tls://gate.example.com:3800/results?client_id=w1&timeout=250ms"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            Endpoint::Tls(String::from("gate.example.com:3800")),
+            uri.endpoint
+        );
+        assert_eq!("results", uri.space);
+        assert_eq!(None, uri.codec);
+        assert_eq!(Some(Duration::from_millis(250)), uri.timeout);
+        assert_eq!(Some(String::from("w1")), uri.client_id);
+        assert_eq!(uri, uri.to_string().parse().unwrap());
+
+        let bare: SpaceUri = "localhost:3800/jobs".parse().unwrap();
+        assert_eq!(Endpoint::Tcp(String::from("localhost:3800")), bare.endpoint);
+        assert_eq!("tcp://localhost:3800/jobs", bare.to_string());
+        let unix: SpaceUri = "unix://./gate.sock/jobs?codec=json".parse().unwrap();
+        assert_eq!(Endpoint::Unix("./gate.sock".into()), unix.endpoint);
+        assert_eq!(Some(Codec::Json), unix.codec);
+
+        let invalid = |uri: &str| uri.parse::<SpaceUri>().unwrap_err();
+        assert_eq!(
+            UriError::UnknownScheme(String::from("udp")),
+            invalid("udp://h:1/s")
+        );
+        assert_eq!(
+            UriError::InvalidHost(String::from("::1")),
+            invalid("::1:3800/s")
+        );
+        assert_eq!(
+            UriError::InvalidHost(String::new()),
+            invalid("tcp://:3800/s")
+        );
+        assert_eq!(
+            UriError::InvalidPort(String::from("http")),
+            invalid("h:http/s")
+        );
+        assert_eq!(
+            UriError::InvalidPort(String::from("70000")),
+            invalid("h:70000/s")
+        );
+        assert_eq!(
+            UriError::InvalidPort(String::from("localhost")),
+            invalid("localhost/s")
+        );
+        assert_eq!(UriError::MissingSpace, invalid("tcp://h:1/"));
+        assert_eq!(UriError::MissingPath, invalid("unix:///space"));
+        assert_eq!(
+            UriError::UnknownOption(String::from("retries")),
+            invalid("h:1/s?retries=3")
+        );
+        for (name, value) in [("codec", "bincode"), ("timeout", "5"), ("timeout", "5d")] {
+            let uri = format!("h:1/s?{}={}", name, value);
+            let expected = UriError::InvalidOption {
+                name: name.to_string(),
+                value: value.to_string(),
+            };
+            assert_eq!(expected, invalid(&uri));
+        }
+
+        assert_eq!(
+            Endpoint::Unix("/tmp/gate.sock".into()),
+            "unix:///tmp/gate.sock".parse().unwrap()
+        );
+        assert_eq!(
+            UriError::UnexpectedSpace(String::from("space")),
+            "127.0.0.1:3800/space".parse::<Endpoint>().unwrap_err()
+        );
+    }
+
+    #[test]
+    fn connect_with_uris() {
+        let repo = Arc::new(Repository::new());
+        repo.add_space(
+            String::from("space"),
+            Arc::new(LocalSpace::new_sequential()),
+        );
+        assert!(matches!(
+            Repository::add_gate(
+                Arc::clone(&repo),
+                String::from("gate"),
+                "127.0.0.1:3832/space".to_string()
+            ),
+            Err(Error::InvalidAddress(UriError::UnexpectedSpace(_)))
+        ));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            "tcp://127.0.0.1:3832".to_string(),
+        )
+        .unwrap();
+
+        // Options in the uri win over the connect options
+        let options = ConnectOptions {
+            client_id: Some(String::from("from-options")),
+            ..Default::default()
+        };
+        let uri = "tcp://127.0.0.1:3832/space?codec=msgpack&client_id=from-uri&timeout=5s";
+        let remote = RemoteSpace::with_options(uri.to_string(), options).unwrap();
+        assert_eq!("from-uri", remote.client_id());
+        remote.put(new_tuple!(1, 'a')).unwrap();
+        let uri: SpaceUri = "127.0.0.1:3832/space".parse().unwrap();
+        let other = RemoteSpace::connect(&uri, ConnectOptions::default()).unwrap();
+        assert!(other.getp(new_template!(1.actual(), 'x'.formal())).is_ok());
+
+        assert!(matches!(
+            RemoteSpace::new(String::from("tcp://127.0.0.1:3832/space?codec=bincode")),
+            Err(Error::InvalidAddress(UriError::InvalidOption { .. }))
+        ));
+        assert!(matches!(
+            RemoteSpace::new(String::from("tls://127.0.0.1:3832/space")),
+            Err(Error::InvalidAddress(UriError::UnsupportedScheme(_)))
+        ));
+
+        // The timeout covers waiting for the welcome
+        let silent = std::net::TcpListener::bind("127.0.0.1:3833").unwrap();
+        let started = std::time::Instant::now();
+        assert!(RemoteSpace::new(String::from("127.0.0.1:3833/space?timeout=100ms")).is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        drop(silent);
+
+        drop((remote, other));
+        repo.close_gate(String::from("gate"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_gate() {
+        let path = std::env::temp_dir().join(format!("rspaces-{}.sock", std::process::id()));
+        // A socket file left behind by a gate that did not close is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        let addr = format!("unix://{}", path.display());
+        Repository::add_gate(Arc::clone(&repo), String::from("gate"), addr.clone()).unwrap();
+
+        let remote = RemoteSpace::new(format!("{}/space", addr)).unwrap();
+        let packed = RemoteSpace::new(format!("{}/space?codec=msgpack", addr)).unwrap();
+        remote.put(new_tuple!(1, 'a')).unwrap();
+        let t = packed.get(new_template!(0.formal(), 'a'.actual())).unwrap();
+        assert_eq!(1, *t.get_field::<i32>(0));
+        assert!(matches!(
+            RemoteSpace::new(format!("{}/missing", addr)),
+            Err(Error::SpaceNotFound)
+        ));
+
+        drop((remote, packed));
+        repo.close_gate(String::from("gate"));
+        assert!(!path.exists());
+        assert!(RemoteSpace::new(format!("{}/space", addr)).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_gate_keeps_other_files() {
+        let path = std::env::temp_dir().join(format!("rspaces-{}.txt", std::process::id()));
+        std::fs::write(&path, "not a socket").unwrap();
+
+        let repo = Arc::new(Repository::new());
+        let addr = format!("unix://{}", path.display());
+        assert!(Repository::add_gate(Arc::clone(&repo), String::from("gate"), addr).is_err());
+        assert_eq!("not a socket", std::fs::read_to_string(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn in_memory_gate() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        assert!(matches!(
+            RemoteSpace::connect_in_memory(&repo, String::from("gate"), String::from("space")),
+            Err(Error::Io(_))
+        ));
+        Repository::add_gate_in_memory(Arc::clone(&repo), String::from("gate"));
+
+        let remote = Arc::new(
+            RemoteSpace::connect_in_memory(&repo, String::from("gate"), String::from("space"))
+                .unwrap(),
+        );
+        assert!(remote.client_id().starts_with("client-"));
+        // A blocked get does not hold up other requests on the connection
+        let clone = Arc::clone(&remote);
+        let waiting = thread::spawn(move || clone.get(new_template!(2.actual())));
+        remote.put(new_tuple!(1)).unwrap();
+        assert_eq!(1, space.len());
+        remote.put(new_tuple!(2)).unwrap();
+        assert_eq!(2, *waiting.join().unwrap().unwrap().get_field::<i32>(0));
+        assert!(matches!(
+            remote.getp(new_template!(2.actual())),
+            Err(Error::NoMatch)
+        ));
+        assert!(matches!(
+            RemoteSpace::connect_in_memory(&repo, String::from("gate"), String::from("missing")),
+            Err(Error::SpaceNotFound)
+        ));
+
+        // Clients are authenticated like those of any other gate
+        repo.set_authenticator(Authenticator::token("secret", "worker"));
+        assert!(matches!(
+            RemoteSpace::connect_in_memory(&repo, String::from("gate"), String::from("space")),
+            Err(Error::Rejected(Rejection::Unauthorized, _))
+        ));
+
+        // Clients pick the gate by its name, so closing one leaves the others open
+        Repository::add_gate_in_memory(Arc::clone(&repo), String::from("other"));
+        drop(remote);
+        repo.close_gate(String::from("gate"));
+        assert!(matches!(
+            RemoteSpace::connect_in_memory(&repo, String::from("gate"), String::from("space")),
+            Err(Error::Io(_))
+        ));
+        assert!(matches!(
+            RemoteSpace::connect_in_memory(&repo, String::from("other"), String::from("space")),
+            Err(Error::Rejected(Rejection::Unauthorized, _))
+        ));
+        repo.close_gate(String::from("other"));
+    }
+
+    #[test]
+    fn export_and_import() {
+        let space = LocalSpace::new_stack();
+        space.put(new_tuple!(1, String::from("one"))).unwrap();
+        space.put(new_tuple!(2, 'b', true)).unwrap();
+        space.put(new_tuple!(3, 0.5)).unwrap();
+        let mut exported = Vec::new();
+        space.export_json(&mut exported).unwrap();
+        assert_eq!(3, String::from_utf8_lossy(&exported).lines().count());
+
+        // The copy keeps the order of the tuples, so the stack still returns the newest first
+        let copy = LocalSpace::new_stack();
+        assert_eq!(3, copy.import_json(&exported[..]).unwrap());
+        let numbers: Vec<i32> = copy
+            .snapshot()
+            .iter()
+            .map(|t| *t.get_field::<i32>(0))
+            .collect();
+        assert_eq!(vec![1, 2, 3], numbers);
+        let t = copy.getp(new_template!(0.formal()).prefix()).unwrap();
+        assert_eq!(0.5, *t.get_field::<f64>(1));
+
+        // Nothing is put unless every line is a tuple
+        let mut broken = exported.clone();
+        broken.extend_from_slice(b"\n\nnot a tuple\n");
+        assert!(matches!(
+            copy.import_json(&broken[..]),
+            Err(Error::Serialization(_))
+        ));
+        assert_eq!(2, copy.len());
+
+        let repo = Repository::new();
+        repo.add_space(String::from("a"), Arc::new(space));
+        repo.add_space(String::from("b"), Arc::new(copy));
+        let mut exported = Vec::new();
+        repo.export_json(&mut exported).unwrap();
+        let snapshot = repo.snapshot().unwrap();
+        assert_eq!(3, snapshot["a"].len());
+        assert_eq!(2, snapshot["b"].len());
+
+        let other = Repository::new();
+        let a = Arc::new(LocalSpace::new_sequential());
+        other.add_space(String::from("a"), Arc::clone(&a));
+        assert!(matches!(
+            other.import_json(&exported[..]),
+            Err(Error::SpaceNotFound)
+        ));
+        assert!(a.is_empty());
+        let b = Arc::new(LocalSpace::new_sequential());
+        other.add_space(String::from("b"), Arc::clone(&b));
+        assert_eq!(5, other.import_json(&exported[..]).unwrap());
+        assert_eq!((3, 2), (a.len(), b.len()));
+    }
+
+    #[test]
+    fn import_into_a_failing_space() {
+        let mut exported = Vec::new();
+        for (space, n) in [("a", 1), ("b", 2), ("a", 3), ("b", 4)] {
+            exported.extend_from_slice(
+                format!("{{\"space\":\"{}\",\"tuple\":{}}}\n", space, tuple_json(n)).as_bytes(),
+            );
+        }
+
+        // The remote space may only be read, so it takes none of its tuples
+        let gate_repo = Arc::new(Repository::new());
+        let read_only = Arc::new(LocalSpace::new_sequential());
+        gate_repo.add_space(String::from("b"), Arc::clone(&read_only));
+        gate_repo.set_authenticator(Authenticator::token("secret", "reader"));
+        gate_repo.grant("reader", "b", Permissions::READ);
+        Repository::add_gate(
+            Arc::clone(&gate_repo),
+            String::from("gate"),
+            String::from("127.0.0.1:3845"),
+        )
+        .unwrap();
+        let remote = connect_as(
+            "127.0.0.1:3845/b",
+            Some(Credentials::Token(String::from("secret"))),
+        )
+        .unwrap();
+
+        let repo = Repository::new();
+        let a = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("a"), Arc::clone(&a));
+        repo.add_space(String::from("b"), Arc::new(remote));
+        assert!(matches!(
+            repo.import_json(&exported[..]),
+            Err(Error::Remote(ErrorCode::Forbidden, _))
+        ));
+        // The space filled before the failing one keeps its tuples
+        let numbers: Vec<i32> = a
+            .snapshot()
+            .iter()
+            .map(|t| *t.get_field::<i32>(0))
+            .collect();
+        assert_eq!(vec![1, 3], numbers);
+        assert!(read_only.is_empty());
+        gate_repo.close_gate(String::from("gate"));
+    }
+
+    fn tuple_json(n: i32) -> String {
+        serde_json::to_string(&new_tuple!(n)).unwrap()
+    }
+
+    #[test]
+    fn time_to_live() {
+        let space = LocalSpace::new_queue();
+        space
+            .put_with_ttl(new_tuple!(1), Duration::from_millis(50))
+            .unwrap();
+        space.put(new_tuple!(2)).unwrap();
+        assert_eq!(2, space.queryall(new_template!(0.formal())).unwrap().len());
+        thread::sleep(Duration::from_millis(100));
+
+        // The expired tuple no longer holds up the queue
+        assert_eq!(1, space.len());
+        assert!(space.queryp(new_template!(1.actual())).is_err());
+        let t = space.getp(new_template!(0.formal())).unwrap();
+        assert_eq!(2, *t.get_field::<i32>(0));
+        assert!(space.snapshot().is_empty());
+    }
+
+    #[test]
+    fn expired_queue_head_wakes_readers() {
+        let space = Arc::new(LocalSpace::new_queue());
+        space
+            .put_with_ttl(new_tuple!('a'), Duration::from_secs(3600))
+            .unwrap();
+        space.put(new_tuple!('b')).unwrap();
+        let reader = Arc::clone(&space);
+        let waiting = thread::spawn(move || {
+            reader.get_timeout(new_template!('b'.actual()), Duration::from_secs(3))
+        });
+        let started = std::time::Instant::now();
+        space
+            .renew(new_template!('a'.actual()), Duration::from_millis(50))
+            .unwrap();
+        // The reader gets the tuple once the head expires, without any other change to the space
+        assert_eq!('b', *waiting.join().unwrap().unwrap().get_field::<char>(0));
+        assert!(started.elapsed() < Duration::from_millis(800));
+        assert!(space.is_empty());
+    }
+
+    #[test]
+    fn endless_time_to_live() {
+        let space = LocalSpace::new_sequential();
+        space
+            .put_with_ttl(new_tuple!(1), Duration::from_millis(50))
+            .unwrap();
+        space.put_with_ttl(new_tuple!(2), Duration::MAX).unwrap();
+        let renewed = space
+            .renew(new_template!(1.actual()), Duration::MAX)
+            .unwrap();
+        assert_eq!(1, renewed.len());
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(2, space.len());
+        let t = space
+            .query_timeout(new_template!(2.actual()), Duration::MAX)
+            .unwrap();
+        assert_eq!(2, *t.get_field::<i32>(0));
+        assert!(space
+            .renew(new_template!(0.formal()), Duration::from_secs(1))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn lease_renewal() {
+        let space = LocalSpace::new_sequential();
+        let presence = || new_template!(String::from("alive").actual(), 0.formal());
+        space
+            .put_with_ttl(
+                new_tuple!(String::from("alive"), 7),
+                Duration::from_millis(300),
+            )
+            .unwrap();
+        space.put(new_tuple!(String::from("alive"), 8)).unwrap();
+        for _ in 0..4 {
+            thread::sleep(Duration::from_millis(100));
+            // Only tuples put with a time to live have a lease to renew
+            let renewed = space.renew(presence(), Duration::from_millis(300)).unwrap();
+            assert_eq!(1, renewed.len());
+            assert_eq!(7, *renewed[0].get_field::<i32>(1));
+        }
+        thread::sleep(Duration::from_millis(400));
+        assert!(space
+            .renew(presence(), Duration::from_millis(300))
+            .unwrap()
+            .is_empty());
+        assert_eq!(1, space.queryall(presence()).unwrap().len());
+    }
+
+    #[test]
+    fn remote_time_to_live() {
+        let repo = Arc::new(Repository::new());
+        repo.add_space(
+            String::from("space"),
+            Arc::new(LocalSpace::new_sequential()),
+        );
+        Repository::add_gate_in_memory(Arc::clone(&repo), String::from("gate"));
+        let remote =
+            RemoteSpace::connect_in_memory(&repo, String::from("gate"), String::from("space"))
+                .unwrap();
+        remote
+            .put_with_ttl(new_tuple!(1), Duration::from_millis(100))
+            .unwrap();
+        assert_eq!(
+            1,
+            remote
+                .renew(new_template!(1.actual()), Duration::from_millis(100))
+                .unwrap()
+                .len()
+        );
+        thread::sleep(Duration::from_millis(150));
+        assert!(matches!(
+            remote.queryp(new_template!(1.actual())),
+            Err(Error::NoMatch)
+        ));
+        // A time to live too long to be represented does not take the space down
+        remote.put_with_ttl(new_tuple!(2), Duration::MAX).unwrap();
+        let t = remote
+            .get_timeout(new_template!(2.actual()), Duration::MAX)
+            .unwrap();
+        assert_eq!(2, *t.get_field::<i32>(0));
+
+        // Renewing reveals the tuples, so it takes more than permission to put
+        repo.set_authenticator(Authenticator::token("secret", "writer"));
+        repo.grant("writer", "space", Permissions::PUT);
+        let options = ConnectOptions {
+            credentials: Some(Credentials::Token(String::from("secret"))),
+            ..Default::default()
+        };
+        drop(remote);
+        repo.close_gate(String::from("gate"));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            "127.0.0.1:3834".to_string(),
+        )
+        .unwrap();
+        let writer =
+            RemoteSpace::with_options(String::from("127.0.0.1:3834/space"), options).unwrap();
+        writer
+            .put_with_ttl(new_tuple!(2), Duration::from_secs(10))
+            .unwrap();
+        assert!(matches!(
+            writer.renew(new_template!(2.actual()), Duration::from_secs(10)),
+            Err(Error::Remote(ErrorCode::Forbidden, _))
+        ));
+        drop(writer);
+        repo.close_gate(String::from("gate"));
+    }
+
+    fn numbers(event: SpaceEvent) -> (&'static str, Vec<i32>) {
+        let (kind, tuples) = match event {
+            SpaceEvent::Put(t) => ("put", vec![t]),
+            SpaceEvent::Removed(t) => ("removed", vec![t]),
+            SpaceEvent::RemovedAll(tuples) => ("removed all", tuples),
+        };
+        (
+            kind,
+            tuples.iter().map(|t| *t.get_field::<i32>(0)).collect(),
+        )
+    }
+
+    #[test]
+    fn subscriptions() {
+        let space = Arc::new(LocalSpace::new_sequential());
+        let events = space.subscribe(new_template!(0.formal(), 'a'.actual()));
+        let everything = space.subscribe(new_template!(0.formal(), 'x'.formal()));
+
+        space.put(new_tuple!(1, 'a')).unwrap();
+        space.put(new_tuple!(2, 'b')).unwrap();
+        space.put(new_tuple!(3, 'a')).unwrap();
+        space.getp(new_template!(1.actual(), 'a'.actual())).unwrap();
+        space
+            .getall(new_template!(0.formal(), 'x'.formal()))
+            .unwrap();
+        // A tuple handed straight to a blocked reader is put and removed at once
+        let clone = Arc::clone(&space);
+        let waiting = thread::spawn(move || clone.get(new_template!(4.actual(), 'a'.actual())));
+        thread::sleep(Duration::from_millis(50));
+        space.put(new_tuple!(4, 'a')).unwrap();
+        waiting.join().unwrap().unwrap();
+        // Queries and failed removals change nothing
+        space.put(new_tuple!(5, 'a')).unwrap();
+        space
+            .queryall(new_template!(0.formal(), 'x'.formal()))
+            .unwrap();
+        assert!(space.getp(new_template!(6.actual(), 'a'.actual())).is_err());
+
+        let received: Vec<_> = events.try_iter().map(numbers).collect();
+        assert_eq!(
+            vec![
+                ("put", vec![1]),
+                ("put", vec![3]),
+                ("removed", vec![1]),
+                ("removed all", vec![3]),
+                ("put", vec![4]),
+                ("removed", vec![4]),
+                ("put", vec![5]),
+            ],
+            received
+        );
+        let all: Vec<_> = everything.try_iter().map(numbers).collect();
+        assert_eq!(("removed all", vec![2, 3]), all[4]);
+        assert_eq!(8, all.len());
+
+        // Dropping the receiver ends the subscription
+        drop(events);
+        space.put(new_tuple!(6, 'a')).unwrap();
+        assert_eq!(("put", vec![6]), numbers(everything.try_recv().unwrap()));
+    }
+
+    #[test]
+    fn remote_subscription() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        Repository::add_gate_in_memory(Arc::clone(&repo), String::from("gate"));
+        let remote =
+            RemoteSpace::connect_in_memory(&repo, String::from("gate"), String::from("space"))
+                .unwrap();
+        let events = remote.subscribe(new_template!(0.formal())).unwrap();
+
+        space.put(new_tuple!(1)).unwrap();
+        space.put(new_tuple!(String::from("ignored"))).unwrap();
+        remote.put(new_tuple!(2)).unwrap();
+        remote.getall(new_template!(0.formal())).unwrap();
+        let timeout = Duration::from_secs(1);
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(numbers(events.recv_timeout(timeout).unwrap()));
+        }
+        assert_eq!(
+            vec![
+                ("put", vec![1]),
+                ("put", vec![2]),
+                ("removed all", vec![1, 2])
+            ],
+            received
+        );
+
+        // The gate stops pushing events once the receiver is gone, the connection goes on
+        drop(events);
+        space.put(new_tuple!(3)).unwrap();
+        space.put(new_tuple!(4)).unwrap();
+        assert_eq!(2, remote.queryall(new_template!(0.formal())).unwrap().len());
+
+        // Only local spaces can be subscribed to
+        let relay =
+            RemoteSpace::connect_in_memory(&repo, String::from("gate"), String::from("space"))
+                .unwrap();
+        repo.add_space(String::from("relay"), Arc::new(relay));
+        let relayed =
+            RemoteSpace::connect_in_memory(&repo, String::from("gate"), String::from("relay"))
+                .unwrap();
+        assert!(matches!(
+            relayed.subscribe(new_template!(0.formal())),
+            Err(Error::Remote(ErrorCode::InvalidRequest, _))
+        ));
+
+        // Events end with the connection
+        let events = remote.subscribe(new_template!(0.formal())).unwrap();
+        drop(remote);
+        assert!(matches!(
+            events.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+        drop(relayed);
+        repo.close_gate(String::from("gate"));
+    }
+
+    #[test]
+    fn transactions() {
+        let space = Arc::new(LocalSpace::new_queue());
+        for n in 1..=3 {
+            space.put(new_tuple!(n)).unwrap();
+        }
+        let events = space.subscribe(new_template!(0.formal()));
+        let clone = Arc::clone(&space);
+        let waiting = thread::spawn(move || clone.get(new_template!(10.actual())));
+        thread::sleep(Duration::from_millis(50));
+
+        // A failed transaction leaves no trace, even in a queue where the order matters
+        let result = space.transaction(|tx| {
+            assert_eq!(1, *tx.getp(new_template!(0.formal()))?.get_field::<i32>(0));
+            tx.put(new_tuple!(10))?;
+            assert_eq!(3, tx.getall(new_template!(0.formal()))?.len());
+            tx.getp(new_template!(0.formal()))
+        });
+        assert!(matches!(result, Err(Error::NoMatch)));
+        assert!(events.try_recv().is_err());
+        let numbers = |tuples: Vec<Tuple>| -> Vec<i32> {
+            tuples.iter().map(|t| *t.get_field::<i32>(0)).collect()
+        };
+        assert_eq!(
+            vec![1, 2, 3],
+            numbers(space.queryall(new_template!(0.formal())).unwrap())
+        );
+
+        // A committed one is seen by blocked readers and subscribers once it is done
+        let taken = space
+            .transaction(|tx| {
+                let all = tx.queryall(new_template!(0.formal()))?;
+                for _ in all {
+                    tx.getp(new_template!(0.formal()))?;
+                }
+                tx.put(new_tuple!(10))?;
+                tx.queryp(new_template!(10.actual()))
+            })
+            .unwrap();
+        assert_eq!(10, *taken.get_field::<i32>(0));
+        assert_eq!(10, *waiting.join().unwrap().unwrap().get_field::<i32>(0));
+        assert!(space.is_empty());
+        let received: Vec<_> = events.try_iter().collect();
+        assert_eq!(5, received.len());
+        assert!(matches!(received[3], SpaceEvent::Put(_)));
+        assert!(matches!(received[4], SpaceEvent::Removed(_)));
+
+        // Batches run the same way
+        space.put(new_tuple!(1)).unwrap();
+        let results = space
+            .batch(vec![
+                Operation::Put(new_tuple!(2)),
+                Operation::Queryall(new_template!(0.formal())),
+                Operation::Getp(new_template!(0.formal())),
+            ])
+            .unwrap();
+        assert!(results[0].is_empty());
+        assert_eq!(vec![1, 2], numbers(results[1].clone()));
+        assert_eq!(vec![1], numbers(results[2].clone()));
+    }
+
+    #[test]
+    fn panicking_transactions_are_undone() {
+        let space = LocalSpace::new_queue();
+        space.put(new_tuple!(1)).unwrap();
+        let events = space.subscribe(new_template!(0.formal()));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            space.transaction::<(), _>(|tx| {
+                tx.getp(new_template!(0.formal()))?;
+                tx.put(new_tuple!(2))?;
+                panic!("the closure gives up")
+            })
+        }));
+        assert!(result.is_err());
+        assert!(events.try_recv().is_err());
+
+        // The space is left as it was and can still be used
+        assert_eq!(1, space.len());
+        space.put(new_tuple!(3)).unwrap();
+        assert_eq!(
+            1,
+            *space
+                .getp(new_template!(0.formal()))
+                .unwrap()
+                .get_field::<i32>(0)
+        );
+        assert_eq!(
+            3,
+            *space
+                .getp(new_template!(0.formal()))
+                .unwrap()
+                .get_field::<i32>(0)
+        );
+    }
+
+    #[test]
+    fn remote_transactions() {
+        let repo = Arc::new(Repository::new());
+        let space = Arc::new(LocalSpace::new_sequential());
+        repo.add_space(String::from("space"), Arc::clone(&space));
+        Repository::add_gate_in_memory(Arc::clone(&repo), String::from("gate"));
+        let remote =
+            RemoteSpace::connect_in_memory(&repo, String::from("gate"), String::from("space"))
+                .unwrap();
+        let token = || new_template!(String::from("token").actual());
+        let lock = || new_template!(String::from("lock").actual(), 0.formal()).prefix();
+        space.put(new_tuple!(String::from("token"))).unwrap();
+
+        let results = remote
+            .batch(vec![
+                Operation::Getp(token()),
+                Operation::Put(new_tuple!(String::from("lock"), 1)),
+                Operation::Put(new_tuple!(String::from("lock"), 2, 'x')),
+                Operation::Queryall(lock()),
+                Operation::Queryp(
+                    new_template!(String::from("lock").actual(), 2.actual()).prefix(),
+                ),
+            ])
+            .unwrap();
+        assert_eq!(
+            vec![1, 0, 0, 2, 1],
+            results.iter().map(Vec::len).collect::<Vec<_>>()
+        );
+        assert_eq!(2, *results[3][1].get_field::<i32>(1));
+        assert!(matches!(
+            remote.batch(vec![Operation::Getall(lock()), Operation::Getp(token())]),
+            Err(Error::NoMatch)
+        ));
+        assert_eq!(2, space.queryall(lock()).unwrap().len());
+        assert!(remote.batch(Vec::new()).unwrap().is_empty());
+
+        // Every step needs the permission its operation would need on its own
+        repo.set_authenticator(Authenticator::token("secret", "reader"));
+        repo.grant("reader", "space", Permissions::READ | Permissions::PUT);
+        drop(remote);
+        repo.close_gate(String::from("gate"));
+        Repository::add_gate(
+            Arc::clone(&repo),
+            String::from("gate"),
+            "127.0.0.1:3837".to_string(),
+        )
+        .unwrap();
+        let options = ConnectOptions {
+            credentials: Some(Credentials::Token(String::from("secret"))),
+            ..Default::default()
+        };
+        let reader =
+            RemoteSpace::with_options(String::from("127.0.0.1:3837/space"), options).unwrap();
+        assert_eq!(
+            2,
+            reader.batch(vec![Operation::Queryall(lock())]).unwrap()[0].len()
+        );
+        assert!(matches!(
+            reader.batch(vec![
+                Operation::Put(new_tuple!(String::from("token"))),
+                Operation::Getall(lock()),
+            ]),
+            Err(Error::Remote(ErrorCode::Forbidden, _))
+        ));
+        assert!(space.queryp(token()).is_err());
+        drop(reader);
+        repo.close_gate(String::from("gate"));
+    }
 }
@@ -0,0 +1,111 @@
+#![cfg(feature = "tls")]
+
+mod certs;
+
+use certs::Authority;
+use rspaces::{
+    new_template, new_tuple, Authenticator, ConnectOptions, Error, FieldType, LocalSpace,
+    Permissions, Rejection, RemoteSpace, Repository, Space, Template, TlsAcceptor, TlsConnector,
+    Tuple, TupleField,
+};
+use std::sync::Arc;
+
+fn tls_repo(addr: &str, tls: TlsAcceptor) -> Arc<Repository> {
+    let repo = Arc::new(Repository::new());
+    repo.add_space(
+        String::from("space"),
+        Arc::new(LocalSpace::new_sequential()),
+    );
+    Repository::add_gate_tls(
+        Arc::clone(&repo),
+        String::from("gate"),
+        addr.to_string(),
+        tls,
+    )
+    .expect("could not open gate");
+    repo
+}
+
+#[test]
+fn tls_gate() {
+    let ca = Authority::new("test ca");
+    let gate = ca.issue(&["localhost", "127.0.0.1"]);
+    let repo = tls_repo(
+        "127.0.0.1:3829",
+        TlsAcceptor::new(&gate.cert, &gate.key).unwrap(),
+    );
+    let tls = TlsConnector::new(&ca.pem()).unwrap();
+
+    let space = RemoteSpace::new_tls(String::from("127.0.0.1:3829/space"), tls.clone()).unwrap();
+    space.put(new_tuple!(1, 'a')).unwrap();
+    let by_name = RemoteSpace::new_tls(String::from("localhost:3829/space"), tls.clone()).unwrap();
+    let t = by_name
+        .get(new_template!(1.actual(), 'x'.formal()))
+        .unwrap();
+    assert_eq!('a', *t.get_field::<char>(1));
+
+    // Plain text clients never get to greet the gate
+    assert!(RemoteSpace::new(String::from("127.0.0.1:3829/space")).is_err());
+
+    // Nor do clients that do not trust the certificate of the gate
+    let other = TlsConnector::new(&Authority::new("other ca").pem()).unwrap();
+    assert!(matches!(
+        RemoteSpace::new_tls(String::from("127.0.0.1:3829/space"), other),
+        Err(Error::Tls(_))
+    ));
+
+    // The space can still be reached after failed connections
+    let options = ConnectOptions {
+        tls: Some(tls),
+        ..Default::default()
+    };
+    let space = RemoteSpace::with_options(String::from("127.0.0.1:3829/space"), options).unwrap();
+    space.put(new_tuple!(2, 'b')).unwrap();
+    assert_eq!(
+        1,
+        space
+            .queryall(new_template!(0.formal(), 'x'.formal()))
+            .unwrap()
+            .len()
+    );
+
+    drop((space, by_name));
+    repo.close_gate(String::from("gate"));
+}
+
+#[test]
+fn tls_client_certificates() {
+    let ca = Authority::new("test ca");
+    let gate = ca.issue(&["127.0.0.1"]);
+    let alice = ca.issue(&["alice"]);
+    let bob = ca.issue(&["bob"]);
+    let repo = tls_repo(
+        "127.0.0.1:3830",
+        TlsAcceptor::with_client_auth(&gate.cert, &gate.key, &ca.pem()).unwrap(),
+    );
+    repo.set_authenticator(Authenticator::certificates([(&alice.cert[..], "alice")]).unwrap());
+    repo.grant("alice", "space", Permissions::ALL);
+
+    let connect = |tls: TlsConnector| {
+        let conn = String::from("127.0.0.1:3830/space");
+        RemoteSpace::new_tls(conn, tls)
+    };
+    let space =
+        connect(TlsConnector::with_certificate(&ca.pem(), &alice.cert, &alice.key).unwrap())
+            .unwrap();
+    space.put(new_tuple!(1, 'a')).unwrap();
+    assert!(space.getp(new_template!(1.actual(), 'x'.formal())).is_ok());
+
+    // Bob has a valid certificate but is not known to the authenticator
+    let tls = TlsConnector::with_certificate(&ca.pem(), &bob.cert, &bob.key).unwrap();
+    assert!(matches!(
+        connect(tls),
+        Err(Error::Rejected(Rejection::Unauthorized, _))
+    ));
+
+    // Clients without a certificate fail the handshake
+    assert!(connect(TlsConnector::new(&ca.pem()).unwrap()).is_err());
+
+    drop(space);
+    repo.close_gate(String::from("gate"));
+}